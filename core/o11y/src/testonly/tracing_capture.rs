@@ -6,6 +6,10 @@ use std::sync::{Arc, Mutex};
 ///
 /// The intended use-case is for tests which want to probe inner workings of the
 /// system which are not observable through public APIs only.
+///
+/// All spans are captured.  Events are ignored unless the capture is enabled
+/// with [`Self::enable_with_events`] in which case warning and error events are
+/// captured as well.  The `on_log` callback is called for spans only.
 pub struct TracingCapture {
     captured: Arc<Mutex<Captured>>,
     _guard: tracing::subscriber::DefaultGuard,
@@ -14,6 +18,7 @@ pub struct TracingCapture {
 struct Captured {
     on_log: Arc<dyn Fn(&str) + Send + Sync>,
     logs: Vec<String>,
+    events: bool,
 }
 
 struct Subscriber(Arc<Mutex<Captured>>);
@@ -25,8 +30,17 @@ impl TracingCapture {
     /// taken to properly propagate this across threads for multi-threaded
     /// tests.
     pub fn enable() -> TracingCapture {
+        Self::enable_impl(false)
+    }
+    /// Like [`Self::enable`] but captures warning and error events as well.
+    ///
+    /// Useful to verify that some particular condition was reported.
+    pub fn enable_with_events() -> TracingCapture {
+        Self::enable_impl(true)
+    }
+    fn enable_impl(events: bool) -> TracingCapture {
         let captured =
-            Arc::new(Mutex::new(Captured { on_log: Arc::new(|_| ()), logs: Vec::new() }));
+            Arc::new(Mutex::new(Captured { on_log: Arc::new(|_| ()), logs: Vec::new(), events }));
         let subscriber = Subscriber(Arc::clone(&captured));
         let _guard = tracing::subscriber::set_default(subscriber);
         TracingCapture { captured, _guard }
@@ -74,7 +88,20 @@ impl tracing::Subscriber for Subscriber {
     }
     fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
     fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
-    fn event(&self, _event: &tracing::Event<'_>) {}
+    fn event(&self, event: &tracing::Event<'_>) {
+        // Only warnings and errors are captured to keep the overhead of
+        // verbose logging low.
+        if *event.metadata().level() > tracing::Level::WARN {
+            return;
+        }
+        let mut guard = self.0.lock().unwrap();
+        if !guard.events {
+            return;
+        }
+        let mut visitor = AppendToString(event.metadata().level().to_string());
+        event.record(&mut visitor);
+        guard.logs.push(visitor.0);
+    }
     fn enter(&self, _span: &tracing::span::Id) {}
     fn exit(&self, _span: &tracing::span::Id) {}
 }
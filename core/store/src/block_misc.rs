@@ -0,0 +1,144 @@
+//! Typed access to the well-known cells of [`DBCol::BlockMisc`] column.
+//!
+//! The cells are stored under constant keys such as [`HEAD_KEY`] with values
+//! encoded with borsh.  Rather than passing the raw keys around and decoding
+//! the values ad hoc, use the getters on [`Store`] and setters on
+//! [`StoreUpdate`] defined here which make sure the right type is used for
+//! each of the keys.
+
+use std::io;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_primitives::block::Tip;
+use near_primitives::types::BlockHeight;
+
+use crate::db::{
+    CHUNK_TAIL_KEY, COLD_HEAD_KEY, FINAL_HEAD_KEY, FORK_TAIL_KEY, HEADER_HEAD_KEY, HEAD_KEY,
+    TAIL_KEY,
+};
+use crate::{DBCol, Store, StoreUpdate};
+
+impl Store {
+    fn get_block_misc<T: BorshDeserialize>(&self, key: &[u8]) -> io::Result<Option<T>> {
+        self.get_ser(DBCol::BlockMisc, key)
+    }
+
+    /// Returns the head of the block chain.
+    pub fn get_head(&self) -> io::Result<Option<Tip>> {
+        self.get_block_misc(HEAD_KEY)
+    }
+
+    /// Returns the head of the header chain.
+    pub fn get_header_head(&self) -> io::Result<Option<Tip>> {
+        self.get_block_misc(HEADER_HEAD_KEY)
+    }
+
+    /// Returns the final head of the block chain.
+    pub fn get_final_head(&self) -> io::Result<Option<Tip>> {
+        self.get_block_misc(FINAL_HEAD_KEY)
+    }
+
+    /// Returns the head of the cold storage.
+    pub fn get_cold_head(&self) -> io::Result<Option<Tip>> {
+        self.get_block_misc(COLD_HEAD_KEY)
+    }
+
+    /// Returns the blocks tail height, i.e. height up to which blocks have
+    /// been garbage collected.
+    pub fn get_tail(&self) -> io::Result<Option<BlockHeight>> {
+        self.get_block_misc(TAIL_KEY)
+    }
+
+    /// Returns the chunks tail height.
+    pub fn get_chunk_tail(&self) -> io::Result<Option<BlockHeight>> {
+        self.get_block_misc(CHUNK_TAIL_KEY)
+    }
+
+    /// Returns the forks tail height.
+    pub fn get_fork_tail(&self) -> io::Result<Option<BlockHeight>> {
+        self.get_block_misc(FORK_TAIL_KEY)
+    }
+}
+
+impl StoreUpdate {
+    fn set_block_misc<T: BorshSerialize>(&mut self, key: &[u8], value: &T) -> io::Result<()> {
+        self.set_ser(DBCol::BlockMisc, key, value)
+    }
+
+    /// Sets the head of the block chain.
+    pub fn set_head(&mut self, tip: &Tip) -> io::Result<()> {
+        self.set_block_misc(HEAD_KEY, tip)
+    }
+
+    /// Sets the head of the header chain.
+    pub fn set_header_head(&mut self, tip: &Tip) -> io::Result<()> {
+        self.set_block_misc(HEADER_HEAD_KEY, tip)
+    }
+
+    /// Sets the final head of the block chain.
+    pub fn set_final_head(&mut self, tip: &Tip) -> io::Result<()> {
+        self.set_block_misc(FINAL_HEAD_KEY, tip)
+    }
+
+    /// Sets the head of the cold storage.
+    pub fn set_cold_head(&mut self, tip: &Tip) -> io::Result<()> {
+        self.set_block_misc(COLD_HEAD_KEY, tip)
+    }
+
+    /// Sets the blocks tail height.
+    pub fn set_tail(&mut self, height: BlockHeight) -> io::Result<()> {
+        self.set_block_misc(TAIL_KEY, &height)
+    }
+
+    /// Sets the chunks tail height.
+    pub fn set_chunk_tail(&mut self, height: BlockHeight) -> io::Result<()> {
+        self.set_block_misc(CHUNK_TAIL_KEY, &height)
+    }
+
+    /// Sets the forks tail height.
+    pub fn set_fork_tail(&mut self, height: BlockHeight) -> io::Result<()> {
+        self.set_block_misc(FORK_TAIL_KEY, &height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_primitives::block::Tip;
+    use near_primitives::hash::CryptoHash;
+
+    use crate::test_utils::create_test_store;
+    use crate::{DBCol, HEAD_KEY};
+
+    fn tip(height: u64) -> Tip {
+        Tip {
+            height,
+            last_block_hash: CryptoHash::hash_bytes(&height.to_le_bytes()),
+            prev_block_hash: CryptoHash::default(),
+            epoch_id: Default::default(),
+            next_epoch_id: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_head_round_trip() {
+        let store = create_test_store();
+        assert_eq!(store.get_head().unwrap(), None);
+        assert_eq!(store.get_final_head().unwrap(), None);
+
+        let mut update = store.store_update();
+        update.set_head(&tip(42)).unwrap();
+        update.set_final_head(&tip(40)).unwrap();
+        update.set_tail(10).unwrap();
+        update.commit().unwrap();
+
+        assert_eq!(store.get_head().unwrap(), Some(tip(42)));
+        assert_eq!(store.get_final_head().unwrap(), Some(tip(40)));
+        assert_eq!(store.get_header_head().unwrap(), None);
+        assert_eq!(store.get_tail().unwrap(), Some(10));
+        assert_eq!(store.get_chunk_tail().unwrap(), None);
+
+        // The typed API uses the same encoding as reading the raw key.
+        let head: Option<Tip> = store.get_ser(DBCol::BlockMisc, HEAD_KEY).unwrap();
+        assert_eq!(head, Some(tip(42)));
+    }
+}
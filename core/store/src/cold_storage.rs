@@ -173,6 +173,30 @@ fn copy_state_from_store(
     let instant = std::time::Instant::now();
 
     let mut transaction = DBTransaction::new();
+    for (key, value) in get_state_insertions_from_store(shard_layout, block_hash_key, hot_store)? {
+        tracing::trace!(target: "cold_store", pretty_key=?near_fmt::StorageKey(&key), "copying state node to colddb");
+        rc_aware_set(&mut transaction, DBCol::State, key, value);
+    }
+
+    let read_duration = instant.elapsed();
+
+    let instant = std::time::Instant::now();
+    cold_db.write(transaction)?;
+    let write_duration = instant.elapsed();
+
+    tracing::trace!(target: "cold_store", ?read_duration, ?write_duration, "finished");
+
+    Ok(())
+}
+
+/// Returns State keys and values inserted by the block with given hash as
+/// recorded in its TrieChanges.
+fn get_state_insertions_from_store(
+    shard_layout: &ShardLayout,
+    block_hash_key: &[u8],
+    hot_store: &Store,
+) -> io::Result<Vec<(StoreKey, Vec<u8>)>> {
+    let mut insertions = Vec::new();
     for shard_uid in shard_layout.shard_uids() {
         debug_assert_eq!(
             DBCol::TrieChanges.key_type(),
@@ -187,22 +211,10 @@ fn copy_state_from_store(
         let Some(trie_changes) = trie_changes else { continue };
         for op in trie_changes.insertions() {
             let key = join_two_keys(&shard_uid_key, op.hash().as_bytes());
-            let value = op.payload().to_vec();
-
-            tracing::trace!(target: "cold_store", pretty_key=?near_fmt::StorageKey(&key), "copying state node to colddb");
-            rc_aware_set(&mut transaction, DBCol::State, key, value);
+            insertions.push((key, op.payload().to_vec()));
         }
     }
-
-    let read_duration = instant.elapsed();
-
-    let instant = std::time::Instant::now();
-    cold_db.write(transaction)?;
-    let write_duration = instant.elapsed();
-
-    tracing::trace!(target: "cold_store", ?read_duration, ?write_duration, "finished");
-
-    Ok(())
+    Ok(insertions)
 }
 
 /// Gets values for given keys in a column from provided hot_store.
@@ -303,6 +315,64 @@ pub fn update_cold_head(
     return Ok(());
 }
 
+/// Returns keys of given column which blocks at `heights` should have put in
+/// cold storage but which are missing from `cold_db`.
+///
+/// This is a safety check to run before trimming hot storage: an empty result
+/// means the trim loses no data of the column.  The expected keys are derived
+/// from hot storage the same way [`update_cold_db`] derives keys to copy, so
+/// the data must still be present in hot storage.  Like with the copy, only
+/// keys which exist in hot storage are expected, heights without a block are
+/// skipped and `shard_layout` must be the layout of the blocks in the range.
+pub fn verify_cold_completeness(
+    hot_store: &Store,
+    cold_db: &ColdDB,
+    shard_layout: &ShardLayout,
+    col: DBCol,
+    heights: std::ops::RangeInclusive<BlockHeight>,
+) -> io::Result<Vec<StoreKey>> {
+    if !col.is_cold() {
+        let msg = format!("{col} isn’t kept in cold storage");
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    let mut missing = Vec::new();
+    for height in heights {
+        let height_key = height.to_le_bytes();
+        let Some(block_hash) = hot_store.get_for_cold(DBCol::BlockHeight, &height_key)? else {
+            continue;
+        };
+        let keys = if col == DBCol::State {
+            get_state_insertions_from_store(shard_layout, &block_hash, hot_store)?
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect()
+        } else {
+            let key_type_to_keys =
+                get_keys_from_store(hot_store, shard_layout, &height_key, &block_hash)?;
+            let mut keys = Vec::new();
+            for key in combine_keys(&key_type_to_keys, &col.key_type()) {
+                if hot_store.get_for_cold(col, &key)?.is_some() {
+                    keys.push(key);
+                }
+            }
+            keys
+        };
+        for key in keys {
+            if cold_db.get_raw_bytes(col, &key)?.is_none() {
+                tracing::warn!(
+                    target: "cold_store",
+                    %col,
+                    height,
+                    key = %near_fmt::StorageKey(&key),
+                    "Key missing from cold storage"
+                );
+                missing.push(key);
+            }
+        }
+    }
+    Ok(missing)
+}
+
 pub enum CopyAllDataToColdStatus {
     EverythingCopied,
     Interrupted,
@@ -16,7 +16,17 @@ use std::fmt;
 /// deprecation.  Make sure to add `#[strum(serialize = "OriginalName")]`
 /// attribute in front of the variant when you deprecate a column.
 #[derive(
-    PartialEq, Copy, Clone, Debug, Hash, Eq, enum_map::Enum, strum::EnumIter, strum::IntoStaticStr,
+    PartialEq,
+    Copy,
+    Clone,
+    Debug,
+    Hash,
+    Eq,
+    enum_map::Enum,
+    strum::EnumIter,
+    strum::IntoStaticStr,
+    serde::Serialize,
+    serde::Deserialize,
 )]
 pub enum DBCol {
     /// Column to indicate which version of database this is.
@@ -561,6 +571,49 @@ impl DBCol {
             DBCol::EpochSyncInfo => &[DBKeyType::EpochId],
         }
     }
+
+    /// Length of every key in this column or `None` if keys are of variable
+    /// length.
+    ///
+    /// The length is derived from [`Self::key_type`].  Columns which, on top
+    /// of their declared keys, hold special string literal rows (e.g.
+    /// `EpochInfo` stores aggregator under `"AGGREGATOR"` key) are reported as
+    /// variable length.
+    pub fn fixed_key_len(&self) -> Option<usize> {
+        if *self == DBCol::EpochInfo {
+            return None;
+        }
+        self.key_type().iter().map(DBKeyType::fixed_len).sum()
+    }
+}
+
+impl DBKeyType {
+    /// Length of the encoded key part or `None` if it’s variable length.
+    pub const fn fixed_len(&self) -> Option<usize> {
+        match self {
+            DBKeyType::Empty => Some(0),
+            DBKeyType::BlockHash
+            | DBKeyType::PreviousBlockHash
+            | DBKeyType::ChunkHash
+            | DBKeyType::EpochId
+            | DBKeyType::TrieNodeOrValueHash
+            | DBKeyType::ReceiptHash
+            | DBKeyType::TransactionHash
+            | DBKeyType::OutcomeId
+            | DBKeyType::ContractCacheKey => Some(32),
+            DBKeyType::BlockHeight
+            | DBKeyType::BlockOrdinal
+            | DBKeyType::ShardId
+            | DBKeyType::ShardUId
+            | DBKeyType::Nonce
+            | DBKeyType::PartId => Some(8),
+            DBKeyType::StringLiteral
+            | DBKeyType::PeerId
+            | DBKeyType::AccountId
+            | DBKeyType::TrieKey
+            | DBKeyType::ColumnId => None,
+        }
+    }
 }
 
 impl fmt::Display for DBCol {
@@ -598,4 +651,15 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn fixed_key_len_sanity() {
+        assert_eq!(DBCol::State.fixed_key_len(), Some(40));
+        assert_eq!(DBCol::BlockHeight.fixed_key_len(), Some(8));
+        assert_eq!(DBCol::TransactionResultForBlock.fixed_key_len(), Some(64));
+        assert_eq!(DBCol::LastComponentNonce.fixed_key_len(), Some(0));
+        assert_eq!(DBCol::BlockMisc.fixed_key_len(), None);
+        assert_eq!(DBCol::FlatState.fixed_key_len(), None);
+        assert_eq!(DBCol::EpochInfo.fixed_key_len(), None);
+    }
 }
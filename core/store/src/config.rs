@@ -95,6 +95,258 @@ pub struct StoreConfig {
 
     // TODO (#9989): To be phased out in favor of state_snapshot_config
     pub state_snapshot_enabled: bool,
+
+    /// Size of a write batch above which writes are split into multiple
+    /// smaller batches.  Disabled if not set.
+    ///
+    /// Note that a split write is not atomic.  If the node crashes mid-way,
+    /// only some of the sub-batches end up in the database.  Callers which
+    /// need atomicity can use [`crate::db::Database::write_atomic`] which
+    /// fails rather than splitting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_write_batch_size: Option<bytesize::ByteSize>,
+
+    /// Duration above which a write is logged at WARN level together with
+    /// its number of operations, size and per-column breakdown.  Disabled if
+    /// not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_write_threshold: Option<std::time::Duration>,
+
+    /// Log a warning whenever [`crate::db::Database::get_raw_bytes`] is called
+    /// on a reference counted column.
+    ///
+    /// Such reads return the value with its reference count attached and
+    /// treat values with non-positive reference count as existing which is
+    /// rarely what the caller wants.  Meant for catching misuse during
+    /// development; low-level code which needs the raw values triggers the
+    /// warning as well.
+    #[serde(skip_serializing_if = "is_false")]
+    pub strict_rc_reads: bool,
+
+    /// Limit of background IO, i.e. of writes done by flushes and compactions,
+    /// in bytes per second.  Not limited if not set.
+    ///
+    /// This caps the disk bandwidth background work can take away from
+    /// foreground reads.  Writes done by the node itself, including the
+    /// write-ahead log, aren’t throttled.  The limit is fixed: RocksDB’s
+    /// auto-tuned rate limiting, which lowers the rate below the limit when
+    /// there’s little background work, isn’t used.  Setting the limit too low
+    /// causes compaction to fall behind which eventually stalls writes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_io_bytes_per_sec: Option<u64>,
+
+    /// Maximum number of background flushes and compactions RocksDB runs
+    /// concurrently.  If not set, half of the available CPUs (but at least one)
+    /// is used.
+    ///
+    /// RocksDB splits the jobs between flushes and compactions on its own.
+    /// The jobs are run by thread pools shared by all databases opened in the
+    /// process, e.g. hot and cold storage, which are grown to fit the largest
+    /// limit of any of them but never shrunk.  Lowering the limit for one
+    /// database therefore doesn’t free threads if another one uses more.
+    /// The limit is ignored when RocksDB is built single-threaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_background_jobs: Option<u32>,
+
+    /// Limit of memory used by memtables of all columns combined.  Not
+    /// limited if not set.
+    ///
+    /// Without the limit each column can hold full memtables of its own (see
+    /// [`ColumnConfig::write_buffer_size`]) which adds up with many columns.
+    /// With the limit, once the memtables together reach about 90% of it,
+    /// RocksDB flushes the largest active memtable even if it isn’t full.
+    /// Writes aren’t stalled so usage can temporarily exceed the limit while
+    /// the flushes are in progress.  A limit which is too low results in many
+    /// small level 0 files and thus more compaction.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memtable_memory_budget: Option<bytesize::ByteSize>,
+
+    /// Per-column RocksDB settings overriding the defaults chosen for given
+    /// column.  Columns not listed here use the defaults.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub column_overrides: HashMap<DBCol, ColumnConfig>,
+}
+
+/// RocksDB settings of a single column which can be overridden in
+/// [`StoreConfig::column_overrides`].
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ColumnConfig {
+    /// Compression used for the column’s SST files.
+    ///
+    /// If not set, the column uses LZ4 compression with Zstd for the bottommost
+    /// level.  Note that changing the compression affects only newly written
+    /// SST files.  Existing files keep their compression until compaction
+    /// rewrites them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+
+    /// Directory where the column’s SST files are stored.  If relative,
+    /// resolved relative to the database directory.
+    ///
+    /// This allows keeping a few large columns on a different disk than the
+    /// rest of the database.  If not set, the files are stored in the database
+    /// directory.
+    ///
+    /// Note that RocksDB doesn’t move existing files when the path changes and
+    /// fails to open the database if it can’t find them.  Moving a column of an
+    /// existing database therefore requires a migration, e.g. moving the
+    /// column’s SST files to the new directory while the node is stopped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<std::path::PathBuf>,
+
+    /// Collect detailed statistics of the column.
+    ///
+    /// If any column has this enabled, [`crate::db::Database::get_store_statistics`]
+    /// reports per-column values only for columns with statistics enabled and
+    /// includes additional per-column counters for them.  The values are read
+    /// from RocksDB properties on demand so unlike [`StoreConfig::enable_statistics`]
+    /// this adds no overhead to reads and writes.
+    #[serde(skip_serializing_if = "is_false")]
+    pub statistics: bool,
+
+    /// Whether data of the column can be regenerated from other data and thus
+    /// doesn’t need write-ahead log durability.
+    ///
+    /// Writes touching only regenerable columns skip the write-ahead log which
+    /// reduces IO.  Writes touching any other column use the log for the
+    /// whole batch.  On crash, writes to regenerable columns which haven’t
+    /// been flushed from memtables yet are lost, even if writes to other
+    /// columns done after them survive.  Only mark columns whose contents the
+    /// node can rebuild, e.g. caches, and which are never read under the
+    /// assumption they are consistent with other columns.
+    #[serde(skip_serializing_if = "is_false")]
+    pub regenerable: bool,
+
+    /// Keep index and filter blocks of the column resident in the block cache.
+    ///
+    /// Meant for small, very hot columns where a cache miss on index or filter
+    /// block adds noticeable tail latency.  The blocks are charged to the
+    /// column’s block cache and are never evicted, so the memory they use
+    /// (roughly 1% of the column’s size for indices plus 10 bits per key for
+    /// bloom filters) is held for as long as the database is open.  To keep
+    /// data blocks resident as well, set [`Self::cache_size`] to be larger
+    /// than the column.
+    #[serde(skip_serializing_if = "is_false")]
+    pub cache_pinned: bool,
+
+    /// Size of the column’s block cache.  Each column has a cache of its own.
+    /// If not set, [`StoreConfig::col_cache_size`] is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_size: Option<bytesize::ByteSize>,
+
+    /// Compaction style of the column.
+    ///
+    /// If not set, leveled compaction is used which suits read-heavy columns
+    /// with random access.  Universal compaction lowers write amplification
+    /// of write-heavy, append-only columns at the cost of more space and
+    /// slower reads.
+    ///
+    /// Changing the style of an existing column requires care.  Going from
+    /// universal to leveled compaction works only if all the column’s files
+    /// are at a single level, e.g. after a full manual compaction, otherwise
+    /// RocksDB fails to open the database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compaction_style: Option<CompactionStyle>,
+
+    /// Size of the column’s SST data blocks.  If not set,
+    /// [`StoreConfig::block_size`] is used.
+    ///
+    /// Each point read loads and caches a whole block so smaller blocks reduce
+    /// read amplification for columns with small values.  Larger blocks shrink
+    /// the index and compress better which suits columns with large values or
+    /// ones mostly read sequentially.  Changing the size affects only newly
+    /// written SST files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_size: Option<bytesize::ByteSize>,
+
+    /// Size of a single memtable of the column.  If not set, 32 MiB is used.
+    ///
+    /// Write-heavy columns benefit from larger memtables which batch more
+    /// writes into each flush and so produce fewer, larger level 0 files.
+    /// Rarely written columns can use smaller ones to save memory.  A column
+    /// may hold several memtables at once, one being written and others
+    /// waiting to be flushed, so its memory use may be a few times this
+    /// size.  A memtable may also be flushed before it fills up, e.g. when
+    /// the write-ahead log grows too large or memtables of all columns
+    /// exceed [`StoreConfig::memtable_memory_budget`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_buffer_size: Option<bytesize::ByteSize>,
+
+    /// Number of bloom filter bits per key in the column’s SST files.  If
+    /// not set, 10 bits are used.
+    ///
+    /// More bits lower the false positive rate, roughly 1% at 10 bits and
+    /// 0.1% at 15 bits, which benefits columns frequently queried for absent
+    /// keys.  Each bit costs memory and disk space for every key though.
+    /// Changing the value affects only newly written SST files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bloom_bits_per_key: Option<f64>,
+
+    /// Format of the column’s SST files.  If not set, block-based tables
+    /// with an unpartitioned index are used.
+    ///
+    /// Meant for experimenting with formats which may suit particular
+    /// columns better.  Changing whether the index is partitioned affects only
+    /// newly written SST files.  However, RocksDB reads a column’s files with
+    /// the configured format only so switching to or from plain tables works
+    /// only for empty columns.  Note that many of the other settings, e.g.
+    /// compression, cache size and bloom filter bits, apply to block-based
+    /// tables only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table_format: Option<TableFormat>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Compression algorithm used for a column’s SST files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    None,
+    Lz4,
+    /// Zstd compression with given compression level.
+    Zstd {
+        level: i32,
+    },
+}
+
+/// Compaction style of a column; see [`ColumnConfig::compaction_style`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionStyle {
+    Level,
+    Universal,
+}
+
+/// Format of a column’s SST files; see [`ColumnConfig::table_format`].
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TableFormat {
+    /// RocksDB’s default block-based format.
+    ///
+    /// With `partitioned_index` the index and bloom filter of each file are
+    /// split into partitions loaded into the block cache on demand.  This
+    /// lowers memory use of columns with large indices at the cost of an
+    /// additional read on cache misses.
+    BlockBased { partitioned_index: bool },
+    /// Plain table format which indexes keys by their prefix in a hash
+    /// table and is optimised for low-latency point reads.
+    ///
+    /// The format requires a prefix extractor so the column is configured
+    /// with a fixed `prefix_len`-byte one and all keys must be at least that
+    /// long.  With a prefix extractor, seeking (and thus range and prefix
+    /// iteration) is reliable only within keys sharing the prefix.  Files
+    /// aren’t compressed and must be smaller than 2 GiB.  See RocksDB’s
+    /// `PlainTableOptions` for the remaining fields.
+    Plain {
+        prefix_len: usize,
+        bloom_bits_per_key: i32,
+        hash_table_ratio: f64,
+        index_sparseness: usize,
+    },
 }
 
 /// Config used to control state snapshot creation. This is used for state sync and resharding.
@@ -171,6 +423,12 @@ impl StoreConfig {
         Self { max_open_files: 512, ..Self::default() }
     }
 
+    /// Returns override settings for given column.  If the column has no
+    /// overrides configured, returns [`ColumnConfig`] with nothing set.
+    pub fn column_config(&self, col: DBCol) -> ColumnConfig {
+        self.column_overrides.get(&col).cloned().unwrap_or_default()
+    }
+
     /// Returns cache size for given column.
     pub const fn col_cache_size(&self, col: DBCol) -> bytesize::ByteSize {
         match col {
@@ -266,6 +524,20 @@ impl Default for StoreConfig {
 
             // TODO: To be phased out in favor of state_snapshot_config
             state_snapshot_enabled: false,
+
+            max_write_batch_size: None,
+
+            slow_write_threshold: None,
+
+            background_io_bytes_per_sec: None,
+
+            max_background_jobs: None,
+
+            memtable_memory_budget: None,
+
+            strict_rc_reads: false,
+
+            column_overrides: Default::default(),
         }
     }
 }
@@ -1,10 +1,16 @@
 use crate::DBCol;
 use near_fmt::{AbbrBytes, StorageKey};
+use near_primitives::hash::CryptoHash;
 use std::io;
 
 pub(crate) mod rocksdb;
 
+mod batch;
 mod colddb;
+mod journal;
+mod mirror;
+mod partitioned;
+mod profiler;
 mod splitdb;
 
 pub mod refcount;
@@ -13,9 +19,17 @@ mod testdb;
 
 mod database_tests;
 
+pub use self::batch::BatchBuilder;
 pub use self::colddb::ColdDB;
-pub use self::rocksdb::RocksDB;
-pub use self::splitdb::SplitDB;
+pub use self::journal::{replay, JournalingDatabase};
+pub use self::mirror::MirroringDatabase;
+pub use self::partitioned::PartitionedDatabase;
+pub use self::profiler::{AccessCounts, AccessProfiler};
+pub use self::rocksdb::{
+    BackgroundPauseGuard, ColumnNotOpen, CompactionInProgress, LogLevel, Logger,
+    PeriodicCompaction, RepairReport, RocksDB,
+};
+pub use self::splitdb::{ReadRepairPolicy, SplitDB, SplitDiskSize};
 
 pub use self::slice::DBSlice;
 pub use self::testdb::TestDB;
@@ -34,35 +48,81 @@ pub const GENESIS_STATE_ROOTS_KEY: &[u8; 19] = b"GENESIS_STATE_ROOTS";
 pub const COLD_HEAD_KEY: &[u8; 9] = b"COLD_HEAD";
 pub const STATE_SYNC_DUMP_KEY: &[u8; 15] = b"STATE_SYNC_DUMP";
 pub const STATE_SNAPSHOT_KEY: &[u8; 18] = b"STATE_SNAPSHOT_KEY";
+/// Set in the hot storage while [`SplitDB::write_both`] is in progress.  If
+/// present after the write returned an error, cold and hot storage may be
+/// inconsistent.
+pub const SPLIT_WRITE_PENDING_KEY: &[u8; 19] = b"SPLIT_WRITE_PENDING";
 
 // `DBCol::Misc` keys
 pub const FLAT_STATE_VALUES_INLINING_MIGRATION_STATUS_KEY: &[u8] =
     b"FLAT_STATE_VALUES_INLINING_MIGRATION_STATUS";
 pub const STATE_TRANSITION_START_HEIGHTS: &[u8] = b"STATE_TRANSITION_START_HEIGHTS";
 
+/// A batch of write operations applied atomically with [`Database::write`].
+///
+/// Keys may be empty in all columns.  An empty key sorts before any other key
+/// and is treated like any other key by all operations and iterators.
 #[derive(Default, Debug)]
 pub struct DBTransaction {
     pub(crate) ops: Vec<DBOp>,
+    /// Cap on size of the transaction set with [`Self::with_limit`].
+    limit: Option<TransactionLimit>,
+    /// Whether the transaction may set raw values in reference counted
+    /// columns; see [`Self::allow_raw_rc_writes`].
+    raw_rc_writes: bool,
 }
 
-pub(crate) enum DBOp {
+#[derive(Debug)]
+struct TransactionLimit {
+    max_ops: usize,
+    max_bytes: usize,
+    /// Total size of operations in the transaction (see
+    /// [`DBTransaction::size_bytes`]) kept so that it doesn’t need to be
+    /// recomputed on each added operation.
+    bytes: usize,
+}
+
+/// A single write operation of a [`DBTransaction`].
+///
+/// The operations can be inspected with [`DBTransaction::ops`] but outside of
+/// this crate they can only be constructed with [`DBTransaction`] methods.
+#[derive(Clone)]
+pub enum DBOp {
     /// Sets `key` to `value`, without doing any checks.
+    #[non_exhaustive]
     Set { col: DBCol, key: Vec<u8>, value: Vec<u8> },
     /// Sets `key` to `value`, and additionally debug-checks that the value is
     /// not overwritten.
+    #[non_exhaustive]
     Insert { col: DBCol, key: Vec<u8>, value: Vec<u8> },
     /// Modifies a reference-counted column. `value` includes both the value per
     /// se and a refcount at the end.
+    #[non_exhaustive]
     UpdateRefcount { col: DBCol, key: Vec<u8>, value: Vec<u8> },
     /// Deletes sepecific `key`.
+    #[non_exhaustive]
     Delete { col: DBCol, key: Vec<u8> },
     /// Deletes all data from a column.
+    #[non_exhaustive]
     DeleteAll { col: DBCol },
     /// Deletes [`from`, `to`) key range, i.e. including `from` and excluding `to`
+    #[non_exhaustive]
     DeleteRange { col: DBCol, from: Vec<u8>, to: Vec<u8> },
 }
 
 impl DBOp {
+    /// Returns number of key and value bytes the operation carries.
+    fn size_bytes(&self) -> usize {
+        match self {
+            DBOp::Set { key, value, .. }
+            | DBOp::Insert { key, value, .. }
+            | DBOp::UpdateRefcount { key, value, .. } => key.len() + value.len(),
+            DBOp::Delete { key, .. } => key.len(),
+            DBOp::DeleteAll { .. } => 0,
+            DBOp::DeleteRange { from, to, .. } => from.len() + to.len(),
+        }
+    }
+
     pub fn col(&self) -> DBCol {
         *match self {
             DBOp::Set { col, .. } => col,
@@ -113,44 +173,430 @@ impl std::fmt::Debug for DBOp {
 
 impl DBTransaction {
     pub fn new() -> Self {
-        Self { ops: Vec::new() }
+        Self::default()
+    }
+
+    /// Creates a transaction which holds at most `max_ops` operations of at
+    /// most `max_bytes` bytes in total (see [`Self::size_bytes`]).
+    ///
+    /// Adding an operation which would exceed either of the limits panics in
+    /// debug builds while in release builds it’s logged and the operation is
+    /// added anyway.  Use [`Self::try_push`] to handle the limit gracefully.
+    /// Meant for catching bugs such as an import building an ever-growing
+    /// batch which would otherwise run out of memory.  Transactions created
+    /// with [`Self::new`] are unbounded.
+    pub fn with_limit(max_ops: usize, max_bytes: usize) -> Self {
+        let limit = Some(TransactionLimit { max_ops, max_bytes, bytes: 0 });
+        Self { limit, ..Self::default() }
+    }
+
+    /// Adds operation to the transaction unless it would exceed the limit
+    /// set with [`Self::with_limit`].  If it would, the operation isn’t added
+    /// and [`io::ErrorKind::InvalidInput`] error is returned.
+    pub fn try_push(&mut self, op: DBOp) -> io::Result<()> {
+        if let Some(msg) = self.check_limit(&op) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        self.add(op);
+        Ok(())
+    }
+
+    /// Adds operation to the transaction checking the limit if there is one;
+    /// see [`Self::with_limit`].
+    fn push(&mut self, op: DBOp) {
+        if let Some(msg) = self.check_limit(&op) {
+            near_o11y::log_assert_fail!("{msg}");
+        }
+        self.add(op);
+    }
+
+    /// Returns description of the exceeded limit if adding `op` would exceed
+    /// it.
+    fn check_limit(&self, op: &DBOp) -> Option<String> {
+        let limit = self.limit.as_ref()?;
+        let bytes = limit.bytes + op.size_bytes();
+        if self.ops.len() < limit.max_ops && bytes <= limit.max_bytes {
+            return None;
+        }
+        Some(format!(
+            "transaction limit exceeded: adding {:?} would make it {} operations and {bytes} \
+             bytes while limit is {} operations and {} bytes",
+            op,
+            self.ops.len() + 1,
+            limit.max_ops,
+            limit.max_bytes
+        ))
+    }
+
+    fn add(&mut self, op: DBOp) {
+        if let Some(limit) = &mut self.limit {
+            limit.bytes += op.size_bytes();
+        }
+        self.ops.push(op);
+    }
+
+    /// Returns operations of the transaction in the order they were added.
+    pub fn ops(&self) -> &[DBOp] {
+        &self.ops
     }
 
     pub fn set(&mut self, col: DBCol, key: Vec<u8>, value: Vec<u8>) {
-        self.ops.push(DBOp::Set { col, key, value });
+        self.push(DBOp::Set { col, key, value });
     }
 
+    /// Inserts `value` under `key` in an insert-only column.
+    ///
+    /// In debug builds, panics if the same key has been deleted earlier in the
+    /// transaction.  The no-overwrite check done on write compares with the
+    /// value stored in the database, so deleting and then inserting the key in
+    /// one batch would pass it even if the value changes.  Such a pattern
+    /// usually means a bug rather than an intended overwrite.
     pub fn insert(&mut self, col: DBCol, key: Vec<u8>, value: Vec<u8>) {
         assert!(col.is_insert_only(), "can't insert: {col:?}");
-        self.ops.push(DBOp::Insert { col, key, value });
+        if cfg!(debug_assertions) {
+            let deleted = self.ops.iter().any(|op| match op {
+                DBOp::Delete { col: op_col, key: op_key } => *op_col == col && *op_key == key,
+                _ => false,
+            });
+            assert!(
+                !deleted,
+                "insert: {col} key {} deleted earlier in the batch",
+                StorageKey(&key)
+            );
+        }
+        self.push(DBOp::Insert { col, key, value });
     }
 
     pub fn update_refcount(&mut self, col: DBCol, key: Vec<u8>, value: Vec<u8>) {
         assert!(col.is_rc(), "can't update refcount: {col:?}");
-        self.ops.push(DBOp::UpdateRefcount { col, key, value });
+        self.push(DBOp::UpdateRefcount { col, key, value });
     }
 
     pub fn delete(&mut self, col: DBCol, key: Vec<u8>) {
-        self.ops.push(DBOp::Delete { col, key });
+        self.push(DBOp::Delete { col, key });
     }
 
     pub fn delete_all(&mut self, col: DBCol) {
-        self.ops.push(DBOp::DeleteAll { col });
+        self.push(DBOp::DeleteAll { col });
     }
 
+    /// Deletes keys in [`from`, `to`) range.
+    ///
+    /// `from` must not be greater than `to`.  If it is, the operation is
+    /// treated as an empty range when written (and panics in debug builds).
     pub fn delete_range(&mut self, col: DBCol, from: Vec<u8>, to: Vec<u8>) {
-        self.ops.push(DBOp::DeleteRange { col, from, to });
+        debug_assert!(
+            from <= to,
+            "delete_range: inverted range in {col}: {} > {}",
+            StorageKey(&from),
+            StorageKey(&to)
+        );
+        self.push(DBOp::DeleteRange { col, from, to });
+    }
+
+    /// Merges overlapping and adjacent delete range operations within the same
+    /// column into minimal covering ranges.
+    ///
+    /// Ranges are merged only if there are no other operations on the column
+    /// between them so the result of applying the transaction doesn’t change.
+    /// Ranges in different columns are never merged.  Empty and inverted
+    /// ranges are left as they are.  Returns number of operations removed by
+    /// merging; anything other than zero may indicate a logic error such as
+    /// deleting the same keys twice.
+    pub fn normalize_ranges(&mut self) -> usize {
+        let mut ops = Vec::with_capacity(self.ops.len());
+        // Indices in `ops` of delete ranges which following ranges in the same
+        // column may be merged with.
+        let mut mergeable: enum_map::EnumMap<DBCol, Vec<usize>> = Default::default();
+        let mut removed = 0;
+        for op in std::mem::take(&mut self.ops) {
+            let (col, mut from, mut to) = match op {
+                DBOp::DeleteRange { col, from, to } => (col, from, to),
+                op => {
+                    mergeable[op.col()].clear();
+                    ops.push(Some(op));
+                    continue;
+                }
+            };
+            if from >= to {
+                ops.push(Some(DBOp::DeleteRange { col, from, to }));
+                continue;
+            }
+            // Absorb all ranges the new one overlaps or touches.  Since the
+            // range may grow, repeat until there’s nothing left to merge.
+            while let Some(pos) = mergeable[col].iter().position(|&idx| match &ops[idx] {
+                Some(DBOp::DeleteRange { from: other_from, to: other_to, .. }) => {
+                    *other_from <= to && from <= *other_to
+                }
+                _ => unreachable!(),
+            }) {
+                let idx = mergeable[col].swap_remove(pos);
+                let Some(DBOp::DeleteRange { from: other_from, to: other_to, .. }) =
+                    ops[idx].take()
+                else {
+                    unreachable!()
+                };
+                from = from.min(other_from);
+                to = to.max(other_to);
+                removed += 1;
+            }
+            mergeable[col].push(ops.len());
+            ops.push(Some(DBOp::DeleteRange { col, from, to }));
+        }
+        self.ops = ops.into_iter().flatten().collect();
+        if let Some(limit) = &mut self.limit {
+            limit.bytes = self.ops.iter().map(DBOp::size_bytes).sum();
+        }
+        removed
+    }
+
+    /// Marks the transaction as deliberately setting raw values, i.e. values
+    /// together with their reference counts, in reference counted columns.
+    ///
+    /// Meant for code which overwrites stored data as is, e.g. when applying
+    /// [`diff_column`] patches or copying columns.  Such transactions pass
+    /// [`Self::check_rc_ops`].
+    pub fn allow_raw_rc_writes(&mut self) {
+        self.raw_rc_writes = true;
+    }
+
+    /// Checks that no reference counted column receives `Set` or `Insert`
+    /// operations.
+    ///
+    /// Reference counted columns should be modified with reference count
+    /// updates only.  A transaction setting a value directly almost
+    /// certainly bypasses reference counting by mistake unless it’s been
+    /// marked with [`Self::allow_raw_rc_writes`].  Databases run the check
+    /// on write in debug builds.
+    pub fn check_rc_ops(&self) -> io::Result<()> {
+        if self.raw_rc_writes {
+            return Ok(());
+        }
+        for op in &self.ops {
+            match op {
+                DBOp::Set { col, key, .. } | DBOp::Insert { col, key, .. } if col.is_rc() => {
+                    let msg = format!(
+                        "batch sets {} in reference counted column {col} bypassing reference \
+                         counting",
+                        StorageKey(key)
+                    );
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns total number of key and value bytes in the transaction.
+    ///
+    /// This is an approximation of the size of the write batch the
+    /// transaction turns into and doesn’t include any per-operation overhead.
+    pub fn size_bytes(&self) -> usize {
+        self.ops.iter().map(DBOp::size_bytes).sum()
+    }
+
+    /// Returns hash of the changes the transaction makes.
+    ///
+    /// Meant for verifying that two parties applied the same change set.  The
+    /// hash doesn’t depend on order of operations where the order doesn’t
+    /// matter: between reference count updates and range deletions, only the
+    /// last set, insert or delete of each key is hashed and those are sorted
+    /// by column name and key.  Reference count updates and range deletions
+    /// are hashed in order.
+    pub fn content_hash(&self) -> CryptoHash {
+        type Record<'a> = (u8, &'static str, &'a [u8], &'a [u8]);
+        fn record(op: &DBOp) -> Record<'_> {
+            let col = op.col().into();
+            match op {
+                DBOp::Set { key, value, .. } => (0, col, key, value),
+                DBOp::Insert { key, value, .. } => (1, col, key, value),
+                DBOp::Delete { key, .. } => (2, col, key, &[]),
+                DBOp::UpdateRefcount { key, value, .. } => (3, col, key, value),
+                DBOp::DeleteAll { .. } => (4, col, &[], &[]),
+                DBOp::DeleteRange { from, to, .. } => (5, col, from, to),
+            }
+        }
+
+        let mut records = Vec::with_capacity(self.ops.len());
+        // Last set, insert or delete of each key since the last operation
+        // whose order matters.
+        let mut run = std::collections::BTreeMap::new();
+        for op in &self.ops {
+            let record = record(op);
+            match op {
+                DBOp::Set { .. } | DBOp::Insert { .. } | DBOp::Delete { .. } => {
+                    run.insert((record.1, record.2), record);
+                }
+                DBOp::UpdateRefcount { .. } | DBOp::DeleteAll { .. } | DBOp::DeleteRange { .. } => {
+                    records.extend(std::mem::take(&mut run).into_values());
+                    records.push(record);
+                }
+            }
+        }
+        records.extend(run.into_values());
+        CryptoHash::hash_borsh_iter(records)
+    }
+
+    /// Splits the transaction into transactions of at most `max_bytes` bytes
+    /// each (as returned by [`Self::size_bytes`]).
+    ///
+    /// Operations are kept in order.  An operation larger than `max_bytes` is
+    /// put in a transaction of its own.
+    pub(crate) fn into_chunks(self, max_bytes: usize) -> Vec<DBTransaction> {
+        let mut chunks = Vec::new();
+        let mut chunk = DBTransaction::new();
+        let mut chunk_size = 0;
+        for op in self.ops {
+            let size = op.size_bytes();
+            if !chunk.ops.is_empty() && chunk_size + size > max_bytes {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_size = 0;
+            }
+            chunk.raw_rc_writes = self.raw_rc_writes;
+            chunk_size += size;
+            chunk.ops.push(op);
+        }
+        if !chunk.ops.is_empty() {
+            chunks.push(chunk);
+        }
+        chunks
     }
 
     pub fn merge(&mut self, other: DBTransaction) {
-        self.ops.extend(other.ops)
+        self.raw_rc_writes |= other.raw_rc_writes;
+        if self.limit.is_some() {
+            other.ops.into_iter().for_each(|op| self.push(op));
+        } else {
+            self.ops.extend(other.ops)
+        }
     }
+
+    /// Merges another transaction into this one unless they conflict.
+    ///
+    /// Two transactions conflict if they both set (via `Set` or `Insert`
+    /// operations) the same key in the same column to different values.  In
+    /// that case, `self` is left unmodified and the first conflicting key is
+    /// returned.  Otherwise this behaves like [`Self::merge`].
+    pub fn try_merge(&mut self, other: DBTransaction) -> Result<(), MergeConflict> {
+        let mut values = std::collections::HashMap::new();
+        for op in &self.ops {
+            if let DBOp::Set { col, key, value } | DBOp::Insert { col, key, value } = op {
+                values.insert((*col, key.as_slice()), value.as_slice());
+            }
+        }
+        for op in &other.ops {
+            if let DBOp::Set { col, key, value } | DBOp::Insert { col, key, value } = op {
+                match values.get(&(*col, key.as_slice())) {
+                    Some(existing) if *existing != value.as_slice() => {
+                        return Err(MergeConflict { col: *col, key: key.clone() });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.merge(other);
+        Ok(())
+    }
+}
+
+/// Error returned by [`DBTransaction::try_merge`] when both transactions set
+/// the same key to different values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub col: DBCol,
+    pub key: Vec<u8>,
+}
+
+impl std::fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "conflicting values set for {} in {}", StorageKey(&self.key), self.col)
+    }
+}
+
+impl std::error::Error for MergeConflict {}
+
+/// Returns the exclusive upper bound of the range of keys starting with given
+/// prefix.
+///
+/// That is, returns the smallest key greater than all keys with the prefix
+/// which can be used as upper bound in [`Database::iter_range`].  If there’s
+/// no such key, i.e. the prefix is empty or consists of `0xff` bytes only,
+/// returns `None` meaning the range has no upper bound.
+pub fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let len = prefix.iter().rposition(|&byte| byte != 0xff)? + 1;
+    let mut bound = prefix[..len].to_vec();
+    bound[len - 1] += 1;
+    Some(bound)
 }
 
 pub type DBIteratorItem = io::Result<(Box<[u8]>, Box<[u8]>)>;
+
+/// Iterator over entries of a column.
+///
+/// All backends follow the same contract on failure: the error is yielded
+/// once and afterwards the iterator is exhausted, i.e. it returns `None` on
+/// all subsequent calls.  An error thus always ends the iteration and callers
+/// don’t need to stop explicitly.  See [`Database::iter_lenient`] for an
+/// iterator which skips malformed entries rather than failing.
 pub type DBIterator<'a> = Box<dyn Iterator<Item = DBIteratorItem> + 'a>;
 
+/// Token used to cancel long running scans; see
+/// [`Database::iter_cancellable`].
+///
+/// Clones share the same state so a clone can be handed to e.g. a signal
+/// handler which cancels the scan by calling [`Self::cancel`].
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trips the token.  Iterators using it stop shortly afterwards.
+    pub fn cancel(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Wraps the iterator so that it yields an [`io::ErrorKind::Interrupted`]
+    /// error and stops once the token is tripped.
+    ///
+    /// To keep the overhead low, the token is checked only once every
+    /// [`CANCELLATION_CHECK_INTERVAL`] items.
+    pub fn wrap_iter<'a>(&self, mut iter: DBIterator<'a>) -> DBIterator<'a> {
+        let token = self.clone();
+        let mut count = 0usize;
+        let mut cancelled = false;
+        Box::new(std::iter::from_fn(move || {
+            if cancelled {
+                return None;
+            }
+            if count % CANCELLATION_CHECK_INTERVAL == 0 && token.is_cancelled() {
+                cancelled = true;
+                let msg = "iteration cancelled";
+                return Some(Err(io::Error::new(io::ErrorKind::Interrupted, msg)));
+            }
+            count += 1;
+            iter.next()
+        }))
+    }
+}
+
+/// How often, in number of items, iterators check [`CancellationToken`].
+pub const CANCELLATION_CHECK_INTERVAL: usize = 1000;
+
 pub trait Database: Sync + Send {
+    /// Returns name of the database used to tell instances apart in logs and
+    /// metrics labels, e.g. `"rocksdb"` or `"colddb"`.
+    fn name(&self) -> &str {
+        "database"
+    }
+
     /// Returns raw bytes for given `key` ignoring any reference count decoding
     /// if any.
     ///
@@ -170,6 +616,56 @@ pub trait Database: Sync + Send {
         Ok(self.get_raw_bytes(col, key)?.and_then(DBSlice::strip_refcount))
     }
 
+    /// Returns value for given `key` along with its reference count.
+    ///
+    /// Works like [`Self::get_with_rc_stripped`] and returns `None` if the
+    /// reference count is non-positive.  Use [`Self::get_refcount`] to inspect
+    /// non-positive reference counts.
+    ///
+    /// **Panics** if the column is not reference counted.
+    fn get_with_rc(&self, col: DBCol, key: &[u8]) -> io::Result<Option<(DBSlice<'_>, i64)>> {
+        assert!(col.is_rc());
+        Ok(self.get_raw_bytes(col, key)?.and_then(DBSlice::split_refcount))
+    }
+
+    /// Returns reference count of given `key`, including non-positive one.
+    ///
+    /// Meant for diagnostics, e.g. finding keys whose reference count dropped
+    /// below zero.  Absent keys have reference count of zero.
+    ///
+    /// **Panics** if the column is not reference counted.
+    fn get_refcount(&self, col: DBCol, key: &[u8]) -> io::Result<i64> {
+        assert!(col.is_rc());
+        let value = self.get_raw_bytes(col, key)?;
+        Ok(value.map_or(0, |value| refcount::decode_value_with_rc(&value).1))
+    }
+
+    /// Returns number of reference counted values which couldn’t be decoded
+    /// because they were too short to hold a reference count.
+    ///
+    /// Such values are treated as absent by [`Self::get_with_rc_stripped`] and
+    /// [`Self::iter`] rather than causing a panic.  Note that the counter is
+    /// shared by all databases in the process.
+    fn refcount_decode_errors(&self) -> u64 {
+        refcount::decode_errors()
+    }
+
+    /// Returns length of the value stored under given `key` without copying
+    /// the value.
+    ///
+    /// For reference-counted columns the length excludes the reference count
+    /// and cells with non-positive reference count are treated as missing.
+    /// There’s no cheaper primitive than a read in RocksDB but the read goes
+    /// through a pinned slice so the value isn’t copied.
+    fn value_len(&self, col: DBCol, key: &[u8]) -> io::Result<Option<usize>> {
+        let value = if col.is_rc() {
+            self.get_with_rc_stripped(col, key)?
+        } else {
+            self.get_raw_bytes(col, key)?
+        };
+        Ok(value.map(|value| value.len()))
+    }
+
     /// Iterate over all items in given column in lexicographical order sorted
     /// by the key.
     ///
@@ -187,11 +683,86 @@ pub trait Database: Sync + Send {
     /// are returned in lexicographical order sorted by the key.
     fn iter_prefix<'a>(&'a self, col: DBCol, key_prefix: &'a [u8]) -> DBIterator<'a>;
 
+    /// Iterate over items in given column whose keys start with given prefix
+    /// and are strictly greater than `start_after`.
+    ///
+    /// This allows resuming a prefix scan from the last key processed.  The
+    /// iteration still stops at the end of the prefix.  If `start_after` is
+    /// before the prefix, this is equivalent to [`Self::iter_prefix`].
+    fn iter_prefix_from<'a>(
+        &'a self,
+        col: DBCol,
+        key_prefix: &[u8],
+        start_after: &[u8],
+    ) -> DBIterator<'a> {
+        let upper_bound = prefix_upper_bound(key_prefix);
+        // The smallest key greater than start_after.
+        let start = [start_after, &[0]].concat();
+        let lower_bound = std::cmp::max(key_prefix, start.as_slice());
+        if upper_bound.as_deref().map_or(false, |upper| lower_bound >= upper) {
+            return Box::new(std::iter::empty());
+        }
+        self.iter_range(col, Some(lower_bound), upper_bound.as_deref())
+    }
+
+    /// Returns number of items in given column whose keys start with given
+    /// prefix.
+    ///
+    /// Items are counted the same way [`Self::iter_prefix`] returns them so
+    /// in reference counted columns cells with non-positive reference count
+    /// are not counted.  Note that this is O(n) in the number of keys with the
+    /// prefix since it iterates over all of them.
+    fn count_prefix(&self, col: DBCol, key_prefix: &[u8]) -> io::Result<u64> {
+        self.iter_prefix(col, key_prefix).try_fold(0, |count, item| item.map(|_| count + 1))
+    }
+
+    /// Returns all versions of a logical key in a column whose keys end with
+    /// a version suffix, as `(full_key, value)` pairs ordered by full key.
+    ///
+    /// This is [`Self::iter_prefix`] collected.  Since keys are matched by
+    /// prefix only, `logical_key_prefix` must be the complete logical key, or
+    /// else versions of other logical keys which extend it are returned as
+    /// well.
+    fn get_versions(
+        &self,
+        col: DBCol,
+        logical_key_prefix: &[u8],
+    ) -> io::Result<Vec<(Box<[u8]>, DBSlice<'_>)>> {
+        self.iter_prefix(col, logical_key_prefix)
+            .map(|item| item.map(|(key, value)| (key, DBSlice::from_vec(value.into_vec()))))
+            .collect()
+    }
+
+    /// Returns number of distinct `prefix_len`-byte prefixes of keys in given
+    /// column.
+    ///
+    /// Keys shorter than `prefix_len` count as prefixes of their own.  Like in
+    /// [`Self::count_prefix`], cells with non-positive reference count are
+    /// ignored.  The default implementation iterates over all keys while
+    /// RocksDB seeks past each prefix once it’s been counted so it reads only
+    /// about one key per prefix.
+    fn count_distinct_prefixes(&self, col: DBCol, prefix_len: usize) -> io::Result<u64> {
+        let mut count = 0;
+        let mut last: Option<Box<[u8]>> = None;
+        for item in self.iter(col) {
+            let (key, _) = item?;
+            let prefix = &key[..key.len().min(prefix_len)];
+            if last.as_deref() != Some(prefix) {
+                count += 1;
+                last = Some(prefix.into());
+            }
+        }
+        Ok(count)
+    }
+
     /// Iterate over items in given column whose keys are between [lower_bound, upper_bound)
     ///
     /// Upper_bound key is not included.
     /// If lower_bound is None - the iterator starts from the first key.
     /// If upper_bound is None - iterator continues to the last key.
+    /// If lower_bound is not less than upper_bound - the iterator is empty.
+    /// Note that an empty slice is a valid bound: as lower bound it includes
+    /// all keys and as upper bound it excludes all keys.
     fn iter_range<'a>(
         &'a self,
         col: DBCol,
@@ -199,6 +770,62 @@ pub trait Database: Sync + Send {
         upper_bound: Option<&[u8]>,
     ) -> DBIterator<'a>;
 
+    /// Iterate over items in given column whose keys are between
+    /// [lower_bound, upper_bound) in descending order.
+    ///
+    /// The bounds have the same meaning as in [`Self::iter_range`], i.e. the
+    /// lower bound is inclusive and the upper bound exclusive, and the
+    /// iterator returns the same items just in reverse order.  The default
+    /// implementation collects the items of the range first.
+    fn iter_range_rev<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        let items = self.iter_range(col, lower_bound, upper_bound).collect::<Vec<_>>();
+        Box::new(items.into_iter().rev())
+    }
+
+    /// Reads raw value like [`Self::get_raw_bytes`] using given options.
+    ///
+    /// The default implementation ignores the options which is correct for
+    /// databases which don’t store blocks with checksums, e.g. in memory ones.
+    fn get_with_opts(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        _opts: &ReadOptions,
+    ) -> io::Result<Option<DBSlice<'_>>> {
+        self.get_raw_bytes(col, key)
+    }
+
+    /// Iterates over items in given column like [`Self::iter`] using given
+    /// options.
+    ///
+    /// The default implementation ignores the options; see
+    /// [`Self::get_with_opts`].
+    fn iter_with_opts<'a>(&'a self, col: DBCol, _opts: &ReadOptions) -> DBIterator<'a> {
+        self.iter(col)
+    }
+
+    /// Iterate over items in given column whose keys are not less than
+    /// `start_inclusive`, up to the last key.
+    ///
+    /// Equivalent to `iter_range(col, Some(start_inclusive), None)`.
+    fn iter_from<'a>(&'a self, col: DBCol, start_inclusive: &[u8]) -> DBIterator<'a> {
+        self.iter_range(col, Some(start_inclusive), None)
+    }
+
+    /// Iterate over items in given column whose keys are greater than
+    /// `start_exclusive`, up to the last key.
+    fn iter_from_exclusive<'a>(&'a self, col: DBCol, start_exclusive: &[u8]) -> DBIterator<'a> {
+        // The smallest key greater than `start_exclusive` is the key followed
+        // by a zero byte.
+        let start = [start_exclusive, &[0]].concat();
+        self.iter_range(col, Some(&start), None)
+    }
+
     /// Iterate over items in given column bypassing reference count decoding if
     /// any.
     ///
@@ -212,20 +839,637 @@ pub trait Database: Sync + Send {
     /// want this method.
     fn iter_raw_bytes<'a>(&'a self, col: DBCol) -> DBIterator<'a>;
 
+    /// Iterate over all items in given column checking the format of the keys.
+    ///
+    /// This is like [`Self::iter`] except that for columns whose keys have
+    /// fixed length (see [`DBCol::fixed_key_len`]) every key of a different
+    /// length is reported as an [`io::ErrorKind::InvalidData`] error.  The
+    /// iteration continues past such errors so all malformed keys can be
+    /// reported.  Meant for audits which want format drift to be loud.
+    fn iter_validated<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        let iter = self.iter(col);
+        let Some(want_len) = col.fixed_key_len() else {
+            return iter;
+        };
+        Box::new(iter.map(move |item| {
+            let (key, value) = item?;
+            if key.len() != want_len {
+                let msg = format!(
+                    "{col}: expected {want_len}-byte key but got {} bytes: {}",
+                    key.len(),
+                    StorageKey(&key)
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+            Ok((key, value))
+        }))
+    }
+
+    /// Iterate over all items in given column skipping entries which can’t
+    /// be decoded.
+    ///
+    /// Works like [`Self::iter`] except that malformed values in reference
+    /// counted columns are logged, together with their keys, and skipped and
+    /// that an iteration error is logged and ends the iteration rather than
+    /// being returned.  The iterator therefore never yields an error.  Meant
+    /// for tools which want to process as much of a possibly corrupted
+    /// database as they can.
+    fn iter_lenient<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        refcount::iter_lenient_rc_logic(col, self.iter_raw_bytes(col))
+    }
+
+    /// Iterate over all keys in given reference counted column yielding their
+    /// reference counts.
+    ///
+    /// Unlike [`Self::iter`], keys with non-positive reference count are
+    /// included which makes this suitable for auditing the counts.  Values
+    /// too short to hold a reference count are reported as having reference
+    /// count of zero.
+    ///
+    /// **Panics** if the column is not reference counted.
+    fn iter_refcounts<'a>(
+        &'a self,
+        col: DBCol,
+    ) -> Box<dyn Iterator<Item = io::Result<(Box<[u8]>, i64)>> + 'a> {
+        assert!(col.is_rc());
+        Box::new(
+            self.iter_raw_bytes(col).map(|item| {
+                item.map(|(key, value)| (key, refcount::decode_value_with_rc(&value).1))
+            }),
+        )
+    }
+
+    /// Iterate over all items in given column yielding values only if they
+    /// are at most `max_value_bytes` long.
+    ///
+    /// Yields `(key, Some(value), len)` for values up to the limit and
+    /// `(key, None, len)` for larger ones, where `len` is length of the value.
+    /// Meant for audits which scan whole columns with occasional giant values
+    /// and only need to flag them.  In reference counted columns values are
+    /// stripped of their reference counts, as returned by [`Self::iter`].  The
+    /// default implementation reads each value and drops it if it’s too large
+    /// so memory use is bounded only by the largest single value.
+    fn iter_keys_and_small_values<'a>(
+        &'a self,
+        col: DBCol,
+        max_value_bytes: usize,
+    ) -> Box<dyn Iterator<Item = io::Result<(Box<[u8]>, Option<Box<[u8]>>, usize)>> + 'a> {
+        Box::new(self.iter(col).map(move |item| {
+            let (key, value) = item?;
+            let len = value.len();
+            Ok((key, (len <= max_value_bytes).then_some(value), len))
+        }))
+    }
+
+    /// Iterate over all items in given column until the token is cancelled.
+    ///
+    /// This is like [`Self::iter`] except that once `token` is tripped the
+    /// iterator yields an [`io::ErrorKind::Interrupted`] error and stops.  Meant
+    /// for scans which may take minutes and need to be interruptible.  Other
+    /// iterators can be made cancellable with [`CancellationToken::wrap_iter`].
+    fn iter_cancellable<'a>(&'a self, col: DBCol, token: CancellationToken) -> DBIterator<'a> {
+        token.wrap_iter(self.iter(col))
+    }
+
     /// Atomically apply all operations in given batch at once.
+    ///
+    /// Depending on configuration, an implementation may split very large
+    /// batches into multiple writes in which case the atomicity is lost.  Use
+    /// [`Self::write_atomic`] or [`Self::write_chunked`] to choose explicitly.
     fn write(&self, batch: DBTransaction) -> io::Result<()>;
 
+    /// Writes the batch like [`Self::write`] and returns identifier of the
+    /// write.
+    ///
+    /// The identifiers increase with each write so callers (e.g. importers)
+    /// can record the last applied write and skip re-applying it after
+    /// a restart.  The default implementation doesn’t support write
+    /// identifiers and fails without writing anything.
+    fn write_with_id(&self, _batch: DBTransaction) -> io::Result<WriteId> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "write identifiers not supported"))
+    }
+
+    /// Atomically apply all operations in given batch at once, never splitting
+    /// it.
+    ///
+    /// Unlike [`Self::write`], fails if the batch exceeds the maximum write
+    /// batch size the database has been configured with.
+    fn write_atomic(&self, batch: DBTransaction) -> io::Result<()> {
+        self.write(batch)
+    }
+
+    /// Applies operations in given batch splitting it into multiple writes of
+    /// at most `max_bytes` bytes each (see [`DBTransaction::size_bytes`]).
+    ///
+    /// Each of the writes is atomic but the whole batch is not.  If an error
+    /// happens part way through, some of the operations may have been applied.
+    fn write_chunked(&self, batch: DBTransaction, max_bytes: usize) -> io::Result<()> {
+        for chunk in batch.into_chunks(max_bytes) {
+            self.write(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the batch like [`Self::write`] and reports for each `Set` and
+    /// `Insert` operation whether it created the key or overwrote an existing
+    /// value.
+    ///
+    /// The result is aligned with `Set` and `Insert` operations of the batch.
+    /// Existence is determined by reading the keys before the write, taking
+    /// earlier operations of the same batch into account, which makes this
+    /// considerably more expensive than a plain write.  Concurrent writes to
+    /// the same keys may make the report inaccurate.
+    fn write_reporting(&self, batch: DBTransaction) -> io::Result<Vec<KeyOutcome>> {
+        let mut outcomes = Vec::new();
+        {
+            let mut written = std::collections::HashMap::<(DBCol, &[u8]), bool>::new();
+            let mut cleared = Vec::<(DBCol, Option<(&[u8], &[u8])>)>::new();
+            for op in batch.ops.iter() {
+                match op {
+                    DBOp::Set { col, key, .. } | DBOp::Insert { col, key, .. } => {
+                        let key = key.as_slice();
+                        let is_cleared = || {
+                            cleared.iter().any(|(cleared_col, range)| {
+                                cleared_col == col
+                                    && range.map_or(true, |(from, to)| from <= key && key < to)
+                            })
+                        };
+                        let existed = match written.get(&(*col, key)) {
+                            Some(&existed) => existed,
+                            None if is_cleared() => false,
+                            None if col.is_rc() => self.get_with_rc_stripped(*col, key)?.is_some(),
+                            None => self.get_raw_bytes(*col, key)?.is_some(),
+                        };
+                        outcomes.push(if existed {
+                            KeyOutcome::Overwritten
+                        } else {
+                            KeyOutcome::Created
+                        });
+                        written.insert((*col, key), true);
+                    }
+                    DBOp::Delete { col, key } => {
+                        written.insert((*col, key.as_slice()), false);
+                    }
+                    DBOp::UpdateRefcount { col, key, .. } => {
+                        written.remove(&(*col, key.as_slice()));
+                    }
+                    DBOp::DeleteAll { col } => {
+                        written.retain(|(written_col, _), _| written_col != col);
+                        cleared.push((*col, None));
+                    }
+                    DBOp::DeleteRange { col, from, to } => {
+                        written.retain(|(written_col, key), _| {
+                            written_col != col || *key < from.as_slice() || *key >= to.as_slice()
+                        });
+                        cleared.push((*col, Some((from.as_slice(), to.as_slice()))));
+                    }
+                }
+            }
+        }
+        self.write(batch)?;
+        Ok(outcomes)
+    }
+
+    /// Atomically reads, modifies and writes back a single value.
+    ///
+    /// Calls `f` with the current value of `key` (or `None` if it doesn’t
+    /// exist) and stores the value `f` returns.  If `f` returns `None`, the
+    /// key is deleted.  No other write to the database happens between the
+    /// read and the write so neither concurrent updates nor plain
+    /// [`Self::write`] calls modifying the same key are lost.
+    ///
+    /// `f` may be called more than once so it shouldn’t have side effects.
+    /// Reference counted columns are refused with
+    /// [`io::ErrorKind::InvalidInput`] error.
+    ///
+    /// Atomicity depends on the underlying storage so the default
+    /// implementation fails with [`io::ErrorKind::Unsupported`] error without
+    /// writing anything.  Wrappers must forward the call.
+    fn update(
+        &self,
+        _col: DBCol,
+        _key: &[u8],
+        _f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "atomic updates not supported"))
+    }
+
+    /// Writes the batch only if all witnesses hold expected values.  Returns
+    /// whether the batch has been written.
+    ///
+    /// Each witness is a column, a key and the raw value, as returned by
+    /// [`Self::get_raw_bytes`], the key is expected to hold, with `None`
+    /// meaning the key is expected to be absent.  This generalises
+    /// compare-and-set to multiple keys and allows optimistic updates where
+    /// a batch computed from values read earlier is discarded and recomputed
+    /// if any of them changed in the meantime.
+    ///
+    /// Like in [`Self::update`], no other write to the database happens
+    /// between the check and the write.  The batch itself is written
+    /// atomically, i.e. it isn’t split like large batches passed to
+    /// [`Self::write`] may be.
+    ///
+    /// Like [`Self::update`], unsupported unless overridden.
+    fn write_if_unchanged(
+        &self,
+        _batch: DBTransaction,
+        _witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> io::Result<bool> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "conditional writes not supported"))
+    }
+
+    /// Rewrites every value in given column with the result of applying `f`
+    /// to it.  Returns number of values rewritten.
+    ///
+    /// This is meant to normalise encoding of values after a migration which
+    /// changed it lazily.  The values are written back in batches so the whole
+    /// operation isn’t atomic and concurrent writes to the column may be lost.
+    ///
+    /// For reference counted columns `f` is given the value without the
+    /// reference count and the reference count is preserved as is.  Cells with
+    /// non-positive reference count are skipped.
+    fn rewrite_column(
+        &self,
+        col: DBCol,
+        f: &dyn Fn(&[u8]) -> io::Result<Vec<u8>>,
+    ) -> io::Result<u64> {
+        const BATCH_SIZE: usize = 1000;
+        // Reference counts are written back together with the values.
+        let new_transaction = || {
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            transaction
+        };
+        let mut count = 0;
+        let mut transaction = new_transaction();
+        for item in self.iter_raw_bytes(col) {
+            let (key, value) = item?;
+            let new_value = if col.is_rc() {
+                let (Some(value), rc) = refcount::decode_value_with_rc(&value) else {
+                    continue;
+                };
+                [f(value)?.as_slice(), &rc.to_le_bytes()].concat()
+            } else {
+                f(&value)?
+            };
+            transaction.set(col, key.into_vec(), new_value);
+            count += 1;
+            if transaction.ops.len() >= BATCH_SIZE {
+                self.write(std::mem::replace(&mut transaction, new_transaction()))?;
+            }
+        }
+        if !transaction.ops.is_empty() {
+            self.write(transaction)?;
+        }
+        Ok(count)
+    }
+
+    /// Removes all data from given column and reclaims the space right away.
+    ///
+    /// Unlike [`DBTransaction::delete_all`], which only records a deletion and
+    /// leaves it to the background compaction to eventually drop the data,
+    /// this compacts the column immediately after deleting its contents.  This
+    /// means the call is blocking and IO heavy but it doesn’t leave a range of
+    /// tombstones behind which subsequent reads and writes need to skip over.
+    ///
+    /// Prefer `delete_all` when the deletion needs to be part of a larger
+    /// atomic batch.  The default implementation simply writes a `DeleteAll`
+    /// operation.  RocksDB built with `single_thread_rocksdb` feature skips
+    /// the compaction and thus behaves the same way.
+    fn truncate_column(&self, col: DBCol) -> io::Result<()> {
+        let mut transaction = DBTransaction::new();
+        transaction.delete_all(col);
+        self.write(transaction)
+    }
+
+    /// Moves value of given key from `from_col` to `to_col`.
+    ///
+    /// The value is written to `to_col` and deleted from `from_col` in
+    /// a single atomic write.  Returns whether the key existed and has been
+    /// moved.  The read isn’t atomic with the write, so the key must not be
+    /// modified concurrently.
+    ///
+    /// Both columns must either be reference counted or not.  Reference
+    /// counted values are moved together with their reference count which
+    /// replaces any value `to_col` had for the key.  Keys with non-positive
+    /// reference count are treated as absent and left in place.  Moving
+    /// between columns which differ in reference counting would require
+    /// re-encoding the value so it fails with `InvalidInput` error.
+    fn move_key(&self, from_col: DBCol, to_col: DBCol, key: &[u8]) -> io::Result<bool> {
+        if from_col == to_col || from_col.is_rc() != to_col.is_rc() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can’t move keys from {from_col} to {to_col}"),
+            ));
+        }
+        let value = if from_col.is_rc() {
+            match self.get_with_rc(from_col, key)? {
+                Some((value, rc)) => [&value[..], &rc.to_le_bytes()].concat(),
+                None => return Ok(false),
+            }
+        } else {
+            match self.get_raw_bytes(from_col, key)? {
+                Some(value) => value.to_vec(),
+                None => return Ok(false),
+            }
+        };
+        let mut transaction = DBTransaction::new();
+        transaction.allow_raw_rc_writes();
+        if to_col.is_insert_only() {
+            transaction.insert(to_col, key.to_vec(), value);
+        } else {
+            transaction.set(to_col, key.to_vec(), value);
+        }
+        transaction.delete(from_col, key.to_vec());
+        self.write_atomic(transaction)?;
+        Ok(true)
+    }
+
+    /// Physically deletes given key regardless of its reference count.
+    ///
+    /// **This may corrupt the database.**  In reference counted columns other
+    /// data may still refer to the value, e.g. trie nodes to their children,
+    /// and reading it afterwards finds nothing.  Subsequent reference count
+    /// updates of the key start over from zero.  Meant only as a last resort
+    /// during incident recovery in place of ad hoc raw writes.  Each call is
+    /// logged at WARN level with the key and its reference count before the
+    /// deletion.
+    fn force_delete(&self, col: DBCol, key: &[u8]) -> io::Result<()> {
+        let refcount = if col.is_rc() { Some(self.get_refcount(col, key)?) } else { None };
+        tracing::warn!(
+            target: "store",
+            %col,
+            key = %StorageKey(key),
+            ?refcount,
+            "Force deleting key ignoring its reference count"
+        );
+        let mut transaction = DBTransaction::new();
+        transaction.delete(col, key.to_vec());
+        self.write(transaction)
+    }
+
+    /// Returns approximate number of keys in given column which are in
+    /// [`from`, `to`) range.
+    ///
+    /// The estimate is intended for planning (e.g. deciding whether deleting
+    /// a range is worth it) without scanning the data.  Depending on the
+    /// implementation it may be exact or a rough estimate.  The default
+    /// implementation counts the keys exactly by iterating over the range.
+    fn approx_keys_in_range(&self, col: DBCol, from: &[u8], to: &[u8]) -> io::Result<u64> {
+        self.iter_range(col, Some(from), Some(to))
+            .try_fold(0, |count, item| item.map(|_| count + 1))
+    }
+
+    /// Loads values of keys in [`from`, `to`) range of given column into the
+    /// database’s cache.
+    ///
+    /// Meant to be called ahead of a sequential scan of the range so that the
+    /// scan doesn’t stall on disk reads.  This reads the whole range so it’s
+    /// only worth it if the range fits in the column’s cache.  The default
+    /// implementation, meant for in-memory databases, does nothing.
+    fn prefetch_range(&self, _col: DBCol, _from: &[u8], _to: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns for each of the keys whether it exists in given column.
+    ///
+    /// The result is aligned with `keys`.  In reference counted columns a key
+    /// exists only if its reference count is positive.  This is lighter than
+    /// fetching the values when only existence matters, e.g. for
+    /// deduplication.
+    fn contains_keys(&self, col: DBCol, keys: &[&[u8]]) -> io::Result<Vec<bool>> {
+        keys.iter()
+            .map(|key| {
+                let value = if col.is_rc() {
+                    self.get_with_rc_stripped(col, key)?
+                } else {
+                    self.get_raw_bytes(col, key)?
+                };
+                Ok(value.is_some())
+            })
+            .collect()
+    }
+
+    /// Returns raw values of given keys like [`Self::get_raw_bytes`] does.
+    ///
+    /// The keys must be sorted in ascending order, with duplicates allowed,
+    /// otherwise an [`io::ErrorKind::InvalidInput`] error is returned.  The
+    /// result is aligned with `sorted_keys`.  Implementations can exploit the
+    /// ordering to read all keys in a single pass of an iterator which, for
+    /// clustered keys, beats both individual gets and a multi-get.  The
+    /// default implementation reads the keys one by one.
+    fn get_sorted(
+        &self,
+        col: DBCol,
+        sorted_keys: &[&[u8]],
+    ) -> io::Result<Vec<Option<DBSlice<'_>>>> {
+        check_sorted(sorted_keys)?;
+        sorted_keys.iter().map(|key| self.get_raw_bytes(col, key)).collect()
+    }
+
+    /// Returns number of SST files of given column at each level of the LSM
+    /// tree.
+    ///
+    /// The vector is indexed by level and ends at the last non-empty level.
+    /// Many files at level 0 indicate that compaction doesn’t keep up with
+    /// flushes which increases read amplification.  Databases without SST
+    /// files, which is what the default implementation assumes, return an
+    /// empty vector.
+    fn level_file_counts(&self, _col: DBCol) -> io::Result<Vec<usize>> {
+        Ok(Vec::new())
+    }
+
+    /// Returns estimated number of bytes compaction of given column needs to
+    /// rewrite to bring all levels down to their target sizes.
+    ///
+    /// High values mean compaction falls behind and writes to the column will
+    /// soon be stalled, so it may be worth triggering a manual compaction
+    /// during a quiet period.  The default implementation returns zero.
+    fn compaction_pending_bytes(&self, _col: DBCol) -> io::Result<u64> {
+        Ok(0)
+    }
+
+    /// Returns total size of the database on disk in bytes.
+    ///
+    /// Meant for disk usage alerts.  For RocksDB this is the size of SST files
+    /// of all columns plus the write-ahead log; log files and other metadata
+    /// aren’t included.  In-memory databases report total size of keys and
+    /// values they hold.  The default implementation fails with
+    /// `Unsupported` error.
+    fn total_disk_size(&self) -> io::Result<u64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "disk size not supported"))
+    }
+
+    /// Returns the oldest and newest sequence numbers in the write-ahead log,
+    /// or `None` if the log is empty.
+    ///
+    /// Sequence numbers are the same as [`WriteId`]s returned by
+    /// [`Self::write_with_id`] so a changefeed consumer which is behind the
+    /// oldest one can no longer catch up from the log.  For RocksDB this
+    /// covers live log files only; once memtables are flushed their log
+    /// files are recycled and the range may become empty.  In-memory
+    /// databases report identifiers of the first and the last write.  The
+    /// default implementation fails with `Unsupported` error.
+    fn wal_sequence_range(&self) -> io::Result<Option<(u64, u64)>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "write-ahead log not supported"))
+    }
+
+    /// Iterates over raw values of given column stored at given level of the
+    /// LSM tree.
+    ///
+    /// This is a diagnostic tool for investigating data layout, e.g. stuck
+    /// compaction, and is specific to RocksDB.  Other databases, which is what
+    /// the default implementation assumes, fail with `Unsupported` error.
+    fn iter_level<'a>(&'a self, _col: DBCol, _level: usize) -> io::Result<DBIterator<'a>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "database has no LSM levels"))
+    }
+
+    /// Returns where the value of given key is currently served from.
+    ///
+    /// Meant for debugging read latency.  The result is a snapshot which may
+    /// change at any time as memtables are flushed and files compacted.
+    /// Returns `None` if the database can’t tell, which is what the default
+    /// implementation does.
+    fn key_location(&self, _col: DBCol, _key: &[u8]) -> io::Result<Option<KeyLocation>> {
+        Ok(None)
+    }
+
+    /// Returns up to `sample_size` values of given column spread across its key
+    /// space.
+    ///
+    /// Meant for quickly estimating distribution of the values (e.g. their
+    /// sizes) without a full scan.  In reference counted columns, values are
+    /// returned with reference count stripped and cells with non-positive
+    /// reference count are skipped.  The default implementation returns every
+    /// n-th value which requires iterating over the column twice.
+    fn sample_values(&self, col: DBCol, sample_size: usize) -> io::Result<Vec<Box<[u8]>>> {
+        if sample_size == 0 {
+            return Ok(Vec::new());
+        }
+        let count = self.iter(col).try_fold(0, |count, item| item.map(|_| count + 1))?;
+        let step = usize::div_ceil(count, sample_size).max(1);
+        self.iter(col).step_by(step).map(|item| item.map(|(_, value)| value)).collect()
+    }
+
+    /// Returns the smallest and the largest key present in given column or
+    /// `None` if the column is empty.
+    ///
+    /// In reference counted columns only keys with positive reference count
+    /// are considered, the same way [`Self::iter`] returns them.  The default
+    /// implementation iterates over the whole column.
+    fn key_range(&self, col: DBCol) -> io::Result<Option<(Box<[u8]>, Box<[u8]>)>> {
+        let mut iter = self.iter(col);
+        let Some((first, _)) = iter.next().transpose()? else {
+            return Ok(None);
+        };
+        let last = iter.try_fold(None, |_, item| item.map(|(key, _)| Some(key)))?;
+        let last = last.unwrap_or_else(|| first.clone());
+        Ok(Some((first, last)))
+    }
+
+    /// Returns digest of the logical contents of given column.
+    ///
+    /// Meant for verifying that two nodes hold identical data.  The digest is
+    /// folded over key-value pairs in key order, as returned by
+    /// [`Self::iter`], so it doesn’t depend on how the data is laid out in
+    /// storage.  In reference counted columns only keys with positive
+    /// reference count are included and the counts themselves aren’t hashed.
+    /// Digest of an empty column is the default hash.
+    fn column_digest(&self, col: DBCol) -> io::Result<CryptoHash> {
+        self.iter(col).try_fold(CryptoHash::default(), |digest, item| {
+            let (key, value) = item?;
+            Ok(CryptoHash::hash_borsh((digest, &key[..], &value[..])))
+        })
+    }
+
+    /// Returns estimated compression ratio achieved for given column, i.e.
+    /// size of the data before compression divided by its size on disk.
+    ///
+    /// Meant for deciding whether changing the column’s compression settings
+    /// is worthwhile.  Returns `None` if the database can’t tell, e.g. if it
+    /// doesn’t compress data or the column has no data on disk.
+    fn compression_ratio(&self, _col: DBCol) -> io::Result<Option<f64>> {
+        Ok(None)
+    }
+
+    /// Returns counters describing how effective bloom filters are for
+    /// negative lookups.
+    ///
+    /// Databases without bloom filters return all zeros.
+    fn bloom_filter_stats(&self, _col: DBCol) -> io::Result<BloomStats> {
+        Ok(BloomStats::default())
+    }
+
+    /// Returns options of given column as resolved by the database, in
+    /// a human readable form.
+    ///
+    /// This is meant for debugging, e.g. checking whether configuration
+    /// overrides took effect.  The format is implementation specific.
+    /// Databases without any options return an empty string.
+    fn effective_options(&self, _col: DBCol) -> io::Result<String> {
+        Ok(String::new())
+    }
+
+    /// Hints how eagerly background compaction of given column should run.
+    ///
+    /// This is best-effort: the hint doesn’t wait for any compaction, doesn’t
+    /// guarantee that one happens at any particular time and is not persisted
+    /// across restarts.  Databases without background compaction ignore it.
+    fn request_compaction_priority(
+        &self,
+        _col: DBCol,
+        _priority: CompactionPriority,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
     /// Flush all in-memory data to disk.
     ///
     /// This is a no-op for in-memory databases.
     fn flush(&self) -> io::Result<()>;
 
+    /// Flush in-memory data of given column to disk.
+    ///
+    /// Cheaper than [`Self::flush`] when only one column needs to be
+    /// persisted, e.g. before creating a checkpoint of it.  The default
+    /// implementation flushes all columns.
+    fn flush_column(&self, _col: DBCol) -> io::Result<()> {
+        self.flush()
+    }
+
     /// Compact database representation.
     ///
     /// If the database supports it a form of compaction, calling this function
     /// is blocking until compaction finishes. Otherwise, this is a no-op.
     fn compact(&self) -> io::Result<()>;
 
+    /// Deletes keys in range `[from, to)` of given column and compacts the
+    /// range so that disk space they used is reclaimed right away.
+    ///
+    /// Meant for garbage collection of large ranges, e.g. all data below some
+    /// height, where the tombstone would otherwise linger until background
+    /// compaction gets to it.  Fails with `InvalidInput` error for reference
+    /// counted columns since deleting their keys ignores reference counts.
+    /// The default implementation compacts the whole database.
+    fn clear_range_and_compact(&self, col: DBCol, from: &[u8], to: &[u8]) -> io::Result<()> {
+        delete_range_checked(self, col, from, to)?;
+        self.compact()
+    }
+
+    /// Drops SST files of given column whose keys all fall within range
+    /// `[from, to)`.
+    ///
+    /// This is much faster than compaction since whole files are removed
+    /// without reading them, but files which are only partially in the range
+    /// (and files at level 0) are kept so some keys in the range may remain.
+    /// Meant as a cheap first step of garbage collection before
+    /// [`Self::clear_range_and_compact`].  Fails with `InvalidInput` error for
+    /// reference counted columns.  Databases without SST files, which is what
+    /// the default implementation assumes, remove nothing.
+    fn delete_files_in_range(&self, col: DBCol, _from: &[u8], _to: &[u8]) -> io::Result<()> {
+        check_not_rc(col, "delete files of")
+    }
+
     /// Returns statistics about the database if available.
     fn get_store_statistics(&self) -> Option<StoreStatistics>;
 
@@ -237,6 +1481,180 @@ pub trait Database: Sync + Send {
     ) -> anyhow::Result<()>;
 }
 
+/// Returns transaction which, written to `a`, makes column `col` of `a` match
+/// the one of `b`.
+///
+/// The transaction sets keys which are missing in `a` or have different
+/// values and deletes keys which exist in `a` only.  Meant for debugging
+/// divergence between nodes.
+///
+/// Raw values are compared, i.e. in reference counted columns the reference
+/// counts are included and the patch sets them directly.  Applying such
+/// patch while the column is being modified may corrupt reference counts so
+/// a warning is logged.
+pub fn diff_column(a: &dyn Database, b: &dyn Database, col: DBCol) -> io::Result<DBTransaction> {
+    if col.is_rc() {
+        tracing::warn!(
+            target: "store",
+            %col,
+            "Diffing raw values of a reference counted column; \
+             applying the patch overwrites reference counts"
+        );
+    }
+    let mut transaction = DBTransaction::new();
+    transaction.allow_raw_rc_writes();
+    let mut a_iter = a.iter_raw_bytes(col);
+    let mut b_iter = b.iter_raw_bytes(col);
+    let mut a_next = a_iter.next().transpose()?;
+    let mut b_next = b_iter.next().transpose()?;
+    loop {
+        match (a_next.take(), b_next.take()) {
+            (None, None) => break,
+            (Some((key, _)), None) => {
+                transaction.delete(col, key.into_vec());
+                a_next = a_iter.next().transpose()?;
+            }
+            (None, Some((key, value))) => {
+                transaction.set(col, key.into_vec(), value.into_vec());
+                b_next = b_iter.next().transpose()?;
+            }
+            (Some(a_entry), Some(b_entry)) => match a_entry.0.cmp(&b_entry.0) {
+                std::cmp::Ordering::Less => {
+                    transaction.delete(col, a_entry.0.into_vec());
+                    a_next = a_iter.next().transpose()?;
+                    b_next = Some(b_entry);
+                }
+                std::cmp::Ordering::Greater => {
+                    transaction.set(col, b_entry.0.into_vec(), b_entry.1.into_vec());
+                    a_next = Some(a_entry);
+                    b_next = b_iter.next().transpose()?;
+                }
+                std::cmp::Ordering::Equal => {
+                    if a_entry.1 != b_entry.1 {
+                        transaction.set(col, b_entry.0.into_vec(), b_entry.1.into_vec());
+                    }
+                    a_next = a_iter.next().transpose()?;
+                    b_next = b_iter.next().transpose()?;
+                }
+            },
+        }
+    }
+    Ok(transaction)
+}
+
+impl dyn Database + '_ {
+    /// Writes up to `limit` entries of column `col` to `w` in human-readable
+    /// form, one per line.
+    ///
+    /// Keys are formatted with [`StorageKey`] and values with [`AbbrBytes`].
+    /// In reference counted columns values are decoded and annotated with
+    /// their reference count, including entries whose count isn’t positive
+    /// and which regular iterators therefore skip.  If there are more entries,
+    /// a final line with an ellipsis is written.  Meant for operators taking
+    /// a quick look at a column.
+    pub fn dump_column(
+        &self,
+        col: DBCol,
+        limit: usize,
+        w: &mut impl std::io::Write,
+    ) -> io::Result<()> {
+        writeln!(w, "{col}:")?;
+        let mut entries = self.iter_raw_bytes(col);
+        for entry in entries.by_ref().take(limit) {
+            let (key, value) = entry?;
+            let key = StorageKey(&key);
+            if col.is_rc() {
+                let (value, rc) = refcount::decode_value_with_rc(&value);
+                writeln!(w, "  {key} => {} (refcount {rc})", AbbrBytes(value))?;
+            } else {
+                writeln!(w, "  {key} => {}", AbbrBytes(&value[..]))?;
+            }
+        }
+        if entries.next().is_some() {
+            writeln!(w, "  …")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns an error if `col` is reference counted.  `op` describes the refused
+/// operation such that it can be followed by the column name.
+fn check_not_rc(col: DBCol, op: &str) -> io::Result<()> {
+    if col.is_rc() {
+        let msg = format!("can’t {op} reference counted column {col}");
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    Ok(())
+}
+
+/// Calls [`Database::update`] and returns a transaction making the same
+/// write the update did, for wrappers which need to replicate it.  Returns
+/// `None` if `f` wasn’t called.
+fn update_returning_write(
+    db: &dyn Database,
+    col: DBCol,
+    key: &[u8],
+    f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+) -> io::Result<Option<DBTransaction>> {
+    let new_value = std::cell::RefCell::new(None);
+    db.update(col, key, &|value| {
+        let value = f(value);
+        *new_value.borrow_mut() = Some(value.clone());
+        value
+    })?;
+    Ok(new_value.into_inner().map(|new_value| {
+        let mut transaction = DBTransaction::new();
+        match new_value {
+            Some(value) => transaction.set(col, key.to_vec(), value),
+            None => transaction.delete(col, key.to_vec()),
+        }
+        transaction
+    }))
+}
+
+/// Returns whether all witnesses hold expected values, reading values with
+/// `get`; see [`Database::write_if_unchanged`].
+fn witnesses_hold<V: std::ops::Deref<Target = [u8]>>(
+    witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    mut get: impl FnMut(DBCol, &[u8]) -> io::Result<Option<V>>,
+) -> io::Result<bool> {
+    for (col, key, expected) in witnesses {
+        if get(*col, key)?.as_deref() != expected.as_deref() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Returns an error if keys aren’t sorted; see [`Database::get_sorted`].
+fn check_sorted(keys: &[&[u8]]) -> io::Result<()> {
+    match keys.windows(2).find(|pair| pair[0] > pair[1]) {
+        Some(pair) => {
+            let msg = format!(
+                "keys aren’t sorted: {} comes after {}",
+                StorageKey(pair[1]),
+                StorageKey(pair[0])
+            );
+            Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Deletes range of keys in a column refusing to do so for reference counted
+/// ones; see [`Database::clear_range_and_compact`].
+fn delete_range_checked(
+    db: &(impl Database + ?Sized),
+    col: DBCol,
+    from: &[u8],
+    to: &[u8],
+) -> io::Result<()> {
+    check_not_rc(col, "delete range of keys in")?;
+    let mut transaction = DBTransaction::new();
+    transaction.delete_range(col, from.to_vec(), to.to_vec());
+    db.write(transaction)
+}
+
 fn assert_no_overwrite(col: DBCol, key: &[u8], value: &[u8], old_value: &[u8]) {
     assert!(
         value == old_value,
@@ -260,3 +1678,550 @@ pub enum StatsValue {
 pub struct StoreStatistics {
     pub data: Vec<(String, Vec<StatsValue>)>,
 }
+
+impl StoreStatistics {
+    /// Returns change of the statistics since `prev` snapshot.
+    ///
+    /// Statistics are matched by name.  `Count`, `Sum` and `ColumnValue`
+    /// values (the latter matched by column) are replaced by the difference
+    /// between the snapshots while percentiles are kept as in the latest
+    /// snapshot since they cannot be subtracted.  Values missing from `prev`
+    /// are treated as zero.  Statistics present only in `prev` are omitted.
+    pub fn delta(&self, prev: &StoreStatistics) -> StoreStatistics {
+        let data = self
+            .data
+            .iter()
+            .map(|(name, values)| {
+                let prev = prev.data.iter().find(|(prev_name, _)| prev_name == name);
+                let prev = prev.map_or(&[][..], |(_, values)| values.as_slice());
+                let values = values
+                    .iter()
+                    .map(|value| match *value {
+                        StatsValue::Count(count) => {
+                            let prev = prev.iter().find_map(|value| match value {
+                                StatsValue::Count(count) => Some(*count),
+                                _ => None,
+                            });
+                            StatsValue::Count(count - prev.unwrap_or(0))
+                        }
+                        StatsValue::Sum(sum) => {
+                            let prev = prev.iter().find_map(|value| match value {
+                                StatsValue::Sum(sum) => Some(*sum),
+                                _ => None,
+                            });
+                            StatsValue::Sum(sum - prev.unwrap_or(0))
+                        }
+                        StatsValue::ColumnValue(col, val) => {
+                            let prev = prev.iter().find_map(|value| match value {
+                                StatsValue::ColumnValue(prev_col, val) if *prev_col == col => {
+                                    Some(*val)
+                                }
+                                _ => None,
+                            });
+                            StatsValue::ColumnValue(col, val - prev.unwrap_or(0))
+                        }
+                        StatsValue::Percentile(..) => *value,
+                    })
+                    .collect();
+                (name.clone(), values)
+            })
+            .collect();
+        StoreStatistics { data }
+    }
+
+    /// Returns the statistics in a stable JSON form meant for metrics
+    /// exporters.
+    ///
+    /// The statistics are an array of `{"name": …, "values": […]}` objects
+    /// in the same order as [`Self::data`].  Each value is an object whose
+    /// `kind` is one of `count`, `sum`, `percentile` or `column_value` and
+    /// which includes the `value` as well as `percentile` or `column` (the
+    /// column’s variant name) for the latter two kinds.
+    pub fn to_json(&self) -> serde_json::Value {
+        let value_to_json = |value: &StatsValue| match *value {
+            StatsValue::Count(value) => serde_json::json!({"kind": "count", "value": value}),
+            StatsValue::Sum(value) => serde_json::json!({"kind": "sum", "value": value}),
+            StatsValue::Percentile(percentile, value) => {
+                serde_json::json!({"kind": "percentile", "percentile": percentile, "value": value})
+            }
+            StatsValue::ColumnValue(col, value) => {
+                let col = <&str>::from(col);
+                serde_json::json!({"kind": "column_value", "column": col, "value": value})
+            }
+        };
+        self.data
+            .iter()
+            .map(|(name, values)| {
+                let values = values.iter().map(value_to_json).collect::<Vec<_>>();
+                serde_json::json!({"name": name, "values": values})
+            })
+            .collect()
+    }
+}
+
+/// Serialises the statistics in the form returned by
+/// [`StoreStatistics::to_json`].
+impl serde::Serialize for StoreStatistics {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.to_json(), serializer)
+    }
+}
+
+/// Identifier of a write returned by [`Database::write_with_id`].
+///
+/// Identifiers of successive writes to the same database increase.  On
+/// RocksDB this is the sequence number which persists across restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WriteId(pub u64);
+
+/// Options of a read; see [`Database::get_with_opts`] and
+/// [`Database::iter_with_opts`].
+///
+/// The default options are the ones regular reads use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Verify checksums of all blocks read from disk.
+    ///
+    /// Regular reads skip the verification for speed.  With it, a corrupted
+    /// block is reported as an error rather than returning garbage which is
+    /// useful when auditing the database.  The blocks are verified as they
+    /// are read from disk so data in memtables or already in the block
+    /// cache isn’t checked.
+    pub verify_checksums: bool,
+    /// Size of readahead done by iterators, in bytes.
+    ///
+    /// Large readahead speeds up sequential scans over whole columns, e.g.
+    /// during migrations, by reading data in big chunks but wastes I/O on
+    /// short or random reads.  With `None` the database picks the readahead
+    /// itself; RocksDB starts small and grows it as the iterator keeps
+    /// reading sequentially.  Ignored by point reads.
+    pub readahead_bytes: Option<usize>,
+}
+
+/// Outcome of a `Set` or `Insert` operation; see
+/// [`Database::write_reporting`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOutcome {
+    /// The key didn’t exist before the write.
+    Created,
+    /// The key existed and its value has been replaced.
+    Overwritten,
+}
+
+/// Location of the value of a key; see [`Database::key_location`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyLocation {
+    /// The value is held in a memtable, i.e. it hasn’t been flushed yet.
+    MemTable,
+    /// The value is stored in an SST file at given level.
+    Sst(u32),
+    /// The key doesn’t exist.
+    Absent,
+}
+
+/// Priority of background compaction of a column; see
+/// [`Database::request_compaction_priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionPriority {
+    /// Compact the column when the default triggers are reached.
+    Normal,
+    /// Compact the column as soon as there’s anything to compact, e.g. before
+    /// the column is going to be heavily read.
+    High,
+}
+
+/// Bloom filter effectiveness counters returned by
+/// [`Database::bloom_filter_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BloomStats {
+    /// Number of lookups for which the filter determined the key is absent
+    /// and thus avoided reading a data block.
+    pub useful: u64,
+    /// Number of lookups for which the filter reported the key may exist.
+    pub positive: u64,
+    /// Number of lookups for which the filter reported the key may exist and
+    /// the key did exist.
+    pub true_positive: u64,
+}
+
+impl BloomStats {
+    /// Returns total number of bloom filter checks.
+    pub fn checked(&self) -> u64 {
+        self.useful + self.positive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(transaction: &DBTransaction) -> Vec<&[u8]> {
+        transaction
+            .ops
+            .iter()
+            .map(|op| match op {
+                DBOp::Set { key, .. } | DBOp::Insert { key, .. } | DBOp::Delete { key, .. } => {
+                    key.as_slice()
+                }
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_transaction_limit() {
+        let mut transaction = DBTransaction::with_limit(3, 10);
+        transaction.set(DBCol::BlockMisc, b"a".to_vec(), b"1".to_vec());
+        transaction.delete(DBCol::BlockMisc, b"b".to_vec());
+        // Exactly at the limits is fine.
+        transaction.set(DBCol::BlockMisc, b"c".to_vec(), b"234567".to_vec());
+        assert_eq!((transaction.ops().len(), transaction.size_bytes()), (3, 10));
+
+        // The checked variant refuses operations over the limit.
+        let op = DBOp::Delete { col: DBCol::BlockMisc, key: b"d".to_vec() };
+        let err = transaction.try_push(op).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().starts_with("transaction limit exceeded"), "{err}");
+        assert_eq!(transaction.ops().len(), 3);
+
+        // Unbounded transactions grow freely.
+        let mut transaction = DBTransaction::new();
+        for key in 0u16..100 {
+            transaction.set(DBCol::BlockMisc, key.to_be_bytes().to_vec(), vec![0; 100]);
+        }
+        transaction.try_push(DBOp::DeleteAll { col: DBCol::BlockMisc }).unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "transaction limit exceeded")]
+    fn test_transaction_limit_ops() {
+        let mut transaction = DBTransaction::with_limit(2, 100);
+        transaction.delete(DBCol::BlockMisc, b"a".to_vec());
+        transaction.delete(DBCol::BlockMisc, b"b".to_vec());
+        transaction.delete(DBCol::BlockMisc, b"c".to_vec());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "3 operations and 11 bytes while limit is 3 operations and 10 bytes")]
+    fn test_transaction_limit_bytes() {
+        let mut transaction = DBTransaction::with_limit(3, 10);
+        transaction.set(DBCol::BlockMisc, b"a".to_vec(), b"1".to_vec());
+        let mut other = DBTransaction::new();
+        other.delete(DBCol::BlockMisc, b"b".to_vec());
+        other.set(DBCol::BlockMisc, b"c".to_vec(), b"2345678".to_vec());
+        transaction.merge(other);
+    }
+
+    #[test]
+    fn test_try_merge() {
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"foo".to_vec(), b"a".to_vec());
+        transaction.insert(DBCol::Block, b"bar".to_vec(), b"b".to_vec());
+
+        // Same values and unrelated keys merge fine.
+        let mut other = DBTransaction::new();
+        other.set(DBCol::BlockMisc, b"foo".to_vec(), b"a".to_vec());
+        other.set(DBCol::BlockMisc, b"baz".to_vec(), b"c".to_vec());
+        other.set(DBCol::BlockHeader, b"bar".to_vec(), b"other column".to_vec());
+        other.delete(DBCol::Block, b"bar".to_vec());
+        transaction.try_merge(other).unwrap();
+        assert_eq!(keys(&transaction), [b"foo", b"bar", b"foo", b"baz", b"bar", b"bar"]);
+
+        // Differing value for the same key is a conflict.
+        let mut other = DBTransaction::new();
+        other.set(DBCol::BlockMisc, b"qux".to_vec(), b"d".to_vec());
+        other.insert(DBCol::Block, b"bar".to_vec(), b"not b".to_vec());
+        let err = transaction.try_merge(other).unwrap_err();
+        assert_eq!(err, MergeConflict { col: DBCol::Block, key: b"bar".to_vec() });
+        assert_eq!(transaction.ops.len(), 6);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let rc = std::num::NonZeroU32::new(1).unwrap();
+        let refcounted = refcount::add_positive_refcount(b"value", rc);
+        let hash = |ops: &[(u8, DBCol, &[u8])]| {
+            let mut transaction = DBTransaction::new();
+            for &(kind, col, key) in ops {
+                match kind {
+                    b's' => transaction.set(col, key.to_vec(), b"value".to_vec()),
+                    b'd' => transaction.delete(col, key.to_vec()),
+                    b'r' => transaction.update_refcount(col, key.to_vec(), refcounted.clone()),
+                    _ => transaction.delete_range(col, key.to_vec(), b"z".to_vec()),
+                }
+            }
+            transaction.content_hash()
+        };
+
+        let want = hash(&[(b's', DBCol::Block, b"a"), (b'd', DBCol::BlockMisc, b"b")]);
+        assert_eq!(want, hash(&[(b'd', DBCol::BlockMisc, b"b"), (b's', DBCol::Block, b"a")]));
+        // Only the last operation on a key matters.
+        assert_eq!(
+            want,
+            hash(&[
+                (b'd', DBCol::Block, b"a"),
+                (b'd', DBCol::BlockMisc, b"b"),
+                (b's', DBCol::Block, b"a"),
+            ])
+        );
+
+        assert_ne!(want, hash(&[(b's', DBCol::Block, b"a")]));
+        assert_ne!(want, hash(&[(b's', DBCol::Block, b"a"), (b'd', DBCol::BlockMisc, b"c")]));
+        assert_ne!(want, hash(&[(b's', DBCol::Block, b"a"), (b'd', DBCol::Block, b"b")]));
+        assert_ne!(want, DBTransaction::new().content_hash());
+
+        // Order relative to refcount updates and range deletions is kept.
+        let want = hash(&[(b's', DBCol::Block, b"a"), (b'x', DBCol::Block, b"a")]);
+        assert_ne!(want, hash(&[(b'x', DBCol::Block, b"a"), (b's', DBCol::Block, b"a")]));
+        let want = hash(&[(b'r', DBCol::State, b"a"), (b'r', DBCol::State, b"b")]);
+        assert_ne!(want, hash(&[(b'r', DBCol::State, b"b"), (b'r', DBCol::State, b"a")]));
+    }
+
+    #[test]
+    fn test_ops() {
+        let mut transaction = DBTransaction::new();
+        assert!(transaction.ops().is_empty());
+        transaction.set(DBCol::BlockMisc, b"foo".to_vec(), b"a".to_vec());
+        transaction.delete(DBCol::Block, b"bar".to_vec());
+        transaction.delete_all(DBCol::State);
+
+        let ops = transaction
+            .ops()
+            .iter()
+            .map(|op| match op {
+                DBOp::Set { col, key, value } => (*col, key.as_slice(), value.as_slice()),
+                DBOp::Delete { col, key } => (*col, key.as_slice(), &b"-"[..]),
+                DBOp::DeleteAll { col } => (*col, &b""[..], &b"-"[..]),
+                op => panic!("unexpected {op:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            [
+                (DBCol::BlockMisc, &b"foo"[..], &b"a"[..]),
+                (DBCol::Block, &b"bar"[..], &b"-"[..]),
+                (DBCol::State, &b""[..], &b"-"[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_ranges() {
+        fn ranges(transaction: &DBTransaction) -> Vec<(DBCol, &[u8], &[u8])> {
+            transaction
+                .ops
+                .iter()
+                .map(|op| match op {
+                    DBOp::DeleteRange { col, from, to } => (*col, from.as_slice(), to.as_slice()),
+                    DBOp::Set { col, key, .. } => (*col, key.as_slice(), &b"set"[..]),
+                    _ => unreachable!(),
+                })
+                .collect()
+        }
+
+        // Overlapping, adjacent and disjoint ranges.
+        let mut transaction = DBTransaction::new();
+        transaction.delete_range(DBCol::Block, b"a".to_vec(), b"c".to_vec());
+        transaction.delete_range(DBCol::Block, b"b".to_vec(), b"d".to_vec());
+        transaction.delete_range(DBCol::Block, b"d".to_vec(), b"e".to_vec());
+        transaction.delete_range(DBCol::Block, b"x".to_vec(), b"y".to_vec());
+        assert_eq!(transaction.normalize_ranges(), 2);
+        assert_eq!(
+            ranges(&transaction),
+            [(DBCol::Block, &b"a"[..], &b"e"[..]), (DBCol::Block, &b"x"[..], &b"y"[..])]
+        );
+
+        // A range bridging two earlier ranges merges all of them.
+        let mut transaction = DBTransaction::new();
+        transaction.delete_range(DBCol::Block, b"a".to_vec(), b"b".to_vec());
+        transaction.delete_range(DBCol::Block, b"c".to_vec(), b"d".to_vec());
+        transaction.delete_range(DBCol::Block, b"b".to_vec(), b"c".to_vec());
+        assert_eq!(transaction.normalize_ranges(), 2);
+        assert_eq!(ranges(&transaction), [(DBCol::Block, &b"a"[..], &b"d"[..])]);
+
+        // Ranges in different columns or separated by another operation on
+        // the column are not merged.
+        let mut transaction = DBTransaction::new();
+        transaction.delete_range(DBCol::Block, b"a".to_vec(), b"c".to_vec());
+        transaction.delete_range(DBCol::BlockHeader, b"b".to_vec(), b"d".to_vec());
+        transaction.set(DBCol::Block, b"b".to_vec(), b"value".to_vec());
+        transaction.delete_range(DBCol::Block, b"c".to_vec(), b"e".to_vec());
+        assert_eq!(transaction.normalize_ranges(), 0);
+        assert_eq!(
+            ranges(&transaction),
+            [
+                (DBCol::Block, &b"a"[..], &b"c"[..]),
+                (DBCol::BlockHeader, &b"b"[..], &b"d"[..]),
+                (DBCol::Block, &b"b"[..], &b"set"[..]),
+                (DBCol::Block, &b"c"[..], &b"e"[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_store_statistics_delta() {
+        let stats = |data: &[(&str, &[StatsValue])]| StoreStatistics {
+            data: data.iter().map(|(name, values)| (name.to_string(), values.to_vec())).collect(),
+        };
+        let prev = stats(&[
+            ("rocksdb.bytes.read", &[StatsValue::Count(100)]),
+            (
+                "rocksdb.db.get.micros",
+                &[StatsValue::Percentile(50, 1.0), StatsValue::Count(10), StatsValue::Sum(20)],
+            ),
+            (
+                "rocksdb.live-sst-files-size",
+                &[
+                    StatsValue::ColumnValue(DBCol::Block, 1000),
+                    StatsValue::ColumnValue(DBCol::State, 5),
+                ],
+            ),
+            ("rocksdb.gone", &[StatsValue::Count(7)]),
+        ]);
+        let next = stats(&[
+            ("rocksdb.bytes.read", &[StatsValue::Count(150)]),
+            (
+                "rocksdb.db.get.micros",
+                &[StatsValue::Percentile(50, 2.5), StatsValue::Count(15), StatsValue::Sum(40)],
+            ),
+            (
+                "rocksdb.live-sst-files-size",
+                &[
+                    StatsValue::ColumnValue(DBCol::Block, 1200),
+                    StatsValue::ColumnValue(DBCol::BlockMisc, 30),
+                ],
+            ),
+            ("rocksdb.new", &[StatsValue::Count(3)]),
+        ]);
+        let want = stats(&[
+            ("rocksdb.bytes.read", &[StatsValue::Count(50)]),
+            (
+                "rocksdb.db.get.micros",
+                &[StatsValue::Percentile(50, 2.5), StatsValue::Count(5), StatsValue::Sum(20)],
+            ),
+            (
+                "rocksdb.live-sst-files-size",
+                &[
+                    StatsValue::ColumnValue(DBCol::Block, 200),
+                    StatsValue::ColumnValue(DBCol::BlockMisc, 30),
+                ],
+            ),
+            ("rocksdb.new", &[StatsValue::Count(3)]),
+        ]);
+        assert_eq!(next.delta(&prev), want);
+    }
+
+    #[test]
+    fn test_store_statistics_to_json() {
+        let stats = StoreStatistics {
+            data: vec![
+                ("rocksdb.bytes.read".to_string(), vec![StatsValue::Count(100)]),
+                (
+                    "rocksdb.db.get.micros".to_string(),
+                    vec![StatsValue::Percentile(99, 2.5), StatsValue::Sum(20)],
+                ),
+                (
+                    "rocksdb.live-sst-files-size".to_string(),
+                    vec![StatsValue::ColumnValue(DBCol::BlockMisc, 30)],
+                ),
+            ],
+        };
+        let want = serde_json::json!([
+            {"name": "rocksdb.bytes.read", "values": [{"kind": "count", "value": 100}]},
+            {
+                "name": "rocksdb.db.get.micros",
+                "values": [
+                    {"kind": "percentile", "percentile": 99, "value": 2.5},
+                    {"kind": "sum", "value": 20},
+                ],
+            },
+            {
+                "name": "rocksdb.live-sst-files-size",
+                "values": [{"kind": "column_value", "column": "BlockMisc", "value": 30}],
+            },
+        ]);
+        assert_eq!(stats.to_json(), want);
+        assert_eq!(serde_json::to_value(&stats).unwrap(), want);
+        let want = r#"[{"name":"rocksdb.bytes.read","values":[{"kind":"count","value":100}]}"#;
+        assert!(serde_json::to_string(&stats).unwrap().starts_with(want));
+    }
+
+    #[test]
+    fn test_insert_after_delete() {
+        let mut transaction = DBTransaction::new();
+        transaction.insert(DBCol::Block, b"foo".to_vec(), b"a".to_vec());
+        transaction.delete(DBCol::Block, b"foo".to_vec());
+        transaction.delete(DBCol::Block, b"bar".to_vec());
+        transaction.delete(DBCol::BlockHeader, b"baz".to_vec());
+        // Different key or column is fine.
+        transaction.insert(DBCol::Block, b"baz".to_vec(), b"b".to_vec());
+        assert_eq!(keys(&transaction).len(), 5);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "deleted earlier in the batch")]
+    fn test_insert_after_delete_panics() {
+        let mut transaction = DBTransaction::new();
+        transaction.delete(DBCol::Block, b"foo".to_vec());
+        transaction.insert(DBCol::Block, b"foo".to_vec(), b"a".to_vec());
+    }
+
+    #[test]
+    fn test_into_chunks() {
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"a".to_vec(), vec![0; 9]);
+        transaction.set(DBCol::BlockMisc, b"b".to_vec(), vec![0; 4]);
+        transaction.delete(DBCol::BlockMisc, b"c".to_vec());
+        transaction.set(DBCol::BlockMisc, b"d".to_vec(), vec![0; 30]);
+        transaction.set(DBCol::BlockMisc, b"e".to_vec(), vec![0; 4]);
+        assert_eq!(transaction.size_bytes(), 53);
+
+        let chunks = transaction.into_chunks(10);
+        let chunks = chunks.iter().map(keys).collect::<Vec<_>>();
+        let want: [&[&[u8]]; 4] = [&[b"a"], &[b"b", b"c"], &[b"d"], &[b"e"]];
+        assert_eq!(chunks, want);
+
+        assert!(DBTransaction::new().into_chunks(10).is_empty());
+    }
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        fn test(want: Option<&[u8]>, prefix: &[u8]) {
+            assert_eq!(want, prefix_upper_bound(prefix).as_deref(), "{prefix:?}");
+        }
+
+        test(None, b"");
+        test(None, b"\xff");
+        test(None, b"\xff\xff\xff");
+        test(Some(b"\x01"), b"\x00");
+        test(Some(b"b"), b"a");
+        test(Some(b"ab"), b"aa");
+        test(Some(b"b"), b"a\xff");
+        test(Some(b"b"), b"a\xff\xff");
+        test(Some(b"\xff\x01"), b"\xff\x00\xff");
+        test(Some(b"a\xff\x01"), b"a\xff\x00");
+
+        // Exhaustively check short prefixes built from interesting bytes against
+        // all slightly longer keys built from the same bytes.  A key must be
+        // below the bound iff it’s less than the prefix or starts with it.
+        let mut keys = vec![vec![]];
+        for len in 1..=3 {
+            for key in keys.clone().into_iter().filter(|key| key.len() == len - 1) {
+                for byte in [0x00, 0x01, 0x7f, 0xfe, 0xff] {
+                    keys.push([key.as_slice(), &[byte]].concat());
+                }
+            }
+        }
+        for prefix in keys.iter().filter(|prefix| prefix.len() <= 2) {
+            let bound = prefix_upper_bound(prefix);
+            for key in keys.iter() {
+                let below = bound.as_ref().map_or(true, |bound| key < bound);
+                let want = key < prefix || key.starts_with(prefix);
+                assert_eq!(want, below, "{prefix:?} {key:?}");
+            }
+        }
+    }
+}
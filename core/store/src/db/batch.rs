@@ -0,0 +1,245 @@
+use std::io;
+use std::num::NonZeroU32;
+
+use borsh::BorshSerialize;
+use near_fmt::StorageKey;
+
+use crate::db::{refcount, DBOp, DBTransaction, Database};
+use crate::DBCol;
+
+/// Builder of a [`DBTransaction`] which serialises values and checks that
+/// operations suit the columns they’re applied to.
+///
+/// The checks are the same as done by [`crate::StoreUpdate`] except that
+/// misuse, e.g. setting a value in a reference counted column, is reported as
+/// an [`io::ErrorKind::InvalidInput`] error rather than a panic.  An
+/// operation which fails the check isn’t added and the builder can still be
+/// used.
+///
+/// ```ignore
+/// db.batch_builder()
+///     .set_borsh(DBCol::BlockMisc, b"key", &value)?
+///     .delete(DBCol::BlockMisc, b"other")?
+///     .write()?;
+/// ```
+pub struct BatchBuilder<'a> {
+    db: &'a dyn Database,
+    transaction: DBTransaction,
+}
+
+impl<'a> BatchBuilder<'a> {
+    pub fn new(db: &'a dyn Database) -> Self {
+        Self { db, transaction: DBTransaction::new() }
+    }
+
+    /// Sets value in a column which is neither reference counted nor
+    /// insert-only.
+    pub fn set(&mut self, col: DBCol, key: &[u8], value: &[u8]) -> io::Result<&mut Self> {
+        check(!(col.is_rc() || col.is_insert_only()), "set", col, key)?;
+        self.transaction.set(col, key.to_vec(), value.to_vec());
+        Ok(self)
+    }
+
+    /// Borsh-serialises the value and sets it like [`Self::set`].
+    pub fn set_borsh<T: BorshSerialize + ?Sized>(
+        &mut self,
+        col: DBCol,
+        key: &[u8],
+        value: &T,
+    ) -> io::Result<&mut Self> {
+        check(!(col.is_rc() || col.is_insert_only()), "set", col, key)?;
+        self.transaction.set(col, key.to_vec(), borsh::to_vec(value)?);
+        Ok(self)
+    }
+
+    /// Inserts value in an insert-only column.  The key mustn’t have been
+    /// deleted earlier in the batch.
+    pub fn insert(&mut self, col: DBCol, key: &[u8], value: &[u8]) -> io::Result<&mut Self> {
+        check(col.is_insert_only(), "insert", col, key)?;
+        self.check_not_deleted(col, key)?;
+        self.transaction.insert(col, key.to_vec(), value.to_vec());
+        Ok(self)
+    }
+
+    /// Borsh-serialises the value and inserts it like [`Self::insert`].
+    pub fn insert_borsh<T: BorshSerialize + ?Sized>(
+        &mut self,
+        col: DBCol,
+        key: &[u8],
+        value: &T,
+    ) -> io::Result<&mut Self> {
+        check(col.is_insert_only(), "insert", col, key)?;
+        self.check_not_deleted(col, key)?;
+        self.transaction.insert(col, key.to_vec(), borsh::to_vec(value)?);
+        Ok(self)
+    }
+
+    /// Increases reference count of a value in a reference counted column.
+    pub fn increment_refcount(
+        &mut self,
+        col: DBCol,
+        key: &[u8],
+        value: &[u8],
+        increase: NonZeroU32,
+    ) -> io::Result<&mut Self> {
+        check(col.is_rc(), "increment refcount of", col, key)?;
+        let value = refcount::add_positive_refcount(value, increase);
+        self.transaction.update_refcount(col, key.to_vec(), value);
+        Ok(self)
+    }
+
+    /// Decreases reference count of a value in a reference counted column.
+    pub fn decrement_refcount(
+        &mut self,
+        col: DBCol,
+        key: &[u8],
+        decrease: NonZeroU32,
+    ) -> io::Result<&mut Self> {
+        check(col.is_rc(), "decrement refcount of", col, key)?;
+        let value = refcount::encode_negative_refcount(decrease);
+        self.transaction.update_refcount(col, key.to_vec(), value);
+        Ok(self)
+    }
+
+    /// Deletes key from a column which isn’t reference counted.
+    pub fn delete(&mut self, col: DBCol, key: &[u8]) -> io::Result<&mut Self> {
+        check(!col.is_rc(), "delete", col, key)?;
+        self.transaction.delete(col, key.to_vec());
+        Ok(self)
+    }
+
+    /// Returns an error if the key has been deleted earlier in the batch.
+    /// [`DBTransaction::insert`] asserts the same.
+    fn check_not_deleted(&self, col: DBCol, key: &[u8]) -> io::Result<()> {
+        let deleted = self.transaction.ops().iter().any(|op| match op {
+            DBOp::Delete { col: op_col, key: op_key } => *op_col == col && op_key == key,
+            _ => false,
+        });
+        if deleted {
+            let msg =
+                format!("can’t insert {} in {col} deleted earlier in the batch", StorageKey(key));
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        Ok(())
+    }
+
+    /// Returns the built transaction.
+    pub fn build(&mut self) -> DBTransaction {
+        std::mem::take(&mut self.transaction)
+    }
+
+    /// Writes the built transaction to the database the builder was created
+    /// for.
+    pub fn write(&mut self) -> io::Result<()> {
+        self.db.write(self.build())
+    }
+}
+
+/// Returns an error describing `op` as unsuitable for `col` unless `ok`.
+fn check(ok: bool, op: &str, col: DBCol, key: &[u8]) -> io::Result<()> {
+    if ok {
+        return Ok(());
+    }
+    let kind = if col.is_rc() {
+        "reference counted"
+    } else if col.is_insert_only() {
+        "insert-only"
+    } else {
+        "plain"
+    };
+    let msg = format!("can’t {op} {} in {kind} column {col}", StorageKey(key));
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+impl dyn Database + '_ {
+    /// Returns a builder of a transaction for this database; see
+    /// [`BatchBuilder`].
+    pub fn batch_builder(&self) -> BatchBuilder<'_> {
+        BatchBuilder::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TestDB;
+
+    #[test]
+    fn test_batch_builder() {
+        let db: std::sync::Arc<dyn Database> = TestDB::new();
+        let one = NonZeroU32::new(1).unwrap();
+        let transaction = db
+            .batch_builder()
+            .set_borsh(DBCol::BlockMisc, b"a", &42u64)
+            .unwrap()
+            .set(DBCol::BlockMisc, b"b", b"value")
+            .unwrap()
+            .insert_borsh(DBCol::Block, b"c", "block")
+            .unwrap()
+            .increment_refcount(DBCol::State, b"d", b"node", one)
+            .unwrap()
+            .delete(DBCol::BlockMisc, b"e")
+            .unwrap()
+            .build();
+        assert_eq!(transaction.ops().len(), 5);
+        assert!(matches!(
+            &transaction.ops()[0],
+            DBOp::Set { col: DBCol::BlockMisc, key, value }
+                if key == b"a" && *value == 42u64.to_le_bytes()
+        ));
+
+        db.batch_builder().set(DBCol::BlockMisc, b"key", b"value").unwrap().write().unwrap();
+        assert_eq!(
+            db.get_raw_bytes(DBCol::BlockMisc, b"key").unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+    }
+
+    #[test]
+    fn test_batch_builder_validation() {
+        let db = TestDB::new();
+        let one = NonZeroU32::new(1).unwrap();
+        let mut builder = BatchBuilder::new(db.as_ref());
+        let check = |result: io::Result<&mut BatchBuilder>, want: &str| {
+            let err = result.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(err.to_string(), want);
+        };
+        check(
+            builder.set(DBCol::State, b"key", b""),
+            "can’t set 'key' in reference counted column State",
+        );
+        check(
+            builder.set_borsh(DBCol::Block, b"key", &1u8),
+            "can’t set 'key' in insert-only column Block",
+        );
+        check(
+            builder.insert(DBCol::BlockMisc, b"key", b""),
+            "can’t insert 'key' in plain column BlockMisc",
+        );
+        check(
+            builder.increment_refcount(DBCol::BlockMisc, b"key", b"", one),
+            "can’t increment refcount of 'key' in plain column BlockMisc",
+        );
+        check(
+            builder.decrement_refcount(DBCol::Block, b"key", one),
+            "can’t decrement refcount of 'key' in insert-only column Block",
+        );
+        check(
+            builder.delete(DBCol::State, b"key"),
+            "can’t delete 'key' in reference counted column State",
+        );
+        // Failed operations aren’t added and the builder is still usable.
+        builder.delete(DBCol::Block, b"key").unwrap();
+        check(
+            builder.insert(DBCol::Block, b"key", b""),
+            "can’t insert 'key' in Block deleted earlier in the batch",
+        );
+        check(
+            builder.insert_borsh(DBCol::Block, b"key", &1u8),
+            "can’t insert 'key' in Block deleted earlier in the batch",
+        );
+        builder.insert(DBCol::Block, b"other", b"").unwrap();
+        assert_eq!(builder.build().ops().len(), 2);
+    }
+}
@@ -1,7 +1,11 @@
+use near_fmt::StorageKey;
 use near_o11y::{log_assert, log_assert_fail};
 
 use crate::db::refcount::set_refcount;
-use crate::db::{DBIterator, DBOp, DBSlice, DBTransaction, Database};
+use crate::db::{
+    CompactionPriority, DBIterator, DBOp, DBSlice, DBTransaction, Database, KeyLocation,
+    ReadOptions, WriteId,
+};
 use crate::DBCol;
 
 /// A database which provides access to the cold storage.
@@ -41,6 +45,10 @@ impl ColdDB {
 }
 
 impl Database for ColdDB {
+    fn name(&self) -> &str {
+        "colddb"
+    }
+
     /// Returns raw bytes for given `key` ignoring any reference count decoding if any.
     fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> std::io::Result<Option<DBSlice<'_>>> {
         Self::check_is_in_colddb(col)?;
@@ -53,12 +61,42 @@ impl Database for ColdDB {
         self.cold.get_with_rc_stripped(col, key)
     }
 
+    fn get_with_rc(&self, col: DBCol, key: &[u8]) -> std::io::Result<Option<(DBSlice<'_>, i64)>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.get_with_rc(col, key)
+    }
+
+    fn get_refcount(&self, col: DBCol, key: &[u8]) -> std::io::Result<i64> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.get_refcount(col, key)
+    }
+
+    fn count_distinct_prefixes(&self, col: DBCol, prefix_len: usize) -> std::io::Result<u64> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.count_distinct_prefixes(col, prefix_len)
+    }
+
+    fn get_with_opts(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        opts: &ReadOptions,
+    ) -> std::io::Result<Option<DBSlice<'_>>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.get_with_opts(col, key, opts)
+    }
+
     /// Iterates over all values in a column.
     fn iter<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
         Self::log_assert_is_in_colddb(col);
         self.cold.iter(col)
     }
 
+    fn iter_with_opts<'a>(&'a self, col: DBCol, opts: &ReadOptions) -> DBIterator<'a> {
+        Self::log_assert_is_in_colddb(col);
+        self.cold.iter_with_opts(col, opts)
+    }
+
     /// Iterates over values in a given column whose key has given prefix.
     fn iter_prefix<'a>(&'a self, col: DBCol, key_prefix: &'a [u8]) -> DBIterator<'a> {
         Self::log_assert_is_in_colddb(col);
@@ -71,6 +109,15 @@ impl Database for ColdDB {
         self.cold.iter_raw_bytes(col)
     }
 
+    fn iter_keys_and_small_values<'a>(
+        &'a self,
+        col: DBCol,
+        max_value_bytes: usize,
+    ) -> Box<dyn Iterator<Item = std::io::Result<(Box<[u8]>, Option<Box<[u8]>>, usize)>> + 'a> {
+        Self::log_assert_is_in_colddb(col);
+        self.cold.iter_keys_and_small_values(col, max_value_bytes)
+    }
+
     /// Iterate over items in given column whose keys are between [lower_bound, upper_bound)
     fn iter_range<'a>(
         &'a self,
@@ -82,31 +129,177 @@ impl Database for ColdDB {
         self.cold.iter_range(col, lower_bound, upper_bound)
     }
 
+    fn iter_range_rev<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        Self::log_assert_is_in_colddb(col);
+        self.cold.iter_range_rev(col, lower_bound, upper_bound)
+    }
+
     /// Atomically applies operations in given transaction.
     ///
     /// If debug assertions are enabled, panics if there are any delete
     /// operations or operations decreasing reference count of a value.  If
     /// debug assertions are not enabled, such operations are filtered out.
     fn write(&self, mut transaction: DBTransaction) -> std::io::Result<()> {
-        let mut idx = 0;
-        while idx < transaction.ops.len() {
-            if adjust_op(&mut transaction.ops[idx]) {
-                idx += 1;
-            } else {
-                transaction.ops.swap_remove(idx);
-            }
-        }
+        adjust_ops(&mut transaction);
         self.cold.write(transaction)
     }
 
+    fn write_with_id(&self, mut transaction: DBTransaction) -> std::io::Result<WriteId> {
+        adjust_ops(&mut transaction);
+        self.cold.write_with_id(transaction)
+    }
+
+    /// Since no data is ever deleted from cold storage, `f` returning `None`
+    /// leaves the value unchanged and the update fails with
+    /// [`std::io::ErrorKind::InvalidInput`] error.
+    fn update(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> std::io::Result<()> {
+        Self::check_is_in_colddb(col)?;
+        let deleted = std::cell::Cell::new(false);
+        self.cold.update(col, key, &|value| {
+            let new_value = f(value);
+            deleted.set(new_value.is_none());
+            new_value.or_else(|| value.map(<[u8]>::to_vec))
+        })?;
+        if deleted.get() {
+            let msg = format!("can’t delete {} from {col} in cold store", StorageKey(key));
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+        }
+        Ok(())
+    }
+
+    fn write_if_unchanged(
+        &self,
+        mut transaction: DBTransaction,
+        witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> std::io::Result<bool> {
+        for (col, _, _) in witnesses {
+            Self::check_is_in_colddb(*col)?;
+        }
+        adjust_ops(&mut transaction);
+        self.cold.write_if_unchanged(transaction, witnesses)
+    }
+
+    fn approx_keys_in_range(&self, col: DBCol, from: &[u8], to: &[u8]) -> std::io::Result<u64> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.approx_keys_in_range(col, from, to)
+    }
+
+    fn key_range(&self, col: DBCol) -> std::io::Result<Option<(Box<[u8]>, Box<[u8]>)>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.key_range(col)
+    }
+
+    fn contains_keys(&self, col: DBCol, keys: &[&[u8]]) -> std::io::Result<Vec<bool>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.contains_keys(col, keys)
+    }
+
+    fn get_sorted(
+        &self,
+        col: DBCol,
+        sorted_keys: &[&[u8]],
+    ) -> std::io::Result<Vec<Option<DBSlice<'_>>>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.get_sorted(col, sorted_keys)
+    }
+
+    fn level_file_counts(&self, col: DBCol) -> std::io::Result<Vec<usize>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.level_file_counts(col)
+    }
+
+    fn prefetch_range(&self, col: DBCol, from: &[u8], to: &[u8]) -> std::io::Result<()> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.prefetch_range(col, from, to)
+    }
+
+    fn iter_level<'a>(&'a self, col: DBCol, level: usize) -> std::io::Result<DBIterator<'a>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.iter_level(col, level)
+    }
+
+    fn total_disk_size(&self) -> std::io::Result<u64> {
+        self.cold.total_disk_size()
+    }
+
+    fn wal_sequence_range(&self) -> std::io::Result<Option<(u64, u64)>> {
+        self.cold.wal_sequence_range()
+    }
+
+    fn compaction_pending_bytes(&self, col: DBCol) -> std::io::Result<u64> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.compaction_pending_bytes(col)
+    }
+
+    fn key_location(&self, col: DBCol, key: &[u8]) -> std::io::Result<Option<KeyLocation>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.key_location(col, key)
+    }
+
+    fn sample_values(&self, col: DBCol, sample_size: usize) -> std::io::Result<Vec<Box<[u8]>>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.sample_values(col, sample_size)
+    }
+
+    fn compression_ratio(&self, col: DBCol) -> std::io::Result<Option<f64>> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.compression_ratio(col)
+    }
+
+    fn bloom_filter_stats(&self, col: DBCol) -> std::io::Result<crate::db::BloomStats> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.bloom_filter_stats(col)
+    }
+
+    fn effective_options(&self, col: DBCol) -> std::io::Result<String> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.effective_options(col)
+    }
+
+    fn request_compaction_priority(
+        &self,
+        col: DBCol,
+        priority: CompactionPriority,
+    ) -> std::io::Result<()> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.request_compaction_priority(col, priority)
+    }
+
     fn compact(&self) -> std::io::Result<()> {
         self.cold.compact()
     }
 
+    /// Always fails since no data is ever deleted from cold storage.
+    fn clear_range_and_compact(&self, col: DBCol, _from: &[u8], _to: &[u8]) -> std::io::Result<()> {
+        let msg = format!("can’t clear range of {col} in cold store");
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, msg))
+    }
+
+    /// Always fails since no data is ever deleted from cold storage.
+    fn delete_files_in_range(&self, col: DBCol, _from: &[u8], _to: &[u8]) -> std::io::Result<()> {
+        let msg = format!("can’t delete files of {col} in cold store");
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, msg))
+    }
+
     fn flush(&self) -> std::io::Result<()> {
         self.cold.flush()
     }
 
+    fn flush_column(&self, col: DBCol) -> std::io::Result<()> {
+        Self::check_is_in_colddb(col)?;
+        self.cold.flush_column(col)
+    }
+
     fn get_store_statistics(&self) -> Option<crate::StoreStatistics> {
         self.cold.get_store_statistics()
     }
@@ -120,6 +313,22 @@ impl Database for ColdDB {
     }
 }
 
+/// Adjusts all operations of the transaction with [`adjust_op`] removing the
+/// ones which should not be applied to cold storage.
+fn adjust_ops(transaction: &mut DBTransaction) {
+    // Cold storage doesn’t track reference counts and stores values with
+    // reference count set to one.
+    transaction.allow_raw_rc_writes();
+    let mut idx = 0;
+    while idx < transaction.ops.len() {
+        if adjust_op(&mut transaction.ops[idx]) {
+            idx += 1;
+        } else {
+            transaction.ops.swap_remove(idx);
+        }
+    }
+}
+
 /// Adjust database operation to be performed on cold storage.
 ///
 /// Returns whether the operation should be kept or dropped.  Generally, dropped
@@ -238,7 +447,7 @@ mod test {
         // Block -> no rc
 
         let ops = vec![set_with_rc(DBCol::State, &[SHARD, HASH].concat()), set(DBCol::Block, HASH)];
-        db.write(DBTransaction { ops }).unwrap();
+        db.write(DBTransaction { ops, ..Default::default() }).unwrap();
 
         // Fetch data
         let mut result = Vec::<String>::new();
@@ -298,7 +507,7 @@ mod test {
 
         // Populate data
         let ops = vec![set_with_rc(DBCol::State, &[SHARD, HASH].concat()), set(DBCol::Block, HASH)];
-        db.write(DBTransaction { ops }).unwrap();
+        db.write(DBTransaction { ops, ..Default::default() }).unwrap();
 
         let mut result = Vec::<String>::new();
         for col in [DBCol::State, DBCol::Block] {
@@ -336,7 +545,7 @@ mod test {
 
         let op =
             DBOp::UpdateRefcount { col, key: key.to_vec(), value: [VALUE, HEIGHT_LE].concat() };
-        db.write(DBTransaction { ops: vec![op] }).unwrap();
+        db.write(DBTransaction { ops: vec![op], ..Default::default() }).unwrap();
 
         // Refcount is set to 1 in the underlying database.
         let got = db.cold.get_raw_bytes(col, key).unwrap();
@@ -346,4 +555,29 @@ mod test {
         let got = db.get_raw_bytes(col, key).unwrap();
         assert_eq!(Some([VALUE, ONE].concat().as_slice()), got.as_deref());
     }
+
+    #[test]
+    fn test_update() {
+        let db = create_test_cold_db();
+        let col = DBCol::BlockMisc;
+        db.update(col, b"key", &|_| Some(VALUE.to_vec())).unwrap();
+        assert_eq!(db.get_raw_bytes(col, b"key").unwrap().as_deref(), Some(VALUE));
+
+        // Updates can’t delete data.
+        let err = db.update(col, b"key", &|_| None).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(db.get_raw_bytes(col, b"key").unwrap().as_deref(), Some(VALUE));
+    }
+
+    #[test]
+    fn test_range_deletion() {
+        let db = create_test_cold_db();
+        let col = DBCol::BlockMisc;
+        db.write(DBTransaction { ops: vec![set(col, b"key")], ..Default::default() }).unwrap();
+        let err = db.clear_range_and_compact(col, b"a", b"z").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        let err = db.delete_files_in_range(col, b"a", b"z").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(db.get_raw_bytes(col, b"key").unwrap().as_deref(), Some(VALUE));
+    }
 }
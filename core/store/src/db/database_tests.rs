@@ -3,10 +3,13 @@
 
 #[cfg(test)]
 mod tests {
+    use std::num::NonZeroU32;
     use std::sync::Arc;
 
+    use near_fmt::{AbbrBytes, StorageKey};
+
     use crate::{
-        db::{DBTransaction, Database, TestDB},
+        db::{refcount, DBOp, DBTransaction, Database, TestDB},
         DBCol, NodeStorage,
     };
 
@@ -17,6 +20,46 @@ mod tests {
         vec![TestDB::new(), store.storage.clone()]
     }
 
+    /// Checks that `iter` over a reference counted column yields exactly the
+    /// entries of `iter_raw_bytes` with positive reference count, with the
+    /// count stripped.  Returns descriptions of all discrepancies.
+    fn verify_rc_iter_consistency(db: &dyn Database, col: DBCol) -> Vec<String> {
+        use std::collections::BTreeMap;
+
+        assert!(col.is_rc());
+        let want = db
+            .iter_raw_bytes(col)
+            .map(Result::unwrap)
+            .filter_map(|(key, value)| {
+                let value = refcount::decode_value_with_rc(&value).0?.to_vec();
+                Some((key, value.into_boxed_slice()))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut got = BTreeMap::new();
+        let mut errors = Vec::new();
+        for (key, value) in db.iter(col).map(Result::unwrap) {
+            if got.insert(key.clone(), value).is_some() {
+                errors.push(format!("{col}: {} yielded more than once", StorageKey(&key)));
+            }
+        }
+        for (key, value) in &want {
+            match got.get(key) {
+                None => errors.push(format!("{col}: {} not yielded", StorageKey(key))),
+                Some(got) if got != value => errors.push(format!(
+                    "{col}: {} yielded {} rather than {}",
+                    StorageKey(key),
+                    AbbrBytes(&got[..]),
+                    AbbrBytes(&value[..])
+                )),
+                Some(_) => {}
+            }
+        }
+        for key in got.keys().filter(|key| !want.contains_key(*key)) {
+            errors.push(format!("{col}: {} unexpectedly yielded", StorageKey(key)));
+        }
+        errors
+    }
+
     /// Tests the behavior of the iterators. Iterators don't really work over cold storage, so we're not testing it here.
     #[test]
     fn test_db_iter() {
@@ -42,4 +85,1183 @@ mod tests {
             assert_eq!(keys, vec!["aa", "aa1"]);
         }
     }
+
+    #[test]
+    fn test_value_len() {
+        for db in test_and_rocksdb() {
+            let rc_value =
+                refcount::add_positive_refcount(b"rc_value", NonZeroU32::new(2).unwrap());
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, "foo".into(), "value".into());
+            transaction.update_refcount(DBCol::State, "bar".into(), rc_value);
+            db.write(transaction).unwrap();
+
+            assert_eq!(db.value_len(DBCol::BlockMisc, b"foo").unwrap(), Some(5));
+            assert_eq!(db.value_len(DBCol::BlockMisc, b"bar").unwrap(), None);
+
+            // Stored bytes include the refcount tail but reported length doesn’t.
+            let raw = db.get_raw_bytes(DBCol::State, b"bar").unwrap();
+            assert_eq!(raw.map(|value| value.len()), Some(16));
+            assert_eq!(db.value_len(DBCol::State, b"bar").unwrap(), Some(8));
+            assert_eq!(db.value_len(DBCol::State, b"foo").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_iter_validated() {
+        for db in test_and_rocksdb() {
+            // BlockHeight keys are 8-byte heights.
+            let col = DBCol::BlockHeight;
+            let mut transaction = DBTransaction::new();
+            transaction.set(col, vec![0; 8], "a".into());
+            transaction.set(col, vec![1, 2, 3], "b".into());
+            transaction.set(col, vec![5; 8], "c".into());
+            db.write(transaction).unwrap();
+
+            let items: Vec<_> = db.iter_validated(col).collect();
+            assert_eq!(items.len(), 3);
+            assert_eq!(&*items[0].as_ref().unwrap().0, &[0; 8]);
+            let err = items[1].as_ref().unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+            assert_eq!(&*items[2].as_ref().unwrap().0, &[5; 8]);
+
+            // Columns with variable length keys are passed through.
+            assert_eq!(db.iter_validated(DBCol::BlockMisc).count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_approx_keys_in_range_testdb() {
+        let db = TestDB::new();
+        let mut transaction = DBTransaction::new();
+        for key in 0u8..100 {
+            transaction.set(DBCol::BlockMisc, vec![key], vec![key]);
+        }
+        db.write(transaction).unwrap();
+
+        // TestDB counts the keys exactly.
+        assert_eq!(db.approx_keys_in_range(DBCol::BlockMisc, &[10], &[40]).unwrap(), 30);
+        assert_eq!(db.approx_keys_in_range(DBCol::BlockMisc, &[], &[255]).unwrap(), 100);
+        assert_eq!(db.approx_keys_in_range(DBCol::BlockMisc, &[200], &[255]).unwrap(), 0);
+        assert_eq!(db.approx_keys_in_range(DBCol::BlockHeader, &[], &[255]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_delete_range_empty_and_inverted() {
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            for key in 0u8..10 {
+                transaction.set(DBCol::BlockMisc, vec![key], vec![key]);
+            }
+            db.write(transaction).unwrap();
+
+            // Empty range deletes nothing.
+            let mut transaction = DBTransaction::new();
+            transaction.delete_range(DBCol::BlockMisc, vec![5], vec![5]);
+            db.write(transaction).unwrap();
+            assert_eq!(db.iter(DBCol::BlockMisc).count(), 10);
+
+            // Inverted range is a no-op.  DBTransaction::delete_range
+            // debug-asserts so construct the operation directly.
+            let mut transaction = DBTransaction::new();
+            transaction.ops.push(DBOp::DeleteRange {
+                col: DBCol::BlockMisc,
+                from: vec![8],
+                to: vec![2],
+            });
+            db.write(transaction).unwrap();
+            assert_eq!(db.iter(DBCol::BlockMisc).count(), 10);
+        }
+    }
+
+    #[test]
+    fn test_update_concurrent() {
+        const THREADS: u64 = 8;
+        const UPDATES: u64 = 100;
+        let increment = |value: Option<&[u8]>| {
+            let count = value.map_or(0, |value| u64::from_le_bytes(value.try_into().unwrap()));
+            Some((count + 1).to_le_bytes().to_vec())
+        };
+        for db in test_and_rocksdb() {
+            std::thread::scope(|scope| {
+                for _ in 0..THREADS {
+                    scope.spawn(|| {
+                        for _ in 0..UPDATES {
+                            db.update(DBCol::BlockMisc, b"counter", &increment).unwrap();
+                        }
+                    });
+                }
+            });
+            let value = db.get_raw_bytes(DBCol::BlockMisc, b"counter").unwrap().unwrap();
+            assert_eq!(u64::from_le_bytes((*value).try_into().unwrap()), THREADS * UPDATES);
+
+            // Returning None deletes the key.
+            db.update(DBCol::BlockMisc, b"counter", &|_| None).unwrap();
+            assert_eq!(db.get_raw_bytes(DBCol::BlockMisc, b"counter").unwrap(), None);
+
+            let err = db.update(DBCol::State, b"key", &|_| Some(vec![])).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_update_with_concurrent_write() {
+        for db in test_and_rocksdb() {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    receiver.recv().unwrap();
+                    let mut transaction = DBTransaction::new();
+                    transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"write".to_vec());
+                    db.write(transaction).unwrap();
+                });
+                // The plain write started in the middle of the update may
+                // only land after it so it isn’t overwritten.
+                db.update(DBCol::BlockMisc, b"key", &|_| {
+                    let _ = sender.send(());
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                    Some(b"update".to_vec())
+                })
+                .unwrap();
+            });
+            let value = db.get_raw_bytes(DBCol::BlockMisc, b"key").unwrap();
+            assert_eq!(value.as_deref(), Some(&b"write"[..]));
+        }
+    }
+
+    #[test]
+    fn test_refcount_decode_errors() {
+        for db in test_and_rocksdb() {
+            // A value in a reference counted column too short to hold
+            // a refcount.
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            transaction.set(DBCol::State, b"bad".to_vec(), b"foo".to_vec());
+            db.write(transaction).unwrap();
+
+            let errors = db.refcount_decode_errors();
+            assert_eq!(db.get_with_rc_stripped(DBCol::State, b"bad").unwrap(), None);
+            assert!(db.refcount_decode_errors() > errors);
+
+            let errors = db.refcount_decode_errors();
+            assert_eq!(db.iter(DBCol::State).count(), 0);
+            assert!(db.refcount_decode_errors() > errors);
+        }
+    }
+
+    #[test]
+    fn test_count_prefix() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(1).unwrap();
+            let mut transaction = DBTransaction::new();
+            for key in ["a", "aa", "aa1", "ab", "b", "bb1", "c"] {
+                transaction.set(DBCol::BlockMisc, key.into(), "value".into());
+                let value = refcount::add_positive_refcount(b"value", rc);
+                transaction.update_refcount(DBCol::State, key.into(), value);
+            }
+            db.write(transaction).unwrap();
+            // Cell with negative refcount isn’t returned when iterating.  It’s
+            // set as a raw value which needs to be explicitly allowed.
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            let value = refcount::encode_negative_refcount(rc);
+            transaction.set(DBCol::State, "ac".into(), value);
+            db.write(transaction).unwrap();
+
+            for col in [DBCol::BlockMisc, DBCol::State] {
+                assert_eq!(db.count_prefix(col, b"").unwrap(), 7);
+                assert_eq!(db.count_prefix(col, b"a").unwrap(), 4);
+                assert_eq!(db.count_prefix(col, b"aa").unwrap(), 2);
+                assert_eq!(db.count_prefix(col, b"b").unwrap(), 2);
+                assert_eq!(db.count_prefix(col, b"d").unwrap(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_chunked() {
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            for key in 0u8..100 {
+                transaction.set(DBCol::BlockMisc, vec![key], vec![key; 100]);
+            }
+            db.write_chunked(transaction, 1 << 20).unwrap();
+            assert_eq!(db.iter(DBCol::BlockMisc).count(), 100);
+
+            let mut transaction = DBTransaction::new();
+            for key in 100u8..200 {
+                transaction.set(DBCol::BlockMisc, vec![key], vec![key; 100]);
+            }
+            db.write_chunked(transaction, 1000).unwrap();
+            assert_eq!(db.iter(DBCol::BlockMisc).count(), 200);
+        }
+    }
+
+    #[test]
+    fn test_iter_prefix_from() {
+        let keys = |iter: crate::db::DBIterator<'_>| -> Vec<String> {
+            iter.map(|item| String::from_utf8(item.unwrap().0.into_vec()).unwrap()).collect()
+        };
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            for key in ["a", "b", "b1", "b2", "b2a", "b3", "b4", "c"] {
+                transaction.set(DBCol::BlockMisc, key.into(), "value".into());
+            }
+            db.write(transaction).unwrap();
+
+            let all = keys(db.iter_prefix(DBCol::BlockMisc, b"b"));
+            assert_eq!(all, ["b", "b1", "b2", "b2a", "b3", "b4"]);
+
+            // Resuming from the midpoint gives the rest with no overlap nor gap.
+            let first = &all[..3];
+            let rest = keys(db.iter_prefix_from(DBCol::BlockMisc, b"b", first[2].as_bytes()));
+            assert_eq!([first, rest.as_slice()].concat(), all);
+
+            // Start key which isn’t in the database.
+            let rest = keys(db.iter_prefix_from(DBCol::BlockMisc, b"b", b"b20"));
+            assert_eq!(rest, ["b2a", "b3", "b4"]);
+            // Start key before the prefix.
+            let rest = keys(db.iter_prefix_from(DBCol::BlockMisc, b"b", b"a"));
+            assert_eq!(rest, all);
+            // Start key at the end or past the prefix.
+            assert_eq!(db.iter_prefix_from(DBCol::BlockMisc, b"b", b"b4").count(), 0);
+            assert_eq!(db.iter_prefix_from(DBCol::BlockMisc, b"b", b"c").count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_rewrite_column() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(2).unwrap();
+            let mut transaction = DBTransaction::new();
+            for key in 0u8..10 {
+                transaction.set(DBCol::BlockMisc, vec![key], b"value".to_vec());
+                let value = refcount::add_positive_refcount(b"value", rc);
+                transaction.update_refcount(DBCol::State, vec![key], value);
+            }
+            db.write(transaction).unwrap();
+
+            // Identity leaves everything as it was.
+            for col in [DBCol::BlockMisc, DBCol::State] {
+                let before: Vec<_> = db.iter_raw_bytes(col).map(Result::unwrap).collect();
+                assert_eq!(db.rewrite_column(col, &|value| Ok(value.to_vec())).unwrap(), 10);
+                let after: Vec<_> = db.iter_raw_bytes(col).map(Result::unwrap).collect();
+                assert_eq!(before, after);
+            }
+
+            // Transformation applies to the value and preserves the refcount.
+            let upper = |value: &[u8]| -> std::io::Result<_> { Ok(value.to_ascii_uppercase()) };
+            assert_eq!(db.rewrite_column(DBCol::BlockMisc, &upper).unwrap(), 10);
+            assert_eq!(db.rewrite_column(DBCol::State, &upper).unwrap(), 10);
+            for key in 0u8..10 {
+                let value = db.get_raw_bytes(DBCol::BlockMisc, &[key]).unwrap();
+                assert_eq!(value.as_deref(), Some(&b"VALUE"[..]));
+                let value = db.get_raw_bytes(DBCol::State, &[key]).unwrap();
+                let want = refcount::add_positive_refcount(b"VALUE", rc);
+                assert_eq!(value.as_deref(), Some(want.as_slice()));
+            }
+
+            // Errors are propagated.
+            let fail =
+                |_: &[u8]| -> std::io::Result<Vec<u8>> { Err(std::io::Error::other("fail")) };
+            db.rewrite_column(DBCol::BlockMisc, &fail).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_empty_key() {
+        let keys = |iter: crate::db::DBIterator<'_>| -> Vec<Vec<u8>> {
+            iter.map(|item| item.unwrap().0.into_vec()).collect()
+        };
+        for db in test_and_rocksdb() {
+            let col = DBCol::BlockMisc;
+            let mut transaction = DBTransaction::new();
+            transaction.set(col, vec![], b"empty".to_vec());
+            transaction.set(col, b"a".to_vec(), b"a".to_vec());
+            db.write(transaction).unwrap();
+
+            let value = db.get_raw_bytes(col, b"").unwrap();
+            assert_eq!(value.as_deref(), Some(&b"empty"[..]));
+            assert_eq!(keys(db.iter(col)), [b"".to_vec(), b"a".to_vec()]);
+            assert_eq!(keys(db.iter_prefix(col, b"")), [b"".to_vec(), b"a".to_vec()]);
+
+            // Empty bounds.
+            assert_eq!(keys(db.iter_range(col, Some(b""), None)), [b"".to_vec(), b"a".to_vec()]);
+            assert_eq!(keys(db.iter_range(col, Some(b""), Some(b"a"))), [b"".to_vec()]);
+            assert!(keys(db.iter_range(col, None, Some(b""))).is_empty());
+            assert!(keys(db.iter_range(col, Some(b""), Some(b""))).is_empty());
+            assert!(keys(db.iter_range(col, Some(b"a"), Some(b""))).is_empty());
+
+            let mut transaction = DBTransaction::new();
+            transaction.delete_range(col, vec![], vec![]);
+            db.write(transaction).unwrap();
+            assert_eq!(keys(db.iter(col)), [b"".to_vec(), b"a".to_vec()]);
+
+            let mut transaction = DBTransaction::new();
+            transaction.delete(col, vec![]);
+            db.write(transaction).unwrap();
+            assert_eq!(db.get_raw_bytes(col, b"").unwrap(), None);
+            assert_eq!(keys(db.iter(col)), [b"a".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn test_key_range() {
+        for db in test_and_rocksdb() {
+            assert_eq!(db.key_range(DBCol::BlockMisc).unwrap(), None);
+            assert_eq!(db.key_range(DBCol::State).unwrap(), None);
+
+            let rc = NonZeroU32::new(1).unwrap();
+            let mut transaction = DBTransaction::new();
+            for key in ["b", "ba", "c", "d"] {
+                transaction.set(DBCol::BlockMisc, key.into(), "value".into());
+                let value = refcount::add_positive_refcount(b"value", rc);
+                transaction.update_refcount(DBCol::State, key.into(), value);
+            }
+            db.write(transaction).unwrap();
+            // Cells with negative refcount at either end are skipped.
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            for key in ["a", "e"] {
+                let value = refcount::encode_negative_refcount(rc);
+                transaction.set(DBCol::State, key.into(), value);
+            }
+            db.write(transaction).unwrap();
+
+            let want = Some((b"b"[..].into(), b"d"[..].into()));
+            assert_eq!(db.key_range(DBCol::BlockMisc).unwrap(), want);
+            assert_eq!(db.key_range(DBCol::State).unwrap(), want);
+
+            // Single key is both the minimum and the maximum.
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::Block, b"foo".to_vec(), b"bar".to_vec());
+            db.write(transaction).unwrap();
+            let want = Some((b"foo"[..].into(), b"foo"[..].into()));
+            assert_eq!(db.key_range(DBCol::Block).unwrap(), want);
+        }
+    }
+
+    #[test]
+    fn test_iter_range_rev() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(1).unwrap();
+            let mut transaction = DBTransaction::new();
+            for key in ["a", "aa", "b", "ba", "c", "d"] {
+                transaction.set(DBCol::BlockMisc, key.into(), key.into());
+                let value = refcount::add_positive_refcount(key.as_bytes(), rc);
+                transaction.update_refcount(DBCol::State, key.into(), value);
+            }
+            db.write(transaction).unwrap();
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            let value = refcount::encode_negative_refcount(rc);
+            transaction.set(DBCol::State, "bb".into(), value);
+            db.write(transaction).unwrap();
+
+            let bounds: [(Option<&[u8]>, Option<&[u8]>); 7] = [
+                (None, None),
+                (Some(b"aa"), None),
+                (None, Some(b"c")),
+                (Some(b"aa"), Some(b"c")),
+                (Some(b"b"), Some(b"b")),
+                (Some(b"c"), Some(b"b")),
+                (Some(b""), Some(b"ba")),
+            ];
+            for col in [DBCol::BlockMisc, DBCol::State] {
+                for (lower, upper) in bounds {
+                    let mut want = db
+                        .iter_range(col, lower, upper)
+                        .collect::<std::io::Result<Vec<_>>>()
+                        .unwrap();
+                    want.reverse();
+                    let got = db
+                        .iter_range_rev(col, lower, upper)
+                        .collect::<std::io::Result<Vec<_>>>()
+                        .unwrap();
+                    assert_eq!(want, got, "{col} {lower:?} {upper:?}");
+                }
+            }
+
+            let keys = db
+                .iter_range_rev(DBCol::BlockMisc, Some(b"aa"), Some(b"c"))
+                .map(|item| item.unwrap().0.into_vec())
+                .collect::<Vec<_>>();
+            assert_eq!(keys, [b"ba".to_vec(), b"b".to_vec(), b"aa".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn test_iter_cancellable() {
+        use crate::db::{CancellationToken, CANCELLATION_CHECK_INTERVAL};
+
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            for key in 0u32..5000 {
+                transaction.set(DBCol::BlockMisc, key.to_be_bytes().to_vec(), vec![]);
+            }
+            db.write(transaction).unwrap();
+
+            // Without cancelling, all items are returned.
+            let token = CancellationToken::new();
+            assert_eq!(db.iter_cancellable(DBCol::BlockMisc, token).count(), 5000);
+
+            // Trip the token mid-scan.
+            let token = CancellationToken::new();
+            let mut iter = db.iter_cancellable(DBCol::BlockMisc, token.clone());
+            let mut count = 0;
+            let err = loop {
+                match iter.next().unwrap() {
+                    Ok(_) => count += 1,
+                    Err(err) => break err,
+                }
+                if count == 1500 {
+                    token.cancel();
+                }
+            };
+            assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+            assert!(count < 1500 + CANCELLATION_CHECK_INTERVAL, "{count}");
+            assert!(iter.next().is_none());
+
+            // Already cancelled token stops the scan right away.
+            let mut iter = db.iter_cancellable(DBCol::BlockMisc, token);
+            assert!(iter.next().unwrap().is_err());
+            assert!(iter.next().is_none());
+        }
+    }
+
+    #[test]
+    fn test_sample_values() {
+        for db in test_and_rocksdb() {
+            assert_eq!(db.sample_values(DBCol::BlockMisc, 10).unwrap(), Vec::<Box<[u8]>>::new());
+
+            let mut transaction = DBTransaction::new();
+            for key in 0u32..1000 {
+                let key = key.to_be_bytes().to_vec();
+                transaction.set(DBCol::BlockMisc, key.clone(), key);
+            }
+            db.write(transaction).unwrap();
+
+            assert_eq!(db.sample_values(DBCol::BlockMisc, 0).unwrap().len(), 0);
+            let sample = db
+                .sample_values(DBCol::BlockMisc, 10)
+                .unwrap()
+                .into_iter()
+                .map(|value| u32::from_be_bytes((*value).try_into().unwrap()))
+                .collect::<Vec<_>>();
+            assert!((5..=10).contains(&sample.len()), "{sample:?}");
+            // The sample is sorted, unique and covers the key space.
+            assert!(sample.windows(2).all(|pair| pair[0] < pair[1]), "{sample:?}");
+            assert!(sample[0] < 100 && *sample.last().unwrap() >= 800, "{sample:?}");
+
+            // Asking for more than there is returns at most all the values.
+            assert!(db.sample_values(DBCol::BlockMisc, 5000).unwrap().len() <= 1000);
+        }
+    }
+
+    #[test]
+    fn test_name() {
+        use crate::db::{ColdDB, RocksDB, SplitDB};
+
+        let hot = TestDB::new();
+        assert_eq!(hot.name(), "testdb");
+        let cold = Arc::new(ColdDB::new(TestDB::new()));
+        assert_eq!(cold.name(), "colddb");
+        assert_eq!(SplitDB::new(hot, cold).name(), "splitdb");
+
+        let (_tmp_dir, opener) = NodeStorage::test_opener();
+        let store = opener.open().unwrap();
+        assert_eq!(store.into_inner(crate::Temperature::Hot).name(), "rocksdb");
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let config = crate::StoreConfig::test_config();
+        let db =
+            RocksDB::open(tmp_dir.path(), &config, crate::Mode::ReadWrite, crate::Temperature::Hot)
+                .unwrap()
+                .with_name("hot");
+        assert_eq!(db.name(), "hot");
+    }
+
+    #[test]
+    fn test_contains_keys() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(1).unwrap();
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, b"a".to_vec(), b"value".to_vec());
+            transaction.set(DBCol::BlockMisc, b"c".to_vec(), vec![]);
+            let value = refcount::add_positive_refcount(b"value", rc);
+            transaction.update_refcount(DBCol::State, b"a".to_vec(), value);
+            db.write(transaction).unwrap();
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            let value = [&b"value"[..], &0i64.to_le_bytes()].concat();
+            transaction.set(DBCol::State, b"b".to_vec(), value);
+            transaction.set(DBCol::State, b"c".to_vec(), refcount::encode_negative_refcount(rc));
+            db.write(transaction).unwrap();
+
+            let keys: [&[u8]; 4] = [b"a", b"b", b"c", b"d"];
+            let got = db.contains_keys(DBCol::BlockMisc, &keys).unwrap();
+            assert_eq!(got, [true, false, true, false]);
+            let got = db.contains_keys(DBCol::State, &keys).unwrap();
+            assert_eq!(got, [true, false, false, false]);
+            assert_eq!(db.contains_keys(DBCol::State, &[]).unwrap(), Vec::<bool>::new());
+        }
+    }
+
+    #[test]
+    fn test_write_with_id() {
+        for db in test_and_rocksdb() {
+            let mut prev = None;
+            for key in 0u8..10 {
+                let mut transaction = DBTransaction::new();
+                transaction.set(DBCol::BlockMisc, vec![key], vec![key]);
+                let id = db.write_with_id(transaction).unwrap();
+                assert!(prev < Some(id), "{prev:?} {id:?}");
+                prev = Some(id);
+
+                // Identifiers keep increasing with plain writes interleaved.
+                let mut transaction = DBTransaction::new();
+                transaction.delete(DBCol::BlockMisc, vec![key]);
+                db.write(transaction).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_error_is_fused() {
+        let db = TestDB::new();
+        let mut transaction = DBTransaction::new();
+        for key in [b"a", b"b", b"c"] {
+            transaction.set(DBCol::BlockMisc, key.to_vec(), key.to_vec());
+        }
+        db.write(transaction).unwrap();
+        db.fail_iteration_at(DBCol::BlockMisc, b"b");
+
+        let mut iter = db.iter(DBCol::BlockMisc);
+        assert_eq!(iter.next().unwrap().unwrap().0.as_ref(), b"a");
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+
+        let mut iter = db.iter_range_rev(DBCol::BlockMisc, None, None);
+        assert_eq!(iter.next().unwrap().unwrap().0.as_ref(), b"c");
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+
+        // The lenient iterator stops at the failure without reporting it.
+        let keys: Vec<_> = db.iter_lenient(DBCol::BlockMisc).map(|item| item.unwrap().0).collect();
+        assert_eq!(keys, [b"a".to_vec().into_boxed_slice()]);
+    }
+
+    #[test]
+    fn test_iter_lenient() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(1).unwrap();
+            let mut transaction = DBTransaction::new();
+            let value = refcount::add_positive_refcount(b"foo", rc);
+            transaction.update_refcount(DBCol::State, b"a".to_vec(), value);
+            db.write(transaction).unwrap();
+            // Too short to hold a reference count.
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            transaction.set(DBCol::State, b"b".to_vec(), b"bad".to_vec());
+            db.write(transaction).unwrap();
+            let mut transaction = DBTransaction::new();
+            let value = refcount::add_positive_refcount(b"bar", rc);
+            transaction.update_refcount(DBCol::State, b"c".to_vec(), value);
+            transaction.set(DBCol::BlockMisc, b"d".to_vec(), b"baz".to_vec());
+            db.write(transaction).unwrap();
+
+            let errors = db.refcount_decode_errors();
+            let got: Vec<_> = db.iter_lenient(DBCol::State).map(Result::unwrap).collect();
+            let want: Vec<(Box<[u8]>, Box<[u8]>)> = vec![
+                (b"a".to_vec().into(), b"foo".to_vec().into()),
+                (b"c".to_vec().into(), b"bar".to_vec().into()),
+            ];
+            assert_eq!(got, want);
+            assert!(db.refcount_decode_errors() > errors);
+
+            // Columns without reference counting are returned as is.
+            let got: Vec<_> = db.iter_lenient(DBCol::BlockMisc).map(Result::unwrap).collect();
+            let want: Vec<(Box<[u8]>, Box<[u8]>)> =
+                vec![(b"d".to_vec().into(), b"baz".to_vec().into())];
+            assert_eq!(got, want);
+        }
+    }
+
+    #[test]
+    fn test_diff_column() {
+        let dbs = test_and_rocksdb();
+        let (a, b) = (&dbs[0], &dbs[1]);
+        let write = |db: &Arc<dyn Database>, col, entries: &[(&[u8], &[u8])]| {
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            for (key, value) in entries {
+                transaction.set(col, key.to_vec(), value.to_vec());
+            }
+            db.write(transaction).unwrap();
+        };
+        let rc = NonZeroU32::new(1).unwrap();
+        let foo = refcount::add_positive_refcount(b"foo", rc);
+        let bar = refcount::add_positive_refcount(b"bar", rc);
+        for col in [DBCol::BlockMisc, DBCol::State] {
+            write(a, col, &[(b"a", &foo), (b"b", &foo), (b"c", &foo), (b"e", &foo)]);
+            write(b, col, &[(b"b", &foo), (b"c", &bar), (b"d", &bar), (b"f", &foo)]);
+
+            let patch = crate::db::diff_column(a.as_ref(), b.as_ref(), col).unwrap();
+            assert_eq!(patch.ops().len(), 5, "{:?}", patch.ops());
+            a.write(patch).unwrap();
+
+            let raw = |db: &Arc<dyn Database>| {
+                db.iter_raw_bytes(col).map(Result::unwrap).collect::<Vec<_>>()
+            };
+            assert_eq!(raw(a), raw(b));
+            assert!(crate::db::diff_column(a.as_ref(), b.as_ref(), col).unwrap().ops().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_write_reporting() {
+        use crate::db::KeyOutcome::{Created, Overwritten};
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, b"a".to_vec(), b"1".to_vec());
+            db.write(transaction).unwrap();
+
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, b"a".to_vec(), b"2".to_vec());
+            transaction.set(DBCol::BlockMisc, b"b".to_vec(), b"2".to_vec());
+            transaction.delete(DBCol::BlockMisc, b"c".to_vec());
+            transaction.set(DBCol::BlockMisc, b"b".to_vec(), b"3".to_vec());
+            transaction.insert(DBCol::Block, b"c".to_vec(), b"3".to_vec());
+            let outcomes = db.write_reporting(transaction).unwrap();
+            assert_eq!(outcomes, [Overwritten, Created, Overwritten, Created]);
+            assert_eq!(
+                db.get_raw_bytes(DBCol::BlockMisc, b"b").unwrap().as_deref(),
+                Some(&b"3"[..])
+            );
+
+            let mut transaction = DBTransaction::new();
+            transaction.delete(DBCol::BlockMisc, b"a".to_vec());
+            transaction.set(DBCol::BlockMisc, b"a".to_vec(), b"4".to_vec());
+            transaction.delete_all(DBCol::Block);
+            transaction.insert(DBCol::Block, b"c".to_vec(), b"3".to_vec());
+            let outcomes = db.write_reporting(transaction).unwrap();
+            assert_eq!(outcomes, [Created, Created]);
+        }
+    }
+
+    #[test]
+    fn test_get_with_rc() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(2).unwrap();
+            let mut transaction = DBTransaction::new();
+            let value = refcount::add_positive_refcount(b"value", rc);
+            transaction.update_refcount(DBCol::State, b"positive".to_vec(), value.clone());
+            transaction.update_refcount(DBCol::State, b"zero".to_vec(), value);
+            let value = refcount::encode_negative_refcount(rc);
+            transaction.update_refcount(DBCol::State, b"zero".to_vec(), value.clone());
+            db.write(transaction).unwrap();
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            transaction.set(DBCol::State, b"negative".to_vec(), value);
+            db.write(transaction).unwrap();
+
+            let (value, rc) = db.get_with_rc(DBCol::State, b"positive").unwrap().unwrap();
+            assert_eq!((value.as_slice(), rc), (&b"value"[..], 2));
+            assert_eq!(db.get_refcount(DBCol::State, b"positive").unwrap(), 2);
+
+            assert!(db.get_with_rc(DBCol::State, b"zero").unwrap().is_none());
+            assert_eq!(db.get_refcount(DBCol::State, b"zero").unwrap(), 0);
+
+            assert!(db.get_with_rc(DBCol::State, b"negative").unwrap().is_none());
+            assert_eq!(db.get_refcount(DBCol::State, b"negative").unwrap(), -2);
+
+            assert!(db.get_with_rc(DBCol::State, b"missing").unwrap().is_none());
+            assert_eq!(db.get_refcount(DBCol::State, b"missing").unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_force_delete() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(2).unwrap();
+            let mut transaction = DBTransaction::new();
+            let value = refcount::add_positive_refcount(b"value", rc);
+            transaction.update_refcount(DBCol::State, b"key".to_vec(), value);
+            db.write(transaction).unwrap();
+
+            let mut capture = near_o11y::testonly::TracingCapture::enable_with_events();
+            db.force_delete(DBCol::State, b"key").unwrap();
+            let logs = capture.drain();
+            let log = logs.iter().find(|log| log.contains("Force deleting")).expect("no log");
+            assert!(log.starts_with("WARN"), "{log}");
+            assert!(log.contains("key='key'") && log.contains("refcount=Some(2)"), "{log}");
+
+            assert_eq!(db.get_raw_bytes(DBCol::State, b"key").unwrap().as_deref(), None);
+            assert_eq!(db.get_refcount(DBCol::State, b"key").unwrap(), 0);
+            assert_eq!(db.iter(DBCol::State).count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_prefetch_range() {
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            for key in 0u8..10 {
+                transaction.set(DBCol::BlockMisc, vec![key], vec![key; 100]);
+            }
+            db.write(transaction).unwrap();
+            db.flush().unwrap();
+
+            db.prefetch_range(DBCol::BlockMisc, &[2], &[5]).unwrap();
+            let entries = db.iter_range(DBCol::BlockMisc, Some(&[2]), Some(&[5]));
+            let got = entries.map(Result::unwrap).collect::<Vec<_>>();
+            let want: Vec<(Box<[u8]>, Box<[u8]>)> =
+                (2u8..5).map(|key| (vec![key].into(), vec![key; 100].into())).collect();
+            assert_eq!(got, want);
+            // Empty and inverted ranges are fine.
+            db.prefetch_range(DBCol::BlockMisc, &[5], &[5]).unwrap();
+            db.prefetch_range(DBCol::BlockMisc, &[7], &[3]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_move_key() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(3).unwrap();
+            let rc_value = refcount::add_positive_refcount(b"node", rc);
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+            transaction.update_refcount(DBCol::State, b"key".to_vec(), rc_value.clone());
+            db.write(transaction).unwrap();
+
+            assert!(db.move_key(DBCol::BlockMisc, DBCol::ChunkExtra, b"key").unwrap());
+            assert_eq!(db.get_raw_bytes(DBCol::BlockMisc, b"key").unwrap().as_deref(), None);
+            let moved = db.get_raw_bytes(DBCol::ChunkExtra, b"key").unwrap();
+            assert_eq!(moved.as_deref(), Some(&b"value"[..]));
+
+            // Reference count is moved along with the value.
+            assert!(db.move_key(DBCol::State, DBCol::Transactions, b"key").unwrap());
+            assert_eq!(db.get_refcount(DBCol::State, b"key").unwrap(), 0);
+            let moved = db.get_raw_bytes(DBCol::Transactions, b"key").unwrap();
+            assert_eq!(moved.as_deref(), Some(rc_value.as_slice()));
+
+            assert!(!db.move_key(DBCol::BlockMisc, DBCol::ChunkExtra, b"missing").unwrap());
+            assert!(!db.move_key(DBCol::State, DBCol::Transactions, b"missing").unwrap());
+            assert_eq!(db.get_raw_bytes(DBCol::ChunkExtra, b"missing").unwrap().as_deref(), None);
+
+            let err = db.move_key(DBCol::BlockMisc, DBCol::State, b"key").unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_iter_refcounts() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(2).unwrap();
+            let mut transaction = DBTransaction::new();
+            let value = refcount::add_positive_refcount(b"value", rc);
+            transaction.update_refcount(DBCol::State, b"a".to_vec(), value.clone());
+            transaction.update_refcount(DBCol::State, b"a".to_vec(), value);
+            db.write(transaction).unwrap();
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            transaction.set(DBCol::State, b"b".to_vec(), 0i64.to_le_bytes().to_vec());
+            transaction.set(DBCol::State, b"c".to_vec(), refcount::encode_negative_refcount(rc));
+            db.write(transaction).unwrap();
+
+            let got = db.iter_refcounts(DBCol::State).map(Result::unwrap).collect::<Vec<_>>();
+            let want: Vec<(Box<[u8]>, i64)> =
+                vec![(b"a"[..].into(), 4), (b"b"[..].into(), 0), (b"c"[..].into(), -2)];
+            assert_eq!(got, want);
+            // Plain iteration skips non-positive reference counts.
+            assert_eq!(db.iter(DBCol::State).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_rc_iter_consistency() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(2).unwrap();
+            for col in [DBCol::State, DBCol::Transactions] {
+                let mut transaction = DBTransaction::new();
+                for key in 0u8..10 {
+                    let value = refcount::add_positive_refcount(&[key; 3], rc);
+                    transaction.update_refcount(col, vec![key], value);
+                }
+                db.write(transaction).unwrap();
+                // Zero and negative reference counts.
+                let mut transaction = DBTransaction::new();
+                transaction.allow_raw_rc_writes();
+                transaction.set(col, vec![20], 0i64.to_le_bytes().to_vec());
+                transaction.set(col, vec![21], refcount::encode_negative_refcount(rc));
+                db.write(transaction).unwrap();
+                let mut transaction = DBTransaction::new();
+                transaction.update_refcount(col, vec![3], refcount::encode_negative_refcount(rc));
+                let one = NonZeroU32::new(1).unwrap();
+                transaction.update_refcount(col, vec![4], refcount::encode_negative_refcount(one));
+                db.write(transaction).unwrap();
+
+                assert_eq!(verify_rc_iter_consistency(db.as_ref(), col), Vec::<String>::new());
+                assert_eq!(db.iter(col).count(), 9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dump_column() {
+        let dump = |db: &dyn Database, col, limit| {
+            let mut out = Vec::new();
+            db.dump_column(col, limit, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, b"a".to_vec(), b"foo".to_vec());
+            transaction.set(DBCol::BlockMisc, b"b".to_vec(), vec![0, 1, 2]);
+            transaction.set(DBCol::BlockMisc, b"c".to_vec(), b"baz".to_vec());
+            let rc = NonZeroU32::new(2).unwrap();
+            let value = refcount::add_positive_refcount(b"node", rc);
+            transaction.update_refcount(DBCol::State, b"d".to_vec(), value);
+            db.write(transaction).unwrap();
+
+            let want = "BlockMisc:\n  'a' => 'foo'\n  'b' => AAEC\n  'c' => 'baz'\n";
+            assert_eq!(dump(db.as_ref(), DBCol::BlockMisc, 10), want);
+            let want = "BlockMisc:\n  'a' => 'foo'\n  …\n";
+            assert_eq!(dump(db.as_ref(), DBCol::BlockMisc, 1), want);
+            let want = "State:\n  'd' => 'node' (refcount 2)\n";
+            assert_eq!(dump(db.as_ref(), DBCol::State, 10), want);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_check_rc_ops() {
+        let rc = NonZeroU32::new(1).unwrap();
+        let value = refcount::add_positive_refcount(b"node", rc);
+        for db in test_and_rocksdb() {
+            // A misdirected set is rejected even without any reference count
+            // updates in the batch.
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, b"c".to_vec(), b"value".to_vec());
+            transaction.set(DBCol::State, b"b".to_vec(), value.clone());
+            let err = db.write(transaction).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert_eq!(
+                err.to_string(),
+                "batch sets 'b' in reference counted column State bypassing reference counting"
+            );
+            assert_eq!(db.get_raw_bytes(DBCol::State, b"b").unwrap(), None);
+            assert_eq!(db.get_raw_bytes(DBCol::BlockMisc, b"c").unwrap(), None);
+
+            // Reference count updates and plain writes to other columns are
+            // fine.
+            let mut transaction = DBTransaction::new();
+            transaction.update_refcount(DBCol::State, b"a".to_vec(), value.clone());
+            transaction.update_refcount(DBCol::State, b"b".to_vec(), value.clone());
+            transaction.set(DBCol::BlockMisc, b"c".to_vec(), b"value".to_vec());
+            db.write(transaction).unwrap();
+
+            // So are raw writes once explicitly allowed.
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            transaction.set(DBCol::State, b"a".to_vec(), value.clone());
+            db.write(transaction).unwrap();
+            assert_eq!(db.get_refcount(DBCol::State, b"a").unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn test_iter_from() {
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            for key in [&b"a"[..], b"b", b"b\0", b"b\0\0", b"c"] {
+                transaction.set(DBCol::BlockMisc, key.to_vec(), b"value".to_vec());
+            }
+            db.write(transaction).unwrap();
+            let keys = |iter: crate::db::DBIterator| {
+                iter.map(|item| item.unwrap().0.into_vec()).collect::<Vec<_>>()
+            };
+            let want = |keys: &[&[u8]]| keys.iter().map(|key| key.to_vec()).collect::<Vec<_>>();
+
+            let got = keys(db.iter_from(DBCol::BlockMisc, b"b"));
+            assert_eq!(got, want(&[b"b", b"b\0", b"b\0\0", b"c"]));
+            let got = keys(db.iter_from_exclusive(DBCol::BlockMisc, b"b"));
+            assert_eq!(got, want(&[b"b\0", b"b\0\0", b"c"]));
+            let got = keys(db.iter_from_exclusive(DBCol::BlockMisc, b"b\0"));
+            assert_eq!(got, want(&[b"b\0\0", b"c"]));
+            // Keys which don’t exist bound the iteration the same way.
+            let got = keys(db.iter_from(DBCol::BlockMisc, b"bb"));
+            assert_eq!(got, want(&[b"c"]));
+            let got = keys(db.iter_from_exclusive(DBCol::BlockMisc, b"bb"));
+            assert_eq!(got, want(&[b"c"]));
+            assert_eq!(keys(db.iter_from(DBCol::BlockMisc, b"")).len(), 5);
+            assert_eq!(keys(db.iter_from_exclusive(DBCol::BlockMisc, b"")).len(), 5);
+            assert!(keys(db.iter_from_exclusive(DBCol::BlockMisc, b"c")).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_count_distinct_prefixes() {
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            // Keys are (hash, index) pairs with a varying number of indices
+            // per hash, plus a few short keys.
+            for hash in [[0u8; 4], [1; 4], [1, 1, 1, 2], [0xff; 4]] {
+                for index in 0..u16::from(hash[3]) + 1 {
+                    let key = [&hash[..], &index.to_be_bytes()].concat();
+                    transaction.set(DBCol::BlockMisc, key, b"value".to_vec());
+                }
+            }
+            for key in [&b"\x01"[..], b"\x01\x01\x01\x01"] {
+                transaction.set(DBCol::BlockMisc, key.to_vec(), b"value".to_vec());
+            }
+            // Prefixes with only dead reference counted cells aren’t counted.
+            let rc = NonZeroU32::new(1).unwrap();
+            let value = refcount::add_positive_refcount(b"node", rc);
+            for key in [b"aa1", b"aa2", b"bb1", b"cc1"] {
+                transaction.update_refcount(DBCol::State, key.to_vec(), value.clone());
+            }
+            db.write(transaction).unwrap();
+            let mut transaction = DBTransaction::new();
+            for key in [b"aa1", b"bb1"] {
+                let value = refcount::encode_negative_refcount(rc);
+                transaction.update_refcount(DBCol::State, key.to_vec(), value);
+            }
+            db.write(transaction).unwrap();
+
+            let count = |col, prefix_len| db.count_distinct_prefixes(col, prefix_len).unwrap();
+            assert_eq!(count(DBCol::BlockMisc, 4), 5);
+            assert_eq!(count(DBCol::BlockMisc, 3), 4);
+            assert_eq!(count(DBCol::BlockMisc, 1), 3);
+            assert_eq!(count(DBCol::BlockMisc, 0), 1);
+            assert_eq!(count(DBCol::BlockMisc, 6), 1 + 2 + 3 + 256 + 2);
+            assert_eq!(count(DBCol::State, 2), 2);
+            assert_eq!(count(DBCol::State, 3), 2);
+            assert_eq!(count(DBCol::Block, 4), 0);
+        }
+    }
+
+    #[test]
+    fn test_get_sorted() {
+        for db in test_and_rocksdb() {
+            let mut transaction = DBTransaction::new();
+            // Two clusters of adjacent keys with a large gap in between.
+            for key in (0u16..50).chain(1000..1050) {
+                transaction.set(DBCol::BlockMisc, key.to_be_bytes().to_vec(), vec![key as u8]);
+            }
+            let rc = NonZeroU32::new(1).unwrap();
+            let value = refcount::add_positive_refcount(b"node", rc);
+            transaction.update_refcount(DBCol::State, b"a".to_vec(), value);
+            db.write(transaction).unwrap();
+
+            let keys = [0u16, 1, 1, 3, 20, 49, 50, 51, 999, 1000, 1002, 1049, 1050, u16::MAX]
+                .map(u16::to_be_bytes);
+            let keys = keys.iter().map(|key| &key[..]).collect::<Vec<_>>();
+            let got = db.get_sorted(DBCol::BlockMisc, &keys).unwrap();
+            let want = keys
+                .iter()
+                .map(|key| db.get_raw_bytes(DBCol::BlockMisc, key).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(got, want);
+            assert_eq!(got.iter().filter(|value| value.is_some()).count(), 9);
+            assert!(db.get_sorted(DBCol::BlockMisc, &[]).unwrap().is_empty());
+
+            // Values in reference counted columns are returned raw.
+            let got = db.get_sorted(DBCol::State, &[&b"a"[..], b"b"]).unwrap();
+            assert_eq!(got, [db.get_raw_bytes(DBCol::State, b"a").unwrap(), None]);
+
+            let err = db.get_sorted(DBCol::BlockMisc, &[&b"b"[..], b"a"]).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert_eq!(err.to_string(), "keys aren’t sorted: 'a' comes after 'b'");
+        }
+    }
+
+    #[test]
+    fn test_column_digest() {
+        let dbs = test_and_rocksdb();
+        let (a, b) = (&dbs[0], &dbs[1]);
+        let rc = NonZeroU32::new(1).unwrap();
+        let empty = a.column_digest(DBCol::BlockMisc).unwrap();
+        assert_eq!(empty, b.column_digest(DBCol::BlockMisc).unwrap());
+
+        // The same contents written in different order and batches.
+        let mut transaction = DBTransaction::new();
+        for key in [b"a", b"b", b"c"] {
+            transaction.set(DBCol::BlockMisc, key.to_vec(), b"value".to_vec());
+            let value = refcount::add_positive_refcount(b"node", rc);
+            transaction.update_refcount(DBCol::State, key.to_vec(), value);
+        }
+        a.write(transaction).unwrap();
+        for key in [b"c", b"a", b"b"] {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, key.to_vec(), b"value".to_vec());
+            // Reference counts aren’t part of the digest.
+            let value = refcount::add_positive_refcount(b"node", rc.saturating_add(1));
+            transaction.update_refcount(DBCol::State, key.to_vec(), value);
+            b.write(transaction).unwrap();
+        }
+        b.flush().unwrap();
+        for col in [DBCol::BlockMisc, DBCol::State] {
+            assert_eq!(a.column_digest(col).unwrap(), b.column_digest(col).unwrap(), "{col}");
+        }
+        let digest = a.column_digest(DBCol::BlockMisc).unwrap();
+        assert_ne!(digest, empty);
+
+        // Differing value, missing key and shifted key-value boundary.
+        let change = |db: &Arc<dyn Database>, key: &[u8], value: Option<&[u8]>| {
+            let mut transaction = DBTransaction::new();
+            match value {
+                Some(value) => transaction.set(DBCol::BlockMisc, key.to_vec(), value.to_vec()),
+                None => transaction.delete(DBCol::BlockMisc, key.to_vec()),
+            }
+            db.write(transaction).unwrap();
+        };
+        change(b, b"b", Some(b"other"));
+        assert_ne!(b.column_digest(DBCol::BlockMisc).unwrap(), digest);
+        change(b, b"b", None);
+        assert_ne!(b.column_digest(DBCol::BlockMisc).unwrap(), digest);
+        change(b, b"b", Some(b"value"));
+        assert_eq!(b.column_digest(DBCol::BlockMisc).unwrap(), digest);
+        change(b, b"c", None);
+        change(b, b"cv", Some(b"alue"));
+        assert_ne!(b.column_digest(DBCol::BlockMisc).unwrap(), digest);
+
+        // Keys with non-positive reference counts are skipped.
+        let mut transaction = DBTransaction::new();
+        transaction.update_refcount(
+            DBCol::State,
+            b"a".to_vec(),
+            refcount::encode_negative_refcount(rc),
+        );
+        a.write(transaction).unwrap();
+        assert_ne!(a.column_digest(DBCol::State).unwrap(), b.column_digest(DBCol::State).unwrap());
+    }
+
+    #[test]
+    fn test_write_if_unchanged() {
+        for db in test_and_rocksdb() {
+            let set = |key: &[u8], value: &[u8]| {
+                let mut transaction = DBTransaction::new();
+                transaction.set(DBCol::BlockMisc, key.to_vec(), value.to_vec());
+                transaction
+            };
+            let get = |key: &[u8]| {
+                db.get_raw_bytes(DBCol::BlockMisc, key).unwrap().map(|value| value.to_vec())
+            };
+            db.write(set(b"a", b"1")).unwrap();
+
+            let witnesses = [
+                (DBCol::BlockMisc, b"a".to_vec(), Some(b"1".to_vec())),
+                (DBCol::BlockMisc, b"b".to_vec(), None),
+            ];
+            assert!(db.write_if_unchanged(set(b"c", b"1"), &witnesses).unwrap());
+            assert_eq!(get(b"c").as_deref(), Some(&b"1"[..]));
+
+            // Any witness not holding its expected value prevents the write.
+            db.write(set(b"b", b"2")).unwrap();
+            assert!(!db.write_if_unchanged(set(b"c", b"2"), &witnesses).unwrap());
+            let witnesses = [(DBCol::BlockMisc, b"a".to_vec(), Some(b"2".to_vec()))];
+            assert!(!db.write_if_unchanged(set(b"c", b"2"), &witnesses).unwrap());
+            assert_eq!(get(b"c").as_deref(), Some(&b"1"[..]));
+        }
+    }
+
+    #[test]
+    fn test_write_if_unchanged_concurrent() {
+        const THREADS: u64 = 8;
+        const UPDATES: u64 = 50;
+        for db in test_and_rocksdb() {
+            std::thread::scope(|scope| {
+                for _ in 0..THREADS {
+                    scope.spawn(|| {
+                        for _ in 0..UPDATES {
+                            // Optimistically increment two counters which must
+                            // stay equal, retrying on conflict.
+                            loop {
+                                let value = db.get_raw_bytes(DBCol::BlockMisc, b"x").unwrap();
+                                let value = value.map(|value| value.to_vec());
+                                let count = value.as_deref().map_or(0, |value| {
+                                    u64::from_le_bytes(value.try_into().unwrap())
+                                });
+                                let next = (count + 1).to_le_bytes().to_vec();
+                                let mut transaction = DBTransaction::new();
+                                transaction.set(DBCol::BlockMisc, b"x".to_vec(), next.clone());
+                                transaction.set(DBCol::BlockMisc, b"y".to_vec(), next);
+                                let witnesses = [
+                                    (DBCol::BlockMisc, b"x".to_vec(), value.clone()),
+                                    (DBCol::BlockMisc, b"y".to_vec(), value),
+                                ];
+                                if db.write_if_unchanged(transaction, &witnesses).unwrap() {
+                                    break;
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+            for key in [b"x", b"y"] {
+                let value = db.get_raw_bytes(DBCol::BlockMisc, key).unwrap().unwrap();
+                assert_eq!(u64::from_le_bytes((*value).try_into().unwrap()), THREADS * UPDATES);
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_keys_and_small_values() {
+        for db in test_and_rocksdb() {
+            let rc = NonZeroU32::new(1).unwrap();
+            let mut transaction = DBTransaction::new();
+            for (key, len) in [(b"a", 0), (b"b", 10), (b"c", 11), (b"d", 1000)] {
+                transaction.set(DBCol::BlockMisc, key.to_vec(), vec![1; len]);
+                let value = refcount::add_positive_refcount(&vec![2; len], rc);
+                transaction.update_refcount(DBCol::State, key.to_vec(), value);
+            }
+            db.write(transaction).unwrap();
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            // Cell with negative refcount is skipped.
+            transaction.set(DBCol::State, b"e".to_vec(), refcount::encode_negative_refcount(rc));
+            db.write(transaction).unwrap();
+
+            for (col, byte) in [(DBCol::BlockMisc, 1u8), (DBCol::State, 2)] {
+                let got = db
+                    .iter_keys_and_small_values(col, 10)
+                    .map(Result::unwrap)
+                    .map(|(key, value, len)| (key.into_vec(), value.map(<[u8]>::into_vec), len))
+                    .collect::<Vec<_>>();
+                let want = vec![
+                    (b"a".to_vec(), Some(vec![]), 0),
+                    (b"b".to_vec(), Some(vec![byte; 10]), 10),
+                    (b"c".to_vec(), None, 11),
+                    (b"d".to_vec(), None, 1000),
+                ];
+                assert_eq!(got, want, "{col}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_versions() {
+        for db in test_and_rocksdb() {
+            // Versions of a logical key are suffixed with a version byte.
+            let versioned = [&b"key"[..], b"key\x00", b"key\x01", b"key\x02"];
+            let mut transaction = DBTransaction::new();
+            for key in versioned.iter().chain(&[&b"kex"[..], b"kez"]) {
+                transaction.set(DBCol::BlockMisc, key.to_vec(), [&b"v-"[..], *key].concat());
+            }
+            db.write(transaction).unwrap();
+
+            let got = db
+                .get_versions(DBCol::BlockMisc, b"key")
+                .unwrap()
+                .into_iter()
+                .map(|(key, value)| (key.into_vec(), value.to_vec()))
+                .collect::<Vec<_>>();
+            let want = versioned.iter().map(|key| (key.to_vec(), [&b"v-"[..], *key].concat()));
+            assert_eq!(got, want.collect::<Vec<_>>());
+            assert_eq!(db.get_versions(DBCol::BlockMisc, b"key\x01").unwrap().len(), 1);
+            assert!(db.get_versions(DBCol::BlockMisc, b"missing").unwrap().is_empty());
+        }
+    }
 }
@@ -0,0 +1,395 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::db::{DBIterator, DBOp, DBSlice, DBTransaction, Database, StoreStatistics, WriteId};
+use crate::DBCol;
+
+/// A database wrapper which records reads and writes in a journal file.
+///
+/// Each [`Database::write`] and each point read is appended to the journal
+/// as a length-prefixed, borsh-encoded record.  The journal can later be
+/// replayed into another database with [`replay`] which is useful for
+/// reproducing bugs deterministically.  Reads are recorded for analysing
+/// access traces only and iterators aren’t recorded at all.
+///
+/// Writes are recorded before they’re applied while holding a lock until
+/// they finish, so the journal lists them in the order they were applied.
+/// If a write fails, its record is removed from the journal again.  Reads
+/// are recorded only once they succeed.  Records are written unbuffered so
+/// that the journal is complete even if the process crashes, though the
+/// last write may then be recorded without having been applied.  This makes
+/// every access noticeably slower.
+pub struct JournalingDatabase {
+    db: Arc<dyn Database>,
+    journal: Mutex<std::fs::File>,
+}
+
+/// A record in the journal.  Columns are identified by name so that the
+/// journal doesn’t depend on order of [`DBCol`] variants.
+#[derive(BorshSerialize, BorshDeserialize)]
+enum JournalRecord {
+    Get { col: String, key: Vec<u8>, found: bool },
+    Write { ops: Vec<JournalOp> },
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+enum JournalOp {
+    Set { col: String, key: Vec<u8>, value: Vec<u8> },
+    Insert { col: String, key: Vec<u8>, value: Vec<u8> },
+    UpdateRefcount { col: String, key: Vec<u8>, value: Vec<u8> },
+    Delete { col: String, key: Vec<u8> },
+    DeleteAll { col: String },
+    DeleteRange { col: String, from: Vec<u8>, to: Vec<u8> },
+}
+
+impl JournalOp {
+    fn new(op: &DBOp) -> Self {
+        let name = |col: &DBCol| <&str>::from(*col).to_string();
+        match op {
+            DBOp::Set { col, key, value } => {
+                Self::Set { col: name(col), key: key.clone(), value: value.clone() }
+            }
+            DBOp::Insert { col, key, value } => {
+                Self::Insert { col: name(col), key: key.clone(), value: value.clone() }
+            }
+            DBOp::UpdateRefcount { col, key, value } => {
+                Self::UpdateRefcount { col: name(col), key: key.clone(), value: value.clone() }
+            }
+            DBOp::Delete { col, key } => Self::Delete { col: name(col), key: key.clone() },
+            DBOp::DeleteAll { col } => Self::DeleteAll { col: name(col) },
+            DBOp::DeleteRange { col, from, to } => {
+                Self::DeleteRange { col: name(col), from: from.clone(), to: to.clone() }
+            }
+        }
+    }
+
+    fn add_to(self, transaction: &mut DBTransaction) -> io::Result<()> {
+        match self {
+            Self::Set { col, key, value } => transaction.set(parse_col(&col)?, key, value),
+            Self::Insert { col, key, value } => transaction.insert(parse_col(&col)?, key, value),
+            Self::UpdateRefcount { col, key, value } => {
+                transaction.update_refcount(parse_col(&col)?, key, value)
+            }
+            Self::Delete { col, key } => transaction.delete(parse_col(&col)?, key),
+            Self::DeleteAll { col } => transaction.delete_all(parse_col(&col)?),
+            Self::DeleteRange { col, from, to } => {
+                transaction.delete_range(parse_col(&col)?, from, to)
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends length-prefixed record to the journal.
+fn append(journal: &mut std::fs::File, record: &JournalRecord) -> io::Result<()> {
+    let payload = borsh::to_vec(record)?;
+    let len = u32::try_from(payload.len()).map_err(io::Error::other)?;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(&payload);
+    journal.write_all(&frame)
+}
+
+/// Truncates the journal to `len` bytes removing the last records.
+fn truncate(journal: &mut std::fs::File, len: u64) {
+    if let Err(err) = journal.set_len(len) {
+        tracing::warn!(
+            target: "store",
+            %err,
+            "Failed to remove write which wasn’t applied from the journal; replay will apply it"
+        );
+    }
+}
+
+fn parse_col(name: &str) -> io::Result<DBCol> {
+    use strum::IntoEnumIterator;
+    DBCol::iter().find(|col| <&str>::from(*col) == name).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("unknown column {name} in journal"))
+    })
+}
+
+impl JournalingDatabase {
+    /// Wraps given database appending records to the journal at given path.
+    /// The file is created if it doesn’t exist.
+    pub fn new(db: Arc<dyn Database>, journal_path: &Path) -> io::Result<Self> {
+        let journal = std::fs::OpenOptions::new().create(true).append(true).open(journal_path)?;
+        Ok(Self { db, journal: Mutex::new(journal) })
+    }
+
+    fn record(&self, record: &JournalRecord) -> io::Result<()> {
+        append(&mut self.journal.lock().unwrap(), record)
+    }
+
+    /// Records the batch and then writes it with `write`, holding the
+    /// journal lock throughout.  The record is removed if the write fails.
+    fn journaled_write<T>(
+        &self,
+        batch: DBTransaction,
+        write: impl FnOnce(&dyn Database, DBTransaction) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut journal = self.journal.lock().unwrap();
+        let len = journal.metadata()?.len();
+        append(&mut journal, &Self::write_record(&batch))?;
+        let result = write(self.db.as_ref(), batch);
+        if result.is_err() {
+            truncate(&mut journal, len);
+        }
+        result
+    }
+
+    fn write_record(batch: &DBTransaction) -> JournalRecord {
+        JournalRecord::Write { ops: batch.ops.iter().map(JournalOp::new).collect() }
+    }
+}
+
+/// Applies writes recorded in the journal at given path to `target`.
+///
+/// Writes are applied in the order they were recorded, each as a single
+/// transaction, while recorded reads are skipped.  Returns number of applied
+/// writes.  Fails if the journal is malformed, e.g. if the last record was
+/// cut short by a crash, in which case writes recorded before it have
+/// already been applied.
+pub fn replay(journal_path: &Path, target: &dyn Database) -> io::Result<usize> {
+    let mut reader = io::BufReader::new(std::fs::File::open(journal_path)?);
+    let mut writes = 0;
+    loop {
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+        let mut payload = vec![0; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut payload)?;
+        match JournalRecord::try_from_slice(&payload)? {
+            JournalRecord::Get { .. } => {}
+            JournalRecord::Write { ops } => {
+                let mut transaction = DBTransaction::new();
+                // The writes have been accepted by the journaled database so
+                // raw values in reference counted columns were intended.
+                transaction.allow_raw_rc_writes();
+                for op in ops {
+                    op.add_to(&mut transaction)?;
+                }
+                target.write(transaction)?;
+                writes += 1;
+            }
+        }
+    }
+    Ok(writes)
+}
+
+impl Database for JournalingDatabase {
+    fn name(&self) -> &str {
+        self.db.name()
+    }
+
+    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        let value = self.db.get_raw_bytes(col, key)?;
+        let col = <&str>::from(col).to_string();
+        self.record(&JournalRecord::Get { col, key: key.to_vec(), found: value.is_some() })?;
+        Ok(value)
+    }
+
+    fn get_with_rc_stripped(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        let value = self.db.get_with_rc_stripped(col, key)?;
+        let col = <&str>::from(col).to_string();
+        self.record(&JournalRecord::Get { col, key: key.to_vec(), found: value.is_some() })?;
+        Ok(value)
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        self.db.iter(col)
+    }
+
+    fn iter_prefix<'a>(&'a self, col: DBCol, key_prefix: &'a [u8]) -> DBIterator<'a> {
+        self.db.iter_prefix(col, key_prefix)
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        self.db.iter_range(col, lower_bound, upper_bound)
+    }
+
+    fn iter_raw_bytes<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        self.db.iter_raw_bytes(col)
+    }
+
+    fn write(&self, batch: DBTransaction) -> io::Result<()> {
+        self.journaled_write(batch, |db, batch| db.write(batch))
+    }
+
+    fn write_with_id(&self, batch: DBTransaction) -> io::Result<WriteId> {
+        self.journaled_write(batch, |db, batch| db.write_with_id(batch))
+    }
+
+    fn write_atomic(&self, batch: DBTransaction) -> io::Result<()> {
+        self.journaled_write(batch, |db, batch| db.write_atomic(batch))
+    }
+
+    /// Forwarded to the inner database so that it’s as atomic as there.  The
+    /// resulting write is recorded only after being applied since it isn’t
+    /// known before; the journal lock is held throughout so the order of
+    /// writes is still preserved.
+    fn update(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        let mut journal = self.journal.lock().unwrap();
+        match crate::db::update_returning_write(self.db.as_ref(), col, key, f)? {
+            Some(transaction) => append(&mut journal, &Self::write_record(&transaction)),
+            None => Ok(()),
+        }
+    }
+
+    /// Forwarded to the inner database so that it’s as atomic as there.  The
+    /// record is removed from the journal if the batch isn’t written.
+    fn write_if_unchanged(
+        &self,
+        batch: DBTransaction,
+        witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> io::Result<bool> {
+        let mut journal = self.journal.lock().unwrap();
+        let len = journal.metadata()?.len();
+        append(&mut journal, &Self::write_record(&batch))?;
+        let result = self.db.write_if_unchanged(batch, witnesses);
+        if !matches!(result, Ok(true)) {
+            truncate(&mut journal, len);
+        }
+        result
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.db.flush()
+    }
+
+    fn flush_column(&self, col: DBCol) -> io::Result<()> {
+        self.db.flush_column(col)
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        self.db.compact()
+    }
+
+    fn total_disk_size(&self) -> io::Result<u64> {
+        self.db.total_disk_size()
+    }
+
+    fn wal_sequence_range(&self) -> io::Result<Option<(u64, u64)>> {
+        self.db.wal_sequence_range()
+    }
+
+    fn get_store_statistics(&self) -> Option<StoreStatistics> {
+        self.db.get_store_statistics()
+    }
+
+    fn create_checkpoint(
+        &self,
+        path: &Path,
+        columns_to_keep: Option<&[DBCol]>,
+    ) -> anyhow::Result<()> {
+        self.db.create_checkpoint(path, columns_to_keep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{refcount, TestDB};
+
+    #[test]
+    fn test_journal_replay() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let journal_path = tmp_dir.path().join("journal");
+        let db = JournalingDatabase::new(TestDB::new(), &journal_path).unwrap();
+
+        let rc = std::num::NonZeroU32::new(2).unwrap();
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"a".to_vec(), b"1".to_vec());
+        transaction.set(DBCol::BlockMisc, b"b".to_vec(), b"2".to_vec());
+        transaction.insert(DBCol::Block, b"c".to_vec(), b"3".to_vec());
+        let value = refcount::add_positive_refcount(b"4", rc);
+        transaction.update_refcount(DBCol::State, b"d".to_vec(), value);
+        db.write(transaction).unwrap();
+        assert!(db.get_raw_bytes(DBCol::BlockMisc, b"a").unwrap().is_some());
+        let mut transaction = DBTransaction::new();
+        transaction.delete(DBCol::BlockMisc, b"a".to_vec());
+        transaction.update_refcount(
+            DBCol::State,
+            b"d".to_vec(),
+            refcount::encode_negative_refcount(rc),
+        );
+        db.write(transaction).unwrap();
+        db.update(DBCol::BlockMisc, b"b", &|_| Some(b"5".to_vec())).unwrap();
+
+        let target = TestDB::new();
+        assert_eq!(replay(&journal_path, target.as_ref()).unwrap(), 3);
+        for col in [DBCol::BlockMisc, DBCol::Block, DBCol::State] {
+            let want = db.iter_raw_bytes(col).map(Result::unwrap).collect::<Vec<_>>();
+            let got = target.iter_raw_bytes(col).map(Result::unwrap).collect::<Vec<_>>();
+            assert_eq!(got, want, "{col}");
+        }
+        assert_eq!(
+            target.get_raw_bytes(DBCol::BlockMisc, b"b").unwrap().as_deref(),
+            Some(&b"5"[..])
+        );
+    }
+
+    #[test]
+    fn test_journal_concurrent_writes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let journal_path = tmp_dir.path().join("journal");
+        let db = JournalingDatabase::new(TestDB::new(), &journal_path).unwrap();
+        std::thread::scope(|scope| {
+            for thread in 0u8..4 {
+                let db = &db;
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        let mut transaction = DBTransaction::new();
+                        transaction.set(DBCol::BlockMisc, b"key".to_vec(), vec![thread]);
+                        db.write(transaction).unwrap();
+                    }
+                });
+            }
+        });
+
+        // Writes are journaled in the order they were applied so replay ends
+        // with the same value.
+        let target = TestDB::new();
+        assert_eq!(replay(&journal_path, target.as_ref()).unwrap(), 400);
+        assert_eq!(
+            target.get_raw_bytes(DBCol::BlockMisc, b"key").unwrap(),
+            db.get_raw_bytes(DBCol::BlockMisc, b"key").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_journal_write_if_unchanged() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let journal_path = tmp_dir.path().join("journal");
+        let db = JournalingDatabase::new(TestDB::new(), &journal_path).unwrap();
+        let set = |value: &[u8]| {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, b"key".to_vec(), value.to_vec());
+            transaction
+        };
+        let witnesses = [(DBCol::BlockMisc, b"key".to_vec(), None)];
+        assert!(db.write_if_unchanged(set(b"1"), &witnesses).unwrap());
+        // The witness no longer holds so the write isn’t journaled.
+        assert!(!db.write_if_unchanged(set(b"2"), &witnesses).unwrap());
+
+        let target = TestDB::new();
+        assert_eq!(replay(&journal_path, target.as_ref()).unwrap(), 1);
+        let value = target.get_raw_bytes(DBCol::BlockMisc, b"key").unwrap();
+        assert_eq!(value.as_deref(), Some(&b"1"[..]));
+    }
+}
@@ -0,0 +1,271 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::db::{DBIterator, DBSlice, DBTransaction, Database, StoreStatistics, WriteId};
+use crate::DBCol;
+
+/// A database wrapper which applies writes to two databases.
+///
+/// Meant for live migration to new storage: writes go to the primary and
+/// then to the mirror while all reads are served by the primary.  By default
+/// a failed mirror write is logged and counted (see
+/// [`Self::mirror_failures`]) but doesn’t fail the write, after which the
+/// mirror is missing the write and needs to be re-synchronised.  In strict
+/// mode (see [`Self::with_strict`]) the error is returned instead, though
+/// the write has already been applied to the primary at that point.
+///
+/// Writes are done one at a time so that concurrent writes are applied to
+/// both databases in the same order.  [`Database::update`] and
+/// [`Database::write_if_unchanged`] are as atomic as in the primary; their
+/// resulting writes are mirrored afterwards.
+pub struct MirroringDatabase {
+    primary: Arc<dyn Database>,
+    mirror: Arc<dyn Database>,
+    strict: bool,
+    mirror_failures: AtomicU64,
+
+    /// Lock held while writing to the primary and the mirror.
+    write_lock: Mutex<()>,
+}
+
+impl MirroringDatabase {
+    pub fn new(primary: Arc<dyn Database>, mirror: Arc<dyn Database>) -> Self {
+        Self {
+            primary,
+            mirror,
+            strict: false,
+            mirror_failures: AtomicU64::new(0),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Sets whether failures of the mirror are returned as errors.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Returns number of mirror operations which failed so far.
+    pub fn mirror_failures(&self) -> u64 {
+        self.mirror_failures.load(Ordering::Relaxed)
+    }
+
+    fn copy(batch: &DBTransaction) -> DBTransaction {
+        DBTransaction {
+            ops: batch.ops.clone(),
+            raw_rc_writes: batch.raw_rc_writes,
+            ..Default::default()
+        }
+    }
+
+    /// Handles result of an operation on the mirror.
+    fn check_mirror(&self, op: &str, result: io::Result<()>) -> io::Result<()> {
+        let Err(err) = result else { return Ok(()) };
+        self.mirror_failures.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(
+            target: "store",
+            mirror = self.mirror.name(),
+            op,
+            %err,
+            "Mirror database failed; it’s now behind the primary"
+        );
+        if self.strict {
+            Err(err)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Database for MirroringDatabase {
+    fn name(&self) -> &str {
+        self.primary.name()
+    }
+
+    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        self.primary.get_raw_bytes(col, key)
+    }
+
+    fn get_with_rc_stripped(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        self.primary.get_with_rc_stripped(col, key)
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        self.primary.iter(col)
+    }
+
+    fn iter_prefix<'a>(&'a self, col: DBCol, key_prefix: &'a [u8]) -> DBIterator<'a> {
+        self.primary.iter_prefix(col, key_prefix)
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        self.primary.iter_range(col, lower_bound, upper_bound)
+    }
+
+    fn iter_raw_bytes<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        self.primary.iter_raw_bytes(col)
+    }
+
+    fn write(&self, batch: DBTransaction) -> io::Result<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        self.primary.write(Self::copy(&batch))?;
+        self.check_mirror("write", self.mirror.write(batch))
+    }
+
+    /// Returns identifier of the write to the primary.
+    fn write_with_id(&self, batch: DBTransaction) -> io::Result<WriteId> {
+        let _guard = self.write_lock.lock().unwrap();
+        let id = self.primary.write_with_id(Self::copy(&batch))?;
+        self.check_mirror("write", self.mirror.write(batch))?;
+        Ok(id)
+    }
+
+    fn write_atomic(&self, batch: DBTransaction) -> io::Result<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        self.primary.write_atomic(Self::copy(&batch))?;
+        self.check_mirror("write", self.mirror.write_atomic(batch))
+    }
+
+    fn update(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        match crate::db::update_returning_write(self.primary.as_ref(), col, key, f)? {
+            Some(transaction) => self.check_mirror("write", self.mirror.write(transaction)),
+            None => Ok(()),
+        }
+    }
+
+    /// Witnesses are checked in the primary only, with its atomicity.  If the
+    /// batch has been written to the primary, it’s mirrored as is.
+    fn write_if_unchanged(
+        &self,
+        batch: DBTransaction,
+        witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> io::Result<bool> {
+        let _guard = self.write_lock.lock().unwrap();
+        if !self.primary.write_if_unchanged(Self::copy(&batch), witnesses)? {
+            return Ok(false);
+        }
+        self.check_mirror("write", self.mirror.write_atomic(batch))?;
+        Ok(true)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.primary.flush()?;
+        self.check_mirror("flush", self.mirror.flush())
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        self.primary.compact()?;
+        self.check_mirror("compact", self.mirror.compact())
+    }
+
+    fn get_store_statistics(&self) -> Option<StoreStatistics> {
+        self.primary.get_store_statistics()
+    }
+
+    fn create_checkpoint(
+        &self,
+        path: &std::path::Path,
+        columns_to_keep: Option<&[DBCol]>,
+    ) -> anyhow::Result<()> {
+        self.primary.create_checkpoint(path, columns_to_keep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TestDB;
+
+    fn setup(strict: bool) -> (Arc<dyn Database>, Arc<TestDB>, MirroringDatabase) {
+        let primary: Arc<dyn Database> = TestDB::new();
+        let mirror = TestDB::new();
+        let db = MirroringDatabase::new(primary.clone(), mirror.clone()).with_strict(strict);
+        (primary, mirror, db)
+    }
+
+    fn set(db: &dyn Database, key: &[u8]) -> io::Result<()> {
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, key.to_vec(), b"value".to_vec());
+        db.write(transaction)
+    }
+
+    fn get(db: &dyn Database, key: &[u8]) -> Option<Vec<u8>> {
+        db.get_raw_bytes(DBCol::BlockMisc, key).unwrap().map(|value| value.to_vec())
+    }
+
+    #[test]
+    fn test_mirror_lenient() {
+        let (primary, mirror, db) = setup(false);
+        set(&db, b"a").unwrap();
+        assert_eq!(get(primary.as_ref(), b"a").as_deref(), Some(&b"value"[..]));
+        assert_eq!(get(mirror.as_ref(), b"a").as_deref(), Some(&b"value"[..]));
+        assert_eq!(db.mirror_failures(), 0);
+
+        mirror.fail_writes_after(0);
+        set(&db, b"b").unwrap();
+        assert_eq!(get(&db, b"b").as_deref(), Some(&b"value"[..]));
+        assert_eq!(get(mirror.as_ref(), b"b"), None);
+        assert_eq!(db.mirror_failures(), 1);
+    }
+
+    #[test]
+    fn test_mirror_strict() {
+        let (primary, mirror, db) = setup(true);
+        set(&db, b"a").unwrap();
+        assert_eq!(get(mirror.as_ref(), b"a").as_deref(), Some(&b"value"[..]));
+
+        mirror.fail_writes_after(0);
+        let err = set(&db, b"b").unwrap_err();
+        assert_eq!(err.to_string(), "injected write failure");
+        assert_eq!(db.mirror_failures(), 1);
+        // The primary has been written before the mirror failed.
+        assert_eq!(get(primary.as_ref(), b"b").as_deref(), Some(&b"value"[..]));
+        assert_eq!(get(mirror.as_ref(), b"b"), None);
+    }
+
+    #[test]
+    fn test_mirror_update() {
+        let (primary, mirror, db) = setup(true);
+        db.update(DBCol::BlockMisc, b"a", &|value| {
+            assert_eq!(value, None);
+            Some(b"value".to_vec())
+        })
+        .unwrap();
+        assert_eq!(get(primary.as_ref(), b"a").as_deref(), Some(&b"value"[..]));
+        assert_eq!(get(mirror.as_ref(), b"a").as_deref(), Some(&b"value"[..]));
+        db.update(DBCol::BlockMisc, b"a", &|_| None).unwrap();
+        assert_eq!(get(primary.as_ref(), b"a"), None);
+        assert_eq!(get(mirror.as_ref(), b"a"), None);
+    }
+
+    #[test]
+    fn test_mirror_concurrent_writes() {
+        let (primary, mirror, db) = setup(true);
+        std::thread::scope(|scope| {
+            for thread in 0u8..4 {
+                let db = &db;
+                scope.spawn(move || {
+                    for value in 0u8..100 {
+                        let mut transaction = DBTransaction::new();
+                        transaction.set(DBCol::BlockMisc, b"a".to_vec(), vec![thread, value]);
+                        db.write(transaction).unwrap();
+                    }
+                });
+            }
+        });
+        // Both databases applied the writes in the same order.
+        assert_eq!(get(primary.as_ref(), b"a"), get(mirror.as_ref(), b"a"));
+    }
+}
@@ -0,0 +1,273 @@
+use std::io;
+use std::sync::Arc;
+
+use crate::db::{prefix_upper_bound, DBIterator, DBOp, DBSlice, DBTransaction, Database};
+use crate::db::{StoreStatistics, WriteId};
+use crate::DBCol;
+
+/// A database view holding only keys which start with a fixed prefix in
+/// another database.
+///
+/// Meant for tests where many fixtures can share a single backing store with
+/// each one working on its own partition.  All keys are transparently
+/// prefixed before being passed to the inner database, and stripped of the
+/// prefix when returned by iterators, which are bounded to the partition.
+/// Values, and reference counts in particular, are passed as is.
+///
+/// Partitions are isolated only if neither of their prefixes is a prefix of
+/// the other, which is easiest to ensure by using prefixes of the same length.
+/// Database-wide operations, i.e. flushing, compaction and statistics, apply
+/// to the whole inner database.
+pub struct PartitionedDatabase {
+    inner: Arc<dyn Database>,
+    prefix: Vec<u8>,
+    /// Exclusive upper bound of keys in the partition.
+    upper_bound: Vec<u8>,
+}
+
+impl PartitionedDatabase {
+    /// Creates a view of keys starting with `partition_prefix` in `inner`.
+    ///
+    /// **Panics** if the prefix is empty or consists of `0xff` bytes only
+    /// since such partition would have no upper bound.
+    pub fn new(inner: Arc<dyn Database>, partition_prefix: Vec<u8>) -> Self {
+        let upper_bound = prefix_upper_bound(&partition_prefix)
+            .expect("partition prefix must not be empty nor consist of 0xff bytes only");
+        Self { inner, prefix: partition_prefix, upper_bound }
+    }
+
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        [&self.prefix[..], key].concat()
+    }
+
+    /// Returns bound within the partition corresponding to `bound` given
+    /// relative to it.  Partition’s end is used if there’s no bound.
+    fn upper(&self, bound: Option<&[u8]>) -> Vec<u8> {
+        bound.map_or_else(|| self.upper_bound.clone(), |bound| self.key(bound))
+    }
+
+    /// Strips partition prefix from keys returned by the iterator.
+    fn strip<'a>(&self, iter: DBIterator<'a>) -> DBIterator<'a> {
+        let len = self.prefix.len();
+        Box::new(iter.map(move |item| item.map(|(key, value)| (key[len..].into(), value))))
+    }
+
+    fn map_transaction(&self, batch: DBTransaction) -> DBTransaction {
+        let mut transaction = DBTransaction::new();
+        if batch.raw_rc_writes {
+            transaction.allow_raw_rc_writes();
+        }
+        for op in batch.ops {
+            match op {
+                DBOp::Set { col, key, value } => transaction.set(col, self.key(&key), value),
+                DBOp::Insert { col, key, value } => transaction.insert(col, self.key(&key), value),
+                DBOp::UpdateRefcount { col, key, value } => {
+                    transaction.update_refcount(col, self.key(&key), value)
+                }
+                DBOp::Delete { col, key } => transaction.delete(col, self.key(&key)),
+                DBOp::DeleteAll { col } => {
+                    transaction.delete_range(col, self.prefix.clone(), self.upper_bound.clone())
+                }
+                DBOp::DeleteRange { col, from, to } => {
+                    transaction.delete_range(col, self.key(&from), self.key(&to))
+                }
+            }
+        }
+        transaction
+    }
+}
+
+impl Database for PartitionedDatabase {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        self.inner.get_raw_bytes(col, &self.key(key))
+    }
+
+    fn get_with_rc_stripped(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        self.inner.get_with_rc_stripped(col, &self.key(key))
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        self.iter_range(col, None, None)
+    }
+
+    fn iter_prefix<'a>(&'a self, col: DBCol, key_prefix: &'a [u8]) -> DBIterator<'a> {
+        let lower = self.key(key_prefix);
+        let upper = prefix_upper_bound(&lower);
+        self.strip(self.inner.iter_range(col, Some(&lower), upper.as_deref()))
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        let lower = self.key(lower_bound.unwrap_or_default());
+        let upper = self.upper(upper_bound);
+        self.strip(self.inner.iter_range(col, Some(&lower), Some(&upper)))
+    }
+
+    /// Since the trait offers no bounded raw iteration, this scans the inner
+    /// column from its start up to the end of the partition.
+    fn iter_raw_bytes<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        let iter = self
+            .inner
+            .iter_raw_bytes(col)
+            .skip_while(|item| item.as_ref().is_ok_and(|(key, _)| **key < *self.prefix))
+            .take_while(|item| item.as_ref().map_or(true, |(key, _)| **key < *self.upper_bound));
+        self.strip(Box::new(iter))
+    }
+
+    fn write(&self, batch: DBTransaction) -> io::Result<()> {
+        self.inner.write(self.map_transaction(batch))
+    }
+
+    fn write_with_id(&self, batch: DBTransaction) -> io::Result<WriteId> {
+        self.inner.write_with_id(self.map_transaction(batch))
+    }
+
+    fn write_atomic(&self, batch: DBTransaction) -> io::Result<()> {
+        self.inner.write_atomic(self.map_transaction(batch))
+    }
+
+    fn update(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        self.inner.update(col, &self.key(key), f)
+    }
+
+    fn write_if_unchanged(
+        &self,
+        batch: DBTransaction,
+        witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> io::Result<bool> {
+        let witnesses = witnesses
+            .iter()
+            .map(|(col, key, value)| (*col, self.key(key), value.clone()))
+            .collect::<Vec<_>>();
+        self.inner.write_if_unchanged(self.map_transaction(batch), &witnesses)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        self.inner.compact()
+    }
+
+    fn get_store_statistics(&self) -> Option<StoreStatistics> {
+        self.inner.get_store_statistics()
+    }
+
+    /// Unsupported; a checkpoint would include all partitions.
+    fn create_checkpoint(
+        &self,
+        _path: &std::path::Path,
+        _columns_to_keep: Option<&[DBCol]>,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("checkpoints of a single partition aren’t supported")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+    use crate::db::{refcount, TestDB};
+
+    fn setup() -> (Arc<dyn Database>, PartitionedDatabase, PartitionedDatabase) {
+        let inner: Arc<dyn Database> = TestDB::new();
+        let a = PartitionedDatabase::new(inner.clone(), b"a/".to_vec());
+        let b = PartitionedDatabase::new(inner.clone(), b"b/".to_vec());
+        (inner, a, b)
+    }
+
+    fn set(db: &dyn Database, key: &[u8], value: &[u8]) {
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, key.to_vec(), value.to_vec());
+        db.write(transaction).unwrap();
+    }
+
+    fn keys(iter: DBIterator) -> Vec<Vec<u8>> {
+        iter.map(|item| item.unwrap().0.into_vec()).collect()
+    }
+
+    #[test]
+    fn test_partition_isolation() {
+        let (inner, a, b) = setup();
+        // Keys sorting around the partitions in the inner database.
+        set(inner.as_ref(), b"a", b"outside");
+        set(inner.as_ref(), b"a0", b"outside");
+        for key in [&b"x"[..], b"y", b"z"] {
+            set(&a, key, b"a");
+        }
+        set(&b, b"x", b"b");
+
+        let get = |db: &dyn Database, key: &[u8]| {
+            db.get_raw_bytes(DBCol::BlockMisc, key).unwrap().map(|value| value.to_vec())
+        };
+        assert_eq!(get(&a, b"x").as_deref(), Some(&b"a"[..]));
+        assert_eq!(get(&b, b"x").as_deref(), Some(&b"b"[..]));
+        assert_eq!(get(&b, b"y"), None);
+        assert_eq!(get(inner.as_ref(), b"a/y").as_deref(), Some(&b"a"[..]));
+
+        let all = vec![b"x".to_vec(), b"y".to_vec(), b"z".to_vec()];
+        assert_eq!(keys(a.iter(DBCol::BlockMisc)), all);
+        assert_eq!(keys(a.iter_raw_bytes(DBCol::BlockMisc)), all);
+        assert_eq!(keys(b.iter(DBCol::BlockMisc)), [b"x".to_vec()]);
+        assert_eq!(keys(a.iter_prefix(DBCol::BlockMisc, b"y")), [b"y".to_vec()]);
+        let range = a.iter_range(DBCol::BlockMisc, Some(b"y"), None);
+        assert_eq!(keys(range), [b"y".to_vec(), b"z".to_vec()]);
+        let range = a.iter_range(DBCol::BlockMisc, None, Some(b"y"));
+        assert_eq!(keys(range), [b"x".to_vec()]);
+
+        // Deleting all data of a column only affects the partition.
+        let mut transaction = DBTransaction::new();
+        transaction.delete_all(DBCol::BlockMisc);
+        a.write(transaction).unwrap();
+        assert_eq!(keys(a.iter(DBCol::BlockMisc)), Vec::<Vec<u8>>::new());
+        assert_eq!(keys(b.iter(DBCol::BlockMisc)), [b"x".to_vec()]);
+        assert_eq!(inner.iter(DBCol::BlockMisc).count(), 3);
+    }
+
+    #[test]
+    fn test_partition_refcount() {
+        let (inner, a, b) = setup();
+        let rc = NonZeroU32::new(1).unwrap();
+        let increment = |db: &dyn Database| {
+            let mut transaction = DBTransaction::new();
+            let value = refcount::add_positive_refcount(b"node", rc);
+            transaction.update_refcount(DBCol::State, b"key".to_vec(), value);
+            db.write(transaction).unwrap();
+        };
+        increment(&a);
+        increment(&a);
+        increment(&b);
+        assert_eq!(a.get_refcount(DBCol::State, b"key").unwrap(), 2);
+        assert_eq!(b.get_refcount(DBCol::State, b"key").unwrap(), 1);
+
+        let mut transaction = DBTransaction::new();
+        transaction.update_refcount(
+            DBCol::State,
+            b"key".to_vec(),
+            refcount::encode_negative_refcount(rc),
+        );
+        b.write(transaction).unwrap();
+        assert_eq!(b.get_with_rc_stripped(DBCol::State, b"key").unwrap(), None);
+        let value = a.get_with_rc_stripped(DBCol::State, b"key").unwrap();
+        assert_eq!(value.as_deref(), Some(&b"node"[..]));
+        let got = a.iter(DBCol::State).map(Result::unwrap).collect::<Vec<_>>();
+        let want: Vec<(Box<[u8]>, Box<[u8]>)> = vec![(b"key"[..].into(), b"node"[..].into())];
+        assert_eq!(got, want);
+        assert_eq!(inner.iter(DBCol::State).count(), 1);
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::db::{DBIterator, DBSlice, DBTransaction, Database, StoreStatistics, WriteId};
+use crate::DBCol;
+
+/// Number of reads and writes of a single column recorded by
+/// [`AccessProfiler`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    /// Number of point reads plus number of items returned by iterators.
+    pub reads: u64,
+    /// Number of write operations, i.e. operations in written transactions.
+    pub writes: u64,
+}
+
+#[derive(Default)]
+struct AtomicAccessCounts {
+    reads: AtomicU64,
+    writes: AtomicU64,
+}
+
+/// A database wrapper which counts reads and writes per column.
+///
+/// The profiling is opt-in.  It starts disabled in which case the only
+/// overhead is checking a flag on each access.  Once enabled with
+/// [`Self::set_enabled`], each access increments an atomic counter.  The
+/// collected counts can be read with [`Self::access_profile`], e.g. to find
+/// which columns are the hottest during a workload.
+pub struct AccessProfiler {
+    db: Arc<dyn Database>,
+    enabled: AtomicBool,
+    counts: enum_map::EnumMap<DBCol, AtomicAccessCounts>,
+}
+
+impl AccessProfiler {
+    /// Wraps given database.  The profiling is initially disabled.
+    pub fn new(db: Arc<dyn Database>) -> Self {
+        Self { db, enabled: AtomicBool::new(false), counts: Default::default() }
+    }
+
+    /// Enables or disables counting of accesses.  Disabling the profiling
+    /// doesn’t reset already collected counts.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns read and write counts of all columns accessed since the
+    /// profiler has been created or last reset.
+    pub fn access_profile(&self) -> HashMap<DBCol, AccessCounts> {
+        self.counts
+            .iter()
+            .map(|(col, counts)| {
+                let reads = counts.reads.load(Ordering::Relaxed);
+                let writes = counts.writes.load(Ordering::Relaxed);
+                (col, AccessCounts { reads, writes })
+            })
+            .filter(|(_, counts)| *counts != AccessCounts::default())
+            .collect()
+    }
+
+    /// Resets all the counts to zero.
+    pub fn reset(&self) {
+        for counts in self.counts.values() {
+            counts.reads.store(0, Ordering::Relaxed);
+            counts.writes.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn record_read(&self, col: DBCol) {
+        if self.enabled.load(Ordering::Relaxed) {
+            self.counts[col].reads.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn record_writes(&self, transaction: &DBTransaction) {
+        if self.enabled.load(Ordering::Relaxed) {
+            for op in transaction.ops.iter() {
+                self.counts[op.col()].writes.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Wraps iterator so that each returned item is counted as a read.
+    fn profile_iter<'a>(&'a self, col: DBCol, iter: DBIterator<'a>) -> DBIterator<'a> {
+        Box::new(iter.inspect(move |_| self.record_read(col)))
+    }
+}
+
+impl Database for AccessProfiler {
+    fn name(&self) -> &str {
+        self.db.name()
+    }
+
+    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        self.record_read(col);
+        self.db.get_raw_bytes(col, key)
+    }
+
+    fn get_with_rc_stripped(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        self.record_read(col);
+        self.db.get_with_rc_stripped(col, key)
+    }
+
+    fn value_len(&self, col: DBCol, key: &[u8]) -> io::Result<Option<usize>> {
+        self.record_read(col);
+        self.db.value_len(col, key)
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        self.profile_iter(col, self.db.iter(col))
+    }
+
+    fn iter_prefix<'a>(&'a self, col: DBCol, key_prefix: &'a [u8]) -> DBIterator<'a> {
+        self.profile_iter(col, self.db.iter_prefix(col, key_prefix))
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        self.profile_iter(col, self.db.iter_range(col, lower_bound, upper_bound))
+    }
+
+    fn iter_raw_bytes<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        self.profile_iter(col, self.db.iter_raw_bytes(col))
+    }
+
+    fn write(&self, batch: DBTransaction) -> io::Result<()> {
+        self.record_writes(&batch);
+        self.db.write(batch)
+    }
+
+    fn write_with_id(&self, batch: DBTransaction) -> io::Result<WriteId> {
+        self.record_writes(&batch);
+        self.db.write_with_id(batch)
+    }
+
+    fn write_atomic(&self, batch: DBTransaction) -> io::Result<()> {
+        self.record_writes(&batch);
+        self.db.write_atomic(batch)
+    }
+
+    fn update(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        if self.enabled.load(Ordering::Relaxed) {
+            self.counts[col].reads.fetch_add(1, Ordering::Relaxed);
+            self.counts[col].writes.fetch_add(1, Ordering::Relaxed);
+        }
+        self.db.update(col, key, f)
+    }
+
+    fn write_if_unchanged(
+        &self,
+        batch: DBTransaction,
+        witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> io::Result<bool> {
+        for (col, _, _) in witnesses {
+            self.record_read(*col);
+        }
+        self.record_writes(&batch);
+        self.db.write_if_unchanged(batch, witnesses)
+    }
+
+    fn truncate_column(&self, col: DBCol) -> io::Result<()> {
+        self.db.truncate_column(col)
+    }
+
+    fn approx_keys_in_range(&self, col: DBCol, from: &[u8], to: &[u8]) -> io::Result<u64> {
+        self.db.approx_keys_in_range(col, from, to)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.db.flush()
+    }
+
+    fn flush_column(&self, col: DBCol) -> io::Result<()> {
+        self.db.flush_column(col)
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        self.db.compact()
+    }
+
+    fn total_disk_size(&self) -> io::Result<u64> {
+        self.db.total_disk_size()
+    }
+
+    fn wal_sequence_range(&self) -> io::Result<Option<(u64, u64)>> {
+        self.db.wal_sequence_range()
+    }
+
+    fn get_store_statistics(&self) -> Option<StoreStatistics> {
+        self.db.get_store_statistics()
+    }
+
+    fn create_checkpoint(
+        &self,
+        path: &std::path::Path,
+        columns_to_keep: Option<&[DBCol]>,
+    ) -> anyhow::Result<()> {
+        self.db.create_checkpoint(path, columns_to_keep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TestDB;
+
+    #[test]
+    fn test_access_profile() {
+        let db = AccessProfiler::new(TestDB::new());
+        let write = |keys: &[&[u8]]| {
+            let mut transaction = DBTransaction::new();
+            for key in keys {
+                transaction.set(DBCol::BlockMisc, key.to_vec(), b"value".to_vec());
+            }
+            transaction.delete(DBCol::Block, b"foo".to_vec());
+            db.write(transaction).unwrap();
+        };
+
+        // Nothing is counted while disabled.
+        write(&[b"a"]);
+        db.get_raw_bytes(DBCol::BlockMisc, b"a").unwrap();
+        assert_eq!(db.access_profile(), HashMap::new());
+
+        db.set_enabled(true);
+        write(&[b"b", b"c"]);
+        db.get_raw_bytes(DBCol::BlockMisc, b"a").unwrap();
+        db.get_raw_bytes(DBCol::BlockHeader, b"a").unwrap();
+        assert_eq!(db.iter(DBCol::BlockMisc).count(), 3);
+        assert_eq!(db.iter_prefix(DBCol::BlockMisc, b"b").count(), 1);
+        let want = HashMap::from([
+            (DBCol::BlockMisc, AccessCounts { reads: 5, writes: 2 }),
+            (DBCol::Block, AccessCounts { reads: 0, writes: 1 }),
+            (DBCol::BlockHeader, AccessCounts { reads: 1, writes: 0 }),
+        ]);
+        assert_eq!(db.access_profile(), want);
+
+        // Disabling keeps the counts, resetting clears them.
+        db.set_enabled(false);
+        write(&[b"d"]);
+        assert_eq!(db.access_profile(), want);
+        db.reset();
+        assert_eq!(db.access_profile(), HashMap::new());
+    }
+}
@@ -21,6 +21,7 @@
 use std::cmp::Ordering;
 use std::io;
 
+use near_fmt::StorageKey;
 use rocksdb::compaction_filter::Decision;
 
 use crate::db::RocksDB;
@@ -32,11 +33,14 @@ use crate::DBCol;
 /// - rc > 0 ⇒ returns `(Some(value), rc)`,
 /// - rc ≤ 0 ⇒ returns `(None, rc)`.
 ///
-/// In builds with debug assertions enabled, panics if `bytes` are non-empty but
-/// too short to fit 64-bit reference count.
+/// If `bytes` are non-empty but too short to fit 64-bit reference count, the
+/// value is treated as absent and the failure is counted in a metric (see
+/// [`crate::db::Database::refcount_decode_errors`]).
 pub fn decode_value_with_rc(bytes: &[u8]) -> (Option<&[u8]>, i64) {
     if bytes.len() < 8 {
-        debug_assert!(bytes.is_empty());
+        if !bytes.is_empty() {
+            report_decode_error(bytes);
+        }
         return (None, 0);
     }
     let (head, tail) = stdx::rsplit_slice::<8>(bytes);
@@ -59,12 +63,28 @@ pub(crate) fn strip_refcount(mut bytes: Vec<u8>) -> Option<Vec<u8>> {
             bytes.truncate(len);
             return Some(bytes);
         }
-    } else {
-        debug_assert!(bytes.is_empty());
+    } else if !bytes.is_empty() {
+        report_decode_error(&bytes);
     }
     None
 }
 
+/// Records a reference counted value which is too short to hold a reference
+/// count.
+fn report_decode_error(bytes: &[u8]) {
+    crate::metrics::REFCOUNT_DECODE_ERRORS.inc();
+    tracing::debug!(
+        target: "store",
+        len = bytes.len(),
+        "Malformed reference counted value; treating it as absent"
+    );
+}
+
+/// Returns number of reference count decoding failures since process start.
+pub(crate) fn decode_errors() -> u64 {
+    crate::metrics::REFCOUNT_DECODE_ERRORS.get()
+}
+
 /// Sets the refcount to the given value.
 ///
 /// This method assumes that the data already contains a reference count stored
@@ -147,6 +167,42 @@ pub(crate) fn iter_with_rc_logic<'a>(
     }
 }
 
+/// Iterator which skips entries that can’t be decoded; see
+/// [`crate::db::Database::iter_lenient`].
+///
+/// `iterator` must return raw values, i.e. with reference counts included.
+pub(crate) fn iter_lenient_rc_logic<'a>(
+    col: DBCol,
+    iterator: impl Iterator<Item = io::Result<(Box<[u8]>, Box<[u8]>)>> + 'a,
+) -> crate::db::DBIterator<'a> {
+    // The outer Option ends the iteration while the inner one skips an entry.
+    let items = iterator.map_while(move |item| {
+        let (key, value) = match item {
+            Ok(item) => item,
+            Err(err) => {
+                tracing::warn!(target: "store", %col, %err, "Iteration failed; stopping");
+                return None;
+            }
+        };
+        if !col.is_rc() {
+            return Some(Some(Ok((key, value))));
+        }
+        if !value.is_empty() && value.len() < 8 {
+            crate::metrics::REFCOUNT_DECODE_ERRORS.inc();
+            tracing::warn!(
+                target: "store",
+                %col,
+                key = %StorageKey(&key),
+                len = value.len(),
+                "Skipping malformed reference counted value"
+            );
+            return Some(None);
+        }
+        Some(strip_refcount(value.into_vec()).map(|value| Ok((key, value.into_boxed_slice()))))
+    });
+    Box::new(items.flatten())
+}
+
 impl RocksDB {
     /// Merge adds refcounts, zero refcount becomes empty value.
     /// Empty values get filtered by get methods, and removed by compaction.
@@ -182,20 +238,6 @@ mod test {
     const PLUS_ONE: &[u8] = b"\x01\0\0\0\0\0\0\0";
     const PLUS_TWO: &[u8] = b"\x02\0\0\0\0\0\0\0";
 
-    fn check_debug_assert_or<F, P, R>(callback: F, predicate: P)
-    where
-        F: FnOnce() -> R + std::panic::UnwindSafe,
-        P: FnOnce(R),
-        R: std::fmt::Debug,
-    {
-        let res = std::panic::catch_unwind(callback);
-        if cfg!(debug_assertions) {
-            assert!(res.is_err(), "{res:?}");
-        } else {
-            predicate(res.unwrap());
-        }
-    }
-
     #[test]
     fn decode_value_with_rc() {
         fn test(want_value: Option<&[u8]>, want_rc: i64, bytes: &[u8]) {
@@ -214,10 +256,10 @@ mod test {
         test(Some(b""), 2, PLUS_TWO);
         test(Some(b"baz"), 2, b"baz\x02\0\0\0\0\0\0\0");
 
-        check_debug_assert_or(
-            || super::decode_value_with_rc(b"short"),
-            |got| assert_eq!((None, 0), got),
-        );
+        // Malformed values are treated as absent and counted.
+        let errors = super::decode_errors();
+        test(None, 0, b"short");
+        assert!(super::decode_errors() >= errors + 2);
     }
 
     #[test]
@@ -350,4 +392,18 @@ mod test {
             ],
         );
     }
+
+    #[test]
+    fn iter_lenient_rc_logic_stops_at_error() {
+        fn item(key: &[u8]) -> std::io::Result<(Box<[u8]>, Box<[u8]>)> {
+            Ok((key.into(), key.into()))
+        }
+
+        // Underlying iterator which carries on after an error.
+        let items = vec![item(b"a"), Err(std::io::Error::other("failure")), item(b"c")];
+        let got: Vec<_> = super::iter_lenient_rc_logic(DBCol::BlockMisc, items.into_iter())
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(got, [b"a".to_vec().into_boxed_slice()]);
+    }
 }
@@ -1,14 +1,20 @@
-use crate::config::Mode;
-use crate::db::{refcount, DBIterator, DBOp, DBSlice, DBTransaction, Database, StatsValue};
+use crate::config::{CompactionStyle, Compression, Mode, TableFormat};
+use crate::db::{
+    refcount, BloomStats, CompactionPriority, DBIterator, DBOp, DBSlice, DBTransaction, Database,
+    KeyLocation, StatsValue, WriteId,
+};
 use crate::{metadata, metrics, DBCol, StoreConfig, StoreStatistics, Temperature};
+pub use ::rocksdb::LogLevel;
 use ::rocksdb::{
-    BlockBasedOptions, Cache, ColumnFamily, Env, IteratorMode, Options, ReadOptions, WriteBatch, DB,
+    BlockBasedOptions, Cache, ColumnFamily, Env, IteratorMode, Options, ReadOptions, ReadTier,
+    WriteBatch, WriteOptions, DB,
 };
 use anyhow::Context;
 use itertools::Itertools;
+use near_fmt::StorageKey;
 use once_cell::sync::Lazy;
+use std::borrow::Cow;
 use std::io;
-use std::ops::Deref;
 use std::path::Path;
 use strum::IntoEnumIterator;
 use tracing::warn;
@@ -43,6 +49,23 @@ static CF_PROPERTY_NAMES: Lazy<Vec<std::ffi::CString>> = Lazy::new(|| {
     ret
 });
 
+/// Additional properties reported for columns with
+/// [`crate::config::ColumnConfig::statistics`] enabled.
+static DETAILED_CF_PROPERTY_NAMES: Lazy<Vec<std::ffi::CString>> = Lazy::new(|| {
+    use ::rocksdb::properties;
+    [
+        properties::ESTIMATE_NUM_KEYS,
+        properties::NUM_ENTRIES_ACTIVE_MEM_TABLE,
+        properties::NUM_ENTRIES_IMM_MEM_TABLES,
+        properties::NUM_DELETES_ACTIVE_MEM_TABLE,
+        properties::NUM_DELETES_IMM_MEM_TABLES,
+        properties::TOTAL_SST_FILES_SIZE,
+        properties::NUM_LIVE_VERSIONS,
+    ]
+    .map(std::ffi::CStr::to_owned)
+    .to_vec()
+});
+
 pub struct RocksDB {
     db: DB,
     db_opt: Options,
@@ -54,6 +77,66 @@ pub struct RocksDB {
     /// want.
     cf_handles: enum_map::EnumMap<DBCol, Option<std::ptr::NonNull<ColumnFamily>>>,
 
+    /// Lock making read-modify-write operations atomic.
+    ///
+    /// The database isn’t opened in transactional mode so rather than
+    /// detecting conflicts and retrying, every write holds the lock shared
+    /// while [`Database::update`] and [`Database::write_if_unchanged`] hold
+    /// it exclusively.  No write can thus happen between their read and
+    /// their write.
+    write_lock: std::sync::RwLock<()>,
+
+    /// Locks held while a manual compaction of given column is running.
+    ///
+    /// They prevent multiple threads from compacting the same column at the
+    /// same time which would only result in redundant work.
+    compaction_locks: enum_map::EnumMap<DBCol, std::sync::Mutex<()>>,
+
+    /// Number of live [`BackgroundPauseGuard`]s.  Automatic compactions are
+    /// disabled while it’s non-zero.
+    background_pauses: std::sync::Mutex<usize>,
+
+    /// Size above which [`Database::write`] splits transactions into multiple
+    /// write batches; see [`StoreConfig::max_write_batch_size`].
+    max_write_batch_size: Option<usize>,
+
+    /// Whether raw reads of reference counted columns are logged; see
+    /// [`StoreConfig::strict_rc_reads`].
+    strict_rc_reads: bool,
+
+    /// Duration above which writes are logged; see
+    /// [`StoreConfig::slow_write_threshold`].
+    slow_write_threshold: Option<std::time::Duration>,
+
+    /// Columns with [`crate::config::ColumnConfig::statistics`] enabled.
+    /// Empty if no column has the statistics enabled.
+    statistics_columns: Vec<DBCol>,
+
+    /// Columns with [`crate::config::ColumnConfig::regenerable`] set.  Writes
+    /// touching only those columns skip the write-ahead log.
+    regenerable_columns: enum_map::EnumMap<DBCol, bool>,
+
+    /// Key mappers set with [`RocksDB::set_key_mapper`].
+    #[cfg(feature = "key_mapper")]
+    key_mappers: enum_map::EnumMap<DBCol, Option<KeyMapper>>,
+
+    /// Name returned by [`Database::name`]; see [`RocksDB::with_name`].
+    name: String,
+
+    /// Whether memtables are flushed when the database is dropped; see
+    /// [`RocksDB::set_flush_on_drop`].
+    flush_on_drop: bool,
+
+    /// Configuration and temperature the database was opened with.  Used to
+    /// recreate column families in [`RocksDB::reset_column`].
+    store_config: StoreConfig,
+    temp: Temperature,
+
+    /// Thread forwarding RocksDB’s log to a logger; see
+    /// [`RocksDB::open_with_logger`].  Declared after `db` so that it’s
+    /// stopped after the database is closed and forwards all the lines.
+    log_tailer: Option<LogTailer>,
+
     // RAII-style of keeping track of the number of instances of RocksDB and
     // counting total sum of max_open_files.
     _instance_tracker: instance_tracker::InstanceTracker,
@@ -92,6 +175,88 @@ impl RocksDB {
         Self::open_with_columns(path, store_config, mode, temp, &columns)
     }
 
+    /// Opens the database like [`Self::open`] and forwards RocksDB’s internal
+    /// log to `logger`.
+    ///
+    /// RocksDB writes its log into a `LOG` file in the database directory.
+    /// A background thread polls the file and calls `logger` with each new
+    /// line and its level so the lines can be routed into `tracing`.  The
+    /// lines are thus delivered with a delay of up to a fraction of a second
+    /// and the last ones when the database is closed.  Only lines at the info
+    /// level and above are logged by RocksDB; debug logging would noticeably
+    /// slow the database down.
+    pub fn open_with_logger(
+        path: &Path,
+        store_config: &StoreConfig,
+        mode: Mode,
+        temp: Temperature,
+        logger: Logger,
+    ) -> io::Result<Self> {
+        let mut db = Self::open(path, store_config, mode, temp)?;
+        db.log_tailer = Some(LogTailer::start(path.join("LOG"), logger)?);
+        Ok(db)
+    }
+
+    /// Sets name of the database returned by [`Database::name`].  The default
+    /// is `"rocksdb"`.  Meant to be called right after opening, e.g. to tell
+    /// apart hot and cold databases in logs.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets whether dropping the database flushes its memtables.  Enabled by
+    /// default unless the database is opened read-only.
+    ///
+    /// With the flush, data of all writes is in SST files once the database
+    /// is closed so the next open needn’t replay the write-ahead log.  This
+    /// includes writes to [`crate::config::ColumnConfig::regenerable`]
+    /// columns which skip the log.  Without it, closing is faster but writes
+    /// which skipped the log and haven’t been flushed yet are lost just as if
+    /// the process crashed while the remaining writes are recovered from the
+    /// log on the next open.  Note that a crash never flushes anything
+    /// regardless of the setting.
+    pub fn set_flush_on_drop(&mut self, flush: bool) -> io::Result<()> {
+        // On close, RocksDB itself flushes memtables holding writes which
+        // skipped the log unless told to avoid it.
+        let avoid = if flush { "false" } else { "true" };
+        self.db.set_options(&[("avoid_flush_during_shutdown", avoid)]).map_err(io::Error::other)?;
+        self.flush_on_drop = flush;
+        Ok(())
+    }
+
+    /// Pauses automatic compactions of all columns until the returned guard
+    /// is dropped.
+    ///
+    /// Meant for backup windows, e.g. while a checkpoint is being copied,
+    /// where compactions would compete for IO.  Writes are still accepted and
+    /// accumulate in memtables.  Flushes of full memtables aren’t paused since
+    /// RocksDB’s C API offers no way to do that, and neither are manual
+    /// compactions such as [`Database::compact`].  Guards may overlap in which
+    /// case compactions resume once the last one is dropped.  Note that level
+    /// 0 files keep piling up during the pause so writes may get slowed down
+    /// if it lasts long.
+    pub fn pause_background_work(&self) -> io::Result<BackgroundPauseGuard<'_>> {
+        let mut pauses = self.background_pauses.lock().unwrap();
+        if *pauses == 0 {
+            self.set_auto_compactions(false)?;
+            tracing::info!(target: "db", "Paused automatic compactions");
+        }
+        *pauses += 1;
+        Ok(BackgroundPauseGuard { db: self })
+    }
+
+    /// Enables or disables automatic compactions of all open columns.
+    fn set_auto_compactions(&self, enable: bool) -> io::Result<()> {
+        let disable = if enable { "false" } else { "true" };
+        for (_, cf_handle) in self.cf_handles() {
+            self.db
+                .set_options_cf(cf_handle, &[("disable_auto_compactions", disable)])
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
     /// Opens the database with given set of column families configured.
     ///
     /// With cold storage, we will need to be able to configure the database
@@ -119,7 +284,132 @@ impl RocksDB {
             .map_err(io::Error::other)?;
         let (db, db_opt) = Self::open_db(path, store_config, mode, temp, columns)?;
         let cf_handles = Self::get_cf_handles(&db, columns);
-        Ok(Self { db, db_opt, cf_handles, _instance_tracker: counter })
+        Ok(Self {
+            db,
+            db_opt,
+            cf_handles,
+            write_lock: Default::default(),
+            compaction_locks: Default::default(),
+            background_pauses: Default::default(),
+            max_write_batch_size: store_config
+                .max_write_batch_size
+                .map(|size| usize::try_from(size.as_u64()).unwrap_or(usize::MAX)),
+            slow_write_threshold: store_config.slow_write_threshold,
+            strict_rc_reads: store_config.strict_rc_reads,
+            statistics_columns: columns
+                .iter()
+                .copied()
+                .filter(|&col| store_config.column_config(col).statistics)
+                .collect(),
+            regenerable_columns: enum_map::enum_map! {
+                col => store_config.column_config(col).regenerable,
+            },
+            #[cfg(feature = "key_mapper")]
+            key_mappers: Default::default(),
+            name: "rocksdb".to_string(),
+            flush_on_drop: mode.read_write(),
+            store_config: store_config.clone(),
+            temp,
+            log_tailer: None,
+            _instance_tracker: counter,
+        })
+    }
+
+    /// Drops the column family of given column and recreates it empty with
+    /// the options it’s configured with.
+    ///
+    /// Unlike deleting all keys, this leaves no tombstones behind and
+    /// reclaims the disk space right away.  Meant for test fixtures and
+    /// resets of regenerable data.  Any outstanding iterators over the column
+    /// would be invalidated which is why this requires exclusive access to
+    /// the database.
+    pub fn reset_column(&mut self, col: DBCol) -> io::Result<()> {
+        let opts = rocksdb_column_options(self.db.path(), col, &self.store_config, self.temp)?;
+        let mut columns = self
+            .cf_handles
+            .iter()
+            .filter(|(_, ptr)| ptr.is_some())
+            .map(|(col, _)| col)
+            .collect_vec();
+        // Modifying column families may move the handles so the pointers have
+        // to be fetched anew.
+        self.cf_handles = Default::default();
+        let name = col_name(col);
+        let result = self.db.drop_cf(&name).and_then(|()| self.db.create_cf(&name, &opts));
+        columns.retain(|&col| self.db.cf_handle(&col_name(col)).is_some());
+        self.cf_handles = Self::get_cf_handles(&self.db, &columns);
+        result.map_err(io::Error::other)
+    }
+
+    /// Replaces the database with one prepared in `new_path` and returns the
+    /// reopened database.
+    ///
+    /// Meant for blue-green swaps where a new database is prepared next to
+    /// the active one.  All memtables are flushed before the database is
+    /// closed so no writes are lost, including ones which skipped the
+    /// write-ahead log.  The current directory is then renamed to `old_path`
+    /// and `new_path` is renamed to the current path which is where the new
+    /// database is opened with the same configuration and columns.  Settings
+    /// made after opening, e.g. key mappers, aren’t carried over except for
+    /// the name.
+    ///
+    /// Each rename is atomic but the database is unavailable from the flush
+    /// until the new database is opened, which for a large database may take
+    /// a while as it needs to read its manifest and open SST files.  If the
+    /// process crashes in between the renames, the database path doesn’t
+    /// exist and the old database is found at `old_path`.
+    ///
+    /// On error the database is closed either way and the directories are
+    /// left as follows:
+    /// - if a rename fails, the completed one, if any, is reverted so all
+    ///   directories are where they were and the database can be reopened;
+    /// - if the revert fails as well, the error of the rename is returned with
+    ///   the revert failure attached and the state is as after a crash in
+    ///   between the renames;
+    /// - if opening the new database fails, both renames are in place.
+    pub fn swap_directory(self, new_path: &Path, old_path: &Path) -> io::Result<Self> {
+        let path = self.db.path().to_path_buf();
+        let columns = self.cf_handles().map(|(col, _)| col).collect_vec();
+        for (_, cf_handle) in self.cf_handles() {
+            self.db.flush_cf(cf_handle).map_err(io::Error::other)?;
+        }
+        let store_config = self.store_config.clone();
+        let (temp, name) = (self.temp, self.name.clone());
+        drop(self);
+
+        tracing::info!(target: "db", ?path, ?new_path, ?old_path, "Swapping database directory");
+        std::fs::rename(&path, old_path)?;
+        if let Err(err) = std::fs::rename(new_path, &path) {
+            if let Err(revert_err) = std::fs::rename(old_path, &path) {
+                let msg = format!(
+                    "{err}; reverting rename of {} to {} failed as well: {revert_err}",
+                    path.display(),
+                    old_path.display()
+                );
+                return Err(io::Error::new(err.kind(), msg));
+            }
+            return Err(err);
+        }
+        // Make the renames durable.
+        if let Some(parent) = path.parent() {
+            std::fs::File::open(parent)?.sync_all()?;
+        }
+        let db = Self::open_with_columns(&path, &store_config, Mode::ReadWrite, temp, &columns)?;
+        Ok(db.with_name(name))
+    }
+
+    /// Returns memory currently used by memtables of all columns, i.e. what
+    /// [`StoreConfig::memtable_memory_budget`] limits.
+    ///
+    /// This includes immutable memtables waiting to be flushed.
+    pub fn memtable_memory_usage(&self) -> io::Result<u64> {
+        let mut usage = 0;
+        for (_, cf_handle) in self.cf_handles() {
+            let prop_name = ::rocksdb::properties::CUR_SIZE_ALL_MEM_TABLES;
+            let size = self.db.property_int_value_cf(cf_handle, prop_name);
+            usage += size.map_err(io::Error::other)?.unwrap_or(0);
+        }
+        Ok(usage)
     }
 
     /// Opens the database with given column families configured.
@@ -131,7 +421,7 @@ impl RocksDB {
         columns: &[DBCol],
     ) -> io::Result<(DB, Options)> {
         let options = rocksdb_options(store_config, mode);
-        let cfs = cf_descriptors(columns, store_config, temp);
+        let cfs = cf_descriptors(path, columns, store_config, temp)?;
         let db = if mode.read_only() {
             DB::open_cf_descriptors_read_only(&options, path, cfs, false)
         } else {
@@ -220,54 +510,403 @@ impl RocksDB {
         })
     }
 
+    /// Sets function used to map keys of given column before they are passed
+    /// to RocksDB.
+    ///
+    /// This is an experimental feature for prototyping key sharding schemes
+    /// without having to change all call sites.  The mapper is applied to keys
+    /// of all write operations and of point reads.  Since reads apply the same
+    /// mapping as writes, the mapper must be deterministic and a bijection
+    /// which doesn’t change between runs otherwise values won’t be found.
+    /// For delete range operations both bounds are mapped so the mapper must
+    /// additionally preserve ordering of keys (which e.g. adding a constant
+    /// prefix does).
+    ///
+    /// Iterators are not affected: they take prefixes and bounds as given and
+    /// return keys as stored, i.e. mapped.
+    #[cfg(feature = "key_mapper")]
+    pub fn set_key_mapper(&mut self, col: DBCol, mapper: KeyMapper) {
+        self.key_mappers[col] = Some(mapper);
+    }
+
+    /// Maps the key with mapper set with [`Self::set_key_mapper`] if any.
+    #[cfg(feature = "key_mapper")]
+    fn map_key<'k>(&self, col: DBCol, key: Cow<'k, [u8]>) -> Cow<'k, [u8]> {
+        match &self.key_mappers[col] {
+            Some(mapper) => Cow::Owned(mapper(&key)),
+            None => key,
+        }
+    }
+
+    #[cfg(not(feature = "key_mapper"))]
+    fn map_key<'k>(&self, _col: DBCol, key: Cow<'k, [u8]>) -> Cow<'k, [u8]> {
+        key
+    }
+
+    /// Reads raw value of given key, i.e. including the reference count in
+    /// reference counted columns.
+    fn get_raw_bytes_internal(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        self.get_raw_bytes_with_options(col, key, rocksdb_read_options())
+    }
+
+    /// Like [`Self::get_raw_bytes_internal`] but uses given read options.
+    fn get_raw_bytes_with_options(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        read_options: ReadOptions,
+    ) -> io::Result<Option<DBSlice<'_>>> {
+        let timer =
+            metrics::DATABASE_OP_LATENCY_HIST.with_label_values(&["get", col.into()]).start_timer();
+        let key = self.map_key(col, key.into());
+        let result = self
+            .db
+            .get_pinned_cf_opt(self.cf_handle(col)?, key, &read_options)
+            .map_err(io::Error::other)?
+            .map(DBSlice::from_rocksdb_slice);
+        timer.observe_duration();
+        Ok(result)
+    }
+
     /// Iterates over rocksDB storage.
     /// You can optionally specify the bounds to limit the range over which it will iterate.
-    /// You can specify EITHER the prefix, OR lower/upper bounds.
     /// Upper bound value is not included in the iteration.
-    /// Specifying both prefix and lower & upper bounds will result in undetermined behavior.
+    /// `mode` must be either `IteratorMode::Start` or `IteratorMode::End`
+    /// where the latter iterates in descending order.
     fn iter_raw_bytes_internal<'a>(
         &'a self,
         col: DBCol,
-        prefix: Option<&[u8]>,
         lower_bound: Option<&[u8]>,
         upper_bound: Option<&[u8]>,
+        mode: IteratorMode<'_>,
+    ) -> RocksDBIterator<'a> {
+        self.iter_raw_bytes_with_options(
+            col,
+            lower_bound,
+            upper_bound,
+            mode,
+            rocksdb_read_options(),
+        )
+    }
+
+    /// Like [`Self::iter_raw_bytes_internal`] but uses given read options,
+    /// e.g. ones reading from a snapshot.
+    fn iter_raw_bytes_with_options<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+        mode: IteratorMode<'_>,
+        mut read_options: ReadOptions,
     ) -> RocksDBIterator<'a> {
         let cf_handle = self.cf_handle(col).unwrap();
-        let mut read_options = rocksdb_read_options();
-        if prefix.is_some() && (lower_bound.is_some() || upper_bound.is_some()) {
-            panic!("Cannot iterate both with prefix and lower/upper bounds at the same time.");
-        }
-        if let Some(prefix) = prefix {
-            read_options.set_iterate_range(::rocksdb::PrefixRange(prefix));
-            // Note: prefix_same_as_start doesn’t do anything for us.  It takes
-            // effect only if prefix extractor is configured for the column
-            // family which is something we’re not doing.  Setting this option
-            // is therefore pointless.
-            //     read_options.set_prefix_same_as_start(true);
-        }
         if let Some(lower_bound) = lower_bound {
             read_options.set_iterate_lower_bound(lower_bound);
         }
         if let Some(upper_bound) = upper_bound {
             read_options.set_iterate_upper_bound(upper_bound);
         }
-        let iter = self.db.iterator_cf_opt(cf_handle, read_options, IteratorMode::Start);
-        RocksDBIterator(iter)
+        let iter = self.db.iterator_cf_opt(cf_handle, read_options, mode);
+        RocksDBIterator(Some(iter))
+    }
+}
+
+/// Function mapping keys before they are passed to RocksDB; see
+/// [`RocksDB::set_key_mapper`].
+#[cfg(feature = "key_mapper")]
+pub type KeyMapper = Box<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+/// Callback receiving RocksDB log lines; see [`RocksDB::open_with_logger`].
+pub type Logger = Box<dyn Fn(LogLevel, &str) + Send + Sync>;
+
+/// Background thread forwarding lines appended to RocksDB’s `LOG` file to
+/// a [`Logger`].  The thread is stopped when the object is dropped.
+struct LogTailer {
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl LogTailer {
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+    fn start(path: std::path::PathBuf, logger: Logger) -> io::Result<Self> {
+        use std::io::BufRead;
+        use std::sync::atomic::Ordering;
+
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread =
+            std::thread::Builder::new().name("rocksdb-log".to_string()).spawn(move || {
+                let mut reader = None;
+                let mut line = String::new();
+                loop {
+                    // Checked before reading so that lines written before the
+                    // stop are forwarded.
+                    let stopping = stopped.load(Ordering::Relaxed);
+                    if reader.is_none() {
+                        reader = std::fs::File::open(&path).ok().map(std::io::BufReader::new);
+                    }
+                    // A line without the terminating new line character is kept
+                    // in the buffer until the rest of it is written.
+                    while let Some(Ok(len)) =
+                        reader.as_mut().map(|reader| reader.read_line(&mut line))
+                    {
+                        if len == 0 || !line.ends_with('\n') {
+                            break;
+                        }
+                        let text = line.trim_end();
+                        logger(log_line_level(text), text);
+                        line.clear();
+                    }
+                    if stopping {
+                        break;
+                    }
+                    std::thread::sleep(Self::POLL_INTERVAL);
+                }
+            })?;
+        Ok(Self { stop, thread: Some(thread) })
+    }
+}
+
+impl Drop for LogTailer {
+    fn drop(&mut self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Handle of a background thread compacting a column periodically; see
+/// [`RocksDB::enable_periodic_compaction`].  The thread is stopped when the
+/// handle is dropped.
+pub struct PeriodicCompaction {
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for PeriodicCompaction {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns level of a line of RocksDB’s log.  RocksDB marks lines with
+/// their level, e.g. `[WARN]`, unless the level is info.
+fn log_line_level(line: &str) -> LogLevel {
+    [
+        ("[DEBUG] ", LogLevel::Debug),
+        ("[WARN] ", LogLevel::Warn),
+        ("[ERROR] ", LogLevel::Error),
+        ("[FATAL] ", LogLevel::Fatal),
+        ("[HEADER] ", LogLevel::Header),
+    ]
+    .into_iter()
+    .find_map(|(marker, level)| line.contains(marker).then_some(level))
+    .unwrap_or(LogLevel::Info)
+}
+
+/// Outcome of [`RocksDB::repair`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Number of SST files in the database after the repair.
+    pub sst_files: usize,
+    /// Files moved to the `lost` directory by the repair.  Their data is no
+    /// longer part of the database.
+    pub lost_files: Vec<std::path::PathBuf>,
+}
+
+/// Error returned by [`RocksDB::try_compact_column`] if the column is already
+/// being compacted.
+#[derive(Debug)]
+pub struct CompactionInProgress {
+    pub col: DBCol,
+}
+
+impl std::fmt::Display for CompactionInProgress {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "compaction of {} is already in progress", self.col)
+    }
+}
+
+impl std::error::Error for CompactionInProgress {}
+
+/// Error returned when writing to a column which the database hasn’t been
+/// opened with, e.g. because the column is unknown to this build or the
+/// database has been opened with a subset of columns.  Returned wrapped in
+/// an [`io::Error`] of [`io::ErrorKind::NotFound`] kind.
+#[derive(Debug)]
+pub struct ColumnNotOpen {
+    pub col: DBCol,
+}
+
+impl std::fmt::Display for ColumnNotOpen {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "column {} isn’t open in this database", self.col)
+    }
+}
+
+impl std::error::Error for ColumnNotOpen {}
+
+/// Guard returned by [`RocksDB::pause_background_work`] which resumes
+/// automatic compactions when dropped.
+#[must_use]
+pub struct BackgroundPauseGuard<'a> {
+    db: &'a RocksDB,
+}
+
+impl Drop for BackgroundPauseGuard<'_> {
+    fn drop(&mut self) {
+        let mut pauses = self.db.background_pauses.lock().unwrap();
+        *pauses -= 1;
+        if *pauses == 0 {
+            match self.db.set_auto_compactions(true) {
+                Ok(()) => tracing::info!(target: "db", "Resumed automatic compactions"),
+                Err(err) => warn!(target: "db", %err, "Failed to resume automatic compactions"),
+            }
+        }
     }
 }
 
-struct RocksDBIterator<'a>(rocksdb::DBIteratorWithThreadMode<'a, DB>);
+/// Iterator over a column which stops after the first error; see
+/// [`crate::db::DBIterator`].  The underlying iterator is dropped once it
+/// fails.
+struct RocksDBIterator<'a>(Option<rocksdb::DBIteratorWithThreadMode<'a, DB>>);
 
 impl<'a> Iterator for RocksDBIterator<'a> {
     type Item = io::Result<(Box<[u8]>, Box<[u8]>)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Some(self.0.next()?.map_err(io::Error::other))
+        match self.0.as_mut()?.next()? {
+            Ok(item) => Some(Ok(item)),
+            Err(err) => {
+                self.0 = None;
+                Some(Err(io::Error::other(err)))
+            }
+        }
     }
 }
 
 impl<'a> std::iter::FusedIterator for RocksDBIterator<'a> {}
 
+impl RocksDB {
+    /// Calls `f` with a read-only view of the database as of now.
+    ///
+    /// All reads done through the view, from any column, observe the same
+    /// state of the database regardless of writes happening concurrently.
+    /// The snapshot is released once `f` returns.  Writes through the view
+    /// fail.
+    pub fn with_snapshot<R>(&self, f: impl FnOnce(&dyn Database) -> R) -> io::Result<R> {
+        let snapshot = SnapshotDB { db: self, snapshot: self.db.snapshot() };
+        Ok(f(&snapshot))
+    }
+}
+
+/// Read-only view of a [`RocksDB`] at a snapshot; see
+/// [`RocksDB::with_snapshot`].
+struct SnapshotDB<'a> {
+    db: &'a RocksDB,
+    snapshot: ::rocksdb::Snapshot<'a>,
+}
+
+impl<'a> SnapshotDB<'a> {
+    fn read_options(&self) -> ReadOptions {
+        let mut read_options = rocksdb_read_options();
+        read_options.set_snapshot(&self.snapshot);
+        read_options
+    }
+
+    fn iter_internal(
+        &self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+        mode: IteratorMode<'_>,
+    ) -> RocksDBIterator<'_> {
+        let read_options = self.read_options();
+        self.db.iter_raw_bytes_with_options(col, lower_bound, upper_bound, mode, read_options)
+    }
+}
+
+impl<'a> Database for SnapshotDB<'a> {
+    fn name(&self) -> &str {
+        self.db.name()
+    }
+
+    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        let key = self.db.map_key(col, key.into());
+        Ok(self
+            .db
+            .db
+            .get_pinned_cf_opt(self.db.cf_handle(col)?, key, &self.read_options())
+            .map_err(io::Error::other)?
+            .map(DBSlice::from_rocksdb_slice))
+    }
+
+    fn iter_raw_bytes<'b>(&'b self, col: DBCol) -> DBIterator<'b> {
+        Box::new(self.iter_internal(col, None, None, IteratorMode::Start))
+    }
+
+    fn iter<'b>(&'b self, col: DBCol) -> DBIterator<'b> {
+        refcount::iter_with_rc_logic(col, self.iter_internal(col, None, None, IteratorMode::Start))
+    }
+
+    fn iter_prefix<'b>(&'b self, col: DBCol, key_prefix: &'b [u8]) -> DBIterator<'b> {
+        let upper_bound = super::prefix_upper_bound(key_prefix);
+        let iter =
+            self.iter_internal(col, Some(key_prefix), upper_bound.as_deref(), IteratorMode::Start);
+        refcount::iter_with_rc_logic(col, iter)
+    }
+
+    fn iter_range<'b>(
+        &'b self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'b> {
+        let iter = self.iter_internal(col, lower_bound, upper_bound, IteratorMode::Start);
+        refcount::iter_with_rc_logic(col, iter)
+    }
+
+    fn iter_range_rev<'b>(
+        &'b self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'b> {
+        let iter = self.iter_internal(col, lower_bound, upper_bound, IteratorMode::End);
+        refcount::iter_with_rc_logic(col, iter)
+    }
+
+    fn write(&self, _batch: DBTransaction) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "database snapshot is read-only"))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.db.flush()
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        self.db.compact()
+    }
+
+    fn get_store_statistics(&self) -> Option<StoreStatistics> {
+        self.db.get_store_statistics()
+    }
+
+    fn create_checkpoint(
+        &self,
+        _path: &std::path::Path,
+        _columns_to_keep: Option<&[DBCol]>,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("can’t create a checkpoint of a database snapshot")
+    }
+}
+
 impl RocksDB {
     /// Returns ranges of keys in a given column family.
     ///
@@ -291,71 +930,67 @@ impl RocksDB {
         }
     }
 
-    pub fn compact_column(&self, col: DBCol) -> io::Result<()> {
-        let none = Option::<&[u8]>::None;
-        tracing::info!(target: "db", column = %col, "Compact column");
-        self.db.compact_range_cf(self.cf_handle(col)?, none, none);
-        Ok(())
-    }
-}
-
-impl Database for RocksDB {
-    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
-        let timer =
-            metrics::DATABASE_OP_LATENCY_HIST.with_label_values(&["get", col.into()]).start_timer();
-        let read_options = rocksdb_read_options();
-        let result = self
-            .db
-            .get_pinned_cf_opt(self.cf_handle(col)?, key, &read_options)
-            .map_err(io::Error::other)?
-            .map(DBSlice::from_rocksdb_slice);
-        timer.observe_duration();
-        Ok(result)
-    }
-
-    fn iter_raw_bytes(&self, col: DBCol) -> DBIterator {
-        Box::new(self.iter_raw_bytes_internal(col, None, None, None))
-    }
-
-    fn iter(&self, col: DBCol) -> DBIterator {
-        refcount::iter_with_rc_logic(col, self.iter_raw_bytes_internal(col, None, None, None))
-    }
-
-    fn iter_prefix(&self, col: DBCol, key_prefix: &[u8]) -> DBIterator {
-        let iter = self.iter_raw_bytes_internal(col, Some(key_prefix), None, None);
-        refcount::iter_with_rc_logic(col, iter)
+    /// Fails with [`ColumnNotOpen`] error if the transaction writes to
+    /// a column which the database hasn’t been opened with.
+    fn check_columns_open(&self, transaction: &DBTransaction) -> io::Result<()> {
+        match transaction.ops.iter().find(|op| self.cf_handles[op.col()].is_none()) {
+            Some(op) => {
+                Err(io::Error::new(io::ErrorKind::NotFound, ColumnNotOpen { col: op.col() }))
+            }
+            None => Ok(()),
+        }
     }
 
-    fn iter_range<'a>(
-        &'a self,
-        col: DBCol,
-        lower_bound: Option<&[u8]>,
-        upper_bound: Option<&[u8]>,
-    ) -> DBIterator<'a> {
-        let iter = self.iter_raw_bytes_internal(col, None, lower_bound, upper_bound);
-        refcount::iter_with_rc_logic(col, iter)
+    /// Writes the transaction as a single batch failing if it exceeds
+    /// [`StoreConfig::max_write_batch_size`].  The caller must hold the write
+    /// lock.
+    fn write_unsplit(&self, transaction: DBTransaction) -> io::Result<()> {
+        self.check_columns_open(&transaction)?;
+        if cfg!(debug_assertions) {
+            transaction.check_rc_ops()?;
+        }
+        let size = transaction.size_bytes();
+        match self.max_write_batch_size {
+            Some(max_bytes) if size > max_bytes => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("write batch of {size} bytes exceeds the {max_bytes}-byte limit"),
+            )),
+            _ => self.write_batch(transaction),
+        }
     }
 
-    fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+    /// Writes the transaction as a single RocksDB write batch.
+    fn write_batch(&self, transaction: DBTransaction) -> io::Result<()> {
+        let use_wal = transaction.ops.iter().any(|op| !self.regenerable_columns[op.col()]);
+        // Number of operations and bytes per column.  Only gathered if slow
+        // writes are logged.
+        let mut breakdown = self
+            .slow_write_threshold
+            .map(|_| enum_map::EnumMap::<DBCol, (usize, usize)>::default());
         let mut batch = WriteBatch::default();
         for op in transaction.ops {
+            if let Some(breakdown) = breakdown.as_mut() {
+                let entry = &mut breakdown[op.col()];
+                entry.0 += 1;
+                entry.1 += op.size_bytes();
+            }
             match op {
                 DBOp::Set { col, key, value } => {
-                    batch.put_cf(self.cf_handle(col)?, key, value);
+                    batch.put_cf(self.cf_handle(col)?, self.map_key(col, key.into()), value);
                 }
                 DBOp::Insert { col, key, value } => {
                     if cfg!(debug_assertions) {
-                        if let Ok(Some(old_value)) = self.get_raw_bytes(col, &key) {
+                        if let Ok(Some(old_value)) = self.get_raw_bytes_internal(col, &key) {
                             super::assert_no_overwrite(col, &key, &value, &*old_value)
                         }
                     }
-                    batch.put_cf(self.cf_handle(col)?, key, value);
+                    batch.put_cf(self.cf_handle(col)?, self.map_key(col, key.into()), value);
                 }
                 DBOp::UpdateRefcount { col, key, value } => {
-                    batch.merge_cf(self.cf_handle(col)?, key, value);
+                    batch.merge_cf(self.cf_handle(col)?, self.map_key(col, key.into()), value);
                 }
                 DBOp::Delete { col, key } => {
-                    batch.delete_cf(self.cf_handle(col)?, key);
+                    batch.delete_cf(self.cf_handle(col)?, self.map_key(col, key.into()));
                 }
                 DBOp::DeleteAll { col } => {
                     let cf_handle = self.cf_handle(col)?;
@@ -367,511 +1002,2948 @@ impl Database for RocksDB {
                     }
                 }
                 DBOp::DeleteRange { col, from, to } => {
+                    if from > to {
+                        // RocksDB doesn’t define what happens with an
+                        // inverted range so treat it as an empty one.
+                        warn!(
+                            target: "db",
+                            %col,
+                            from = %StorageKey(&from),
+                            to = %StorageKey(&to),
+                            "Ignoring DeleteRange with from > to"
+                        );
+                        continue;
+                    }
+                    let from = self.map_key(col, from.into());
+                    let to = self.map_key(col, to.into());
                     batch.delete_range_cf(self.cf_handle(col)?, from, to);
                 }
             }
         }
-        self.db.write(batch).map_err(io::Error::other)
+        let (Some(threshold), Some(breakdown)) = (self.slow_write_threshold, breakdown) else {
+            return self.write_rocksdb_batch(batch, use_wal);
+        };
+        let started = std::time::Instant::now();
+        let result = self.write_rocksdb_batch(batch, use_wal);
+        let elapsed = started.elapsed();
+        if elapsed > threshold {
+            let columns = breakdown
+                .iter()
+                .filter(|(_, (ops, _))| *ops > 0)
+                .map(|(col, (ops, bytes))| format!("{col}: {ops} ops, {bytes} bytes"))
+                .join("; ");
+            warn!(
+                target: "db",
+                ?elapsed,
+                ops = breakdown.values().map(|(ops, _)| ops).sum::<usize>(),
+                size = breakdown.values().map(|(_, bytes)| bytes).sum::<usize>(),
+                columns = %columns,
+                "Slow write"
+            );
+        }
+        result
     }
 
-    fn compact(&self) -> io::Result<()> {
-        for col in DBCol::iter() {
-            self.compact_column(col)?;
+    /// Writes the batch to RocksDB optionally skipping the write-ahead log.
+    fn write_rocksdb_batch(&self, batch: WriteBatch, use_wal: bool) -> io::Result<()> {
+        if use_wal {
+            self.db.write(batch)
+        } else {
+            let mut write_options = WriteOptions::default();
+            write_options.disable_wal(true);
+            self.db.write_opt(batch, &write_options)
         }
-        Ok(())
+        .map_err(io::Error::other)
     }
 
-    fn flush(&self) -> io::Result<()> {
-        // Need to iterator over all CFs because the normal `flush()` only
-        // flushes the default column family.
-        for col in DBCol::iter() {
-            self.db.flush_cf(self.cf_handle(col)?).map_err(io::Error::other)?;
-        }
-        Ok(())
+    /// Compacts given column blocking until the compaction finishes.
+    ///
+    /// If another manual compaction of the column is already running, waits
+    /// for it to finish before starting a new one.  Use
+    /// [`Self::try_compact_column`] to fail instead.
+    pub fn compact_column(&self, col: DBCol) -> io::Result<()> {
+        let _guard = self.compaction_locks[col].lock().unwrap();
+        self.compact_column_locked(col)
     }
 
-    /// Trying to get
-    /// 1. RocksDB statistics
-    /// 2. Selected RockdDB properties for column families
-    fn get_store_statistics(&self) -> Option<StoreStatistics> {
-        let mut result = StoreStatistics { data: vec![] };
-        if let Some(stats_str) = self.db_opt.get_statistics() {
-            if let Err(err) = parse_statistics(&stats_str, &mut result) {
-                warn!(target: "store", "Failed to parse store statistics: {:?}", err);
+    /// Compacts given column unless a manual compaction of it is already
+    /// running in which case returns [`CompactionInProgress`] error.
+    pub fn try_compact_column(&self, col: DBCol) -> io::Result<()> {
+        let _guard = match self.compaction_locks[col].try_lock() {
+            Ok(guard) => guard,
+            Err(std::sync::TryLockError::WouldBlock) => {
+                return Err(io::Error::other(CompactionInProgress { col }))
             }
-        }
-        self.get_cf_statistics(&mut result);
-        if result.data.is_empty() {
-            None
-        } else {
-            Some(result)
-        }
+            Err(std::sync::TryLockError::Poisoned(err)) => panic!("{err}"),
+        };
+        self.compact_column_locked(col)
     }
 
-    fn create_checkpoint(
-        &self,
-        path: &std::path::Path,
-        columns_to_keep: Option<&[DBCol]>,
-    ) -> anyhow::Result<()> {
-        let _span =
-            tracing::info_span!(target: "state_snapshot", "create_checkpoint", ?path).entered();
-        let cp = ::rocksdb::checkpoint::Checkpoint::new(&self.db)?;
-        cp.create_checkpoint(path)
-            .with_context(|| format!("failed to create checkpoint at {}", path.display()))?;
+    /// Starts a background thread compacting given column every `interval`.
+    ///
+    /// Meant for columns with little traffic where automatic compaction
+    /// rarely triggers so deleted keys, e.g. ones whose reference count
+    /// dropped to zero, linger as tombstones.  Each run compacts the whole
+    /// column, i.e. reads and rewrites all its data, so the interval should
+    /// be long compared to how long that takes.  A run is skipped if a manual
+    /// compaction of the column is already in progress.
+    ///
+    /// The thread holds only a weak reference and exits once the database is
+    /// closed.  It also stops when the returned handle is dropped.
+    pub fn enable_periodic_compaction(
+        self: &std::sync::Arc<Self>,
+        col: DBCol,
+        interval: std::time::Duration,
+    ) -> io::Result<PeriodicCompaction> {
+        use std::sync::mpsc::RecvTimeoutError;
 
-        let Some(columns_to_keep) = columns_to_keep else {
-            return Ok(());
-        };
-        let opts = common_rocksdb_options();
-        let cfs =
-            cf_descriptors(&DBCol::iter().collect_vec(), &StoreConfig::default(), Temperature::Hot);
-        let mut db = DB::open_cf_descriptors(&opts, path, cfs)
-            .with_context(|| format!("failed to open checkpoint at {}", path.display()))?;
-        for col in DBCol::iter() {
-            if !columns_to_keep.contains(&col) {
-                if col == DBCol::DbVersion {
-                    // We need to keep DbVersion because it's expected to be there when
-                    // we check the metadata in DBOpener::get_metadata()
-                    tracing::debug!(
-                        target: "store",
-                        "create_checkpoint called with columns to keep not including DBCol::DbVersion. Including it anyway."
-                    );
-                    continue;
+        self.cf_handle(col)?;
+        let db = std::sync::Arc::downgrade(self);
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::Builder::new().name("rocksdb-compaction".to_string()).spawn(
+            move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let Some(db) = db.upgrade() else {
+                        break;
+                    };
+                    if let Err(err) = db.try_compact_column(col) {
+                        let in_progress =
+                            err.get_ref().is_some_and(|err| err.is::<CompactionInProgress>());
+                        if !in_progress {
+                            warn!(target: "db", %col, %err, "Periodic compaction failed");
+                        }
+                    }
                 }
-                db.drop_cf(col_name(col)).with_context(|| {
-                    format!(
-                        "failed to drop column family {:?} from checkpoint at {}",
-                        col,
-                        path.display()
-                    )
-                })?;
-            }
-        }
+            },
+        )?;
+        Ok(PeriodicCompaction { stop: Some(stop), thread: Some(thread) })
+    }
+
+    /// Returns whether a manual compaction of given column is running.
+    pub fn is_compacting(&self, col: DBCol) -> bool {
+        matches!(self.compaction_locks[col].try_lock(), Err(std::sync::TryLockError::WouldBlock))
+    }
+
+    fn compact_column_locked(&self, col: DBCol) -> io::Result<()> {
+        let none = Option::<&[u8]>::None;
+        let _span = tracing::info_span!(target: "db", "compact_column", %col).entered();
+        tracing::info!(target: "db", column = %col, "Compact column");
+        self.db.compact_range_cf(self.cf_handle(col)?, none, none);
         Ok(())
     }
 }
 
-fn cf_descriptors(
-    columns: &[DBCol],
-    store_config: &StoreConfig,
-    temp: Temperature,
-) -> Vec<rocksdb::ColumnFamilyDescriptor> {
-    columns
-        .iter()
-        .copied()
-        .map(|col| {
-            rocksdb::ColumnFamilyDescriptor::new(
-                col_name(col),
-                rocksdb_column_options(col, store_config, temp),
-            )
-        })
-        .collect::<Vec<_>>()
-}
+impl Database for RocksDB {
+    fn name(&self) -> &str {
+        &self.name
+    }
 
-/// DB level options
-fn common_rocksdb_options() -> Options {
-    let mut opts = Options::default();
+    /// In strict mode, logs a warning when called on a reference counted
+    /// column; see [`StoreConfig::strict_rc_reads`].
+    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        if self.strict_rc_reads && col.is_rc() {
+            warn!(
+                target: "db",
+                %col,
+                key = %StorageKey(key),
+                "Raw read of a reference counted column; use get_with_rc_stripped unless the \
+                 reference count is needed"
+            );
+        }
+        self.get_raw_bytes_internal(col, key)
+    }
 
-    set_compression_options(&mut opts);
-    opts.set_use_fsync(false);
-    opts.set_keep_log_file_num(1);
-    opts.set_bytes_per_sync(bytesize::MIB);
-    opts.set_write_buffer_size(256 * bytesize::MIB as usize);
-    opts.set_max_bytes_for_level_base(256 * bytesize::MIB);
+    fn get_with_rc_stripped(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        assert!(col.is_rc());
+        Ok(self.get_raw_bytes_internal(col, key)?.and_then(DBSlice::strip_refcount))
+    }
 
-    if cfg!(feature = "single_thread_rocksdb") {
-        opts.set_disable_auto_compactions(true);
-        opts.set_max_background_jobs(0);
-        opts.set_stats_dump_period_sec(0);
-        opts.set_stats_persist_period_sec(0);
-        opts.set_level_zero_slowdown_writes_trigger(-1);
-        opts.set_level_zero_file_num_compaction_trigger(-1);
-        opts.set_level_zero_stop_writes_trigger(100000000);
-    } else {
-        opts.increase_parallelism(std::cmp::max(1, num_cpus::get() as i32 / 2));
-        opts.set_max_total_wal_size(bytesize::GIB);
+    fn get_with_rc(&self, col: DBCol, key: &[u8]) -> io::Result<Option<(DBSlice<'_>, i64)>> {
+        assert!(col.is_rc());
+        Ok(self.get_raw_bytes_internal(col, key)?.and_then(DBSlice::split_refcount))
     }
-    opts
-}
 
-fn rocksdb_options(store_config: &StoreConfig, mode: Mode) -> Options {
-    let mut opts = common_rocksdb_options();
-    opts.create_missing_column_families(mode.read_write());
-    opts.create_if_missing(mode.can_create());
-    opts.set_max_open_files(store_config.max_open_files.try_into().unwrap_or(i32::MAX));
-    // TODO(mina86): Perhaps enable statistics even in read-only mode?
-    if mode.read_write() && store_config.enable_statistics {
-        // Rust API doesn't permit choosing stats level. The default stats level
-        // is `kExceptDetailedTimers`, which is described as: "Collects all
-        // stats except time inside mutex lock AND time spent on compression."
-        opts.enable_statistics();
-        // Disabling dumping stats to files because the stats are exported to
-        // Prometheus.
-        opts.set_stats_persist_period_sec(0);
-        opts.set_stats_dump_period_sec(0);
+    fn get_refcount(&self, col: DBCol, key: &[u8]) -> io::Result<i64> {
+        assert!(col.is_rc());
+        let value = self.get_raw_bytes_internal(col, key)?;
+        Ok(value.map_or(0, |value| refcount::decode_value_with_rc(&value).1))
     }
 
-    opts
-}
+    fn iter_raw_bytes(&self, col: DBCol) -> DBIterator {
+        Box::new(self.iter_raw_bytes_internal(col, None, None, IteratorMode::Start))
+    }
 
-fn rocksdb_read_options() -> ReadOptions {
-    let mut read_options = ReadOptions::default();
-    read_options.set_verify_checksums(false);
-    read_options
-}
+    fn iter(&self, col: DBCol) -> DBIterator {
+        refcount::iter_with_rc_logic(
+            col,
+            self.iter_raw_bytes_internal(col, None, None, IteratorMode::Start),
+        )
+    }
 
-/// If true then we enable caching of index blocks inside block cache
-fn use_block_cache_for_index_and_filter_blocks(db_col: DBCol) -> bool {
-    match db_col {
-        DBCol::FlatState => false,
-        _ => true,
+    fn get_with_opts(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        opts: &crate::db::ReadOptions,
+    ) -> io::Result<Option<DBSlice<'_>>> {
+        self.get_raw_bytes_with_options(col, key, rocksdb_read_options_with(opts))
     }
-}
 
-fn rocksdb_block_based_options(store_config: &StoreConfig, db_col: DBCol) -> BlockBasedOptions {
-    let cache_size = store_config.col_cache_size(db_col);
+    fn iter_with_opts<'a>(&'a self, col: DBCol, opts: &crate::db::ReadOptions) -> DBIterator<'a> {
+        let read_options = rocksdb_read_options_with(opts);
+        let mode = IteratorMode::Start;
+        let iter = self.iter_raw_bytes_with_options(col, None, None, mode, read_options);
+        refcount::iter_with_rc_logic(col, iter)
+    }
 
-    let mut block_opts = BlockBasedOptions::default();
-    block_opts.set_block_size(store_config.block_size.as_u64().try_into().unwrap());
-    // We create block_cache for each of the columns, so the total cache size is (num_of_columns - 2) * 32MiB
-    // Plus the 128MiB from FlatState and 512MiB from State columns
-    block_opts.set_block_cache(&Cache::new_lru_cache(cache_size.as_u64().try_into().unwrap()));
-    if use_block_cache_for_index_and_filter_blocks(db_col) {
-        block_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
-        block_opts.set_cache_index_and_filter_blocks(true);
-    } else {
-        block_opts.set_cache_index_and_filter_blocks(false);
+    fn iter_prefix(&self, col: DBCol, key_prefix: &[u8]) -> DBIterator {
+        let upper_bound = super::prefix_upper_bound(key_prefix);
+        let iter = self.iter_raw_bytes_internal(
+            col,
+            Some(key_prefix),
+            upper_bound.as_deref(),
+            IteratorMode::Start,
+        );
+        refcount::iter_with_rc_logic(col, iter)
     }
-    block_opts.set_bloom_filter(10.0, true);
 
-    block_opts
-}
+    fn iter_range<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        let iter = self.iter_raw_bytes_internal(col, lower_bound, upper_bound, IteratorMode::Start);
+        refcount::iter_with_rc_logic(col, iter)
+    }
 
-fn rocksdb_column_options(col: DBCol, store_config: &StoreConfig, temp: Temperature) -> Options {
-    let mut opts = Options::default();
-    set_compression_options(&mut opts);
-    opts.set_level_compaction_dynamic_level_bytes(true);
-    opts.set_block_based_table_factory(&rocksdb_block_based_options(store_config, col));
+    fn iter_range_rev<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        let iter = self.iter_raw_bytes_internal(col, lower_bound, upper_bound, IteratorMode::End);
+        refcount::iter_with_rc_logic(col, iter)
+    }
 
-    // Note that this function changes a lot of rustdb parameters including:
-    //      write_buffer_size = memtable_memory_budget / 4
-    //      min_write_buffer_number_to_merge = 2
-    //      max_write_buffer_number = 6
-    //      level0_file_num_compaction_trigger = 2
-    //      target_file_size_base = memtable_memory_budget / 8
-    //      max_bytes_for_level_base = memtable_memory_budget
-    //      compaction_style = kCompactionStyleLevel
-    // Also it sets compression_per_level in a way that the first 2 levels have no compression and
-    // the rest use LZ4 compression.
-    // See the implementation here:
-    //      https://github.com/facebook/rocksdb/blob/c18c4a081c74251798ad2a1abf83bad417518481/options/options.cc#L588.
-    let memtable_memory_budget = 128 * bytesize::MIB as usize;
-    opts.optimize_level_style_compaction(memtable_memory_budget);
+    /// Fails with [`ColumnNotOpen`] error without writing anything if any of
+    /// the columns isn’t open.
+    fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+        self.check_columns_open(&transaction)?;
+        if cfg!(debug_assertions) {
+            transaction.check_rc_ops()?;
+        }
+        let _guard = self.write_lock.read().unwrap();
+        match self.max_write_batch_size {
+            Some(max_bytes)
+                if transaction.ops.len() > 1 && transaction.size_bytes() > max_bytes =>
+            {
+                warn!(
+                    target: "db",
+                    size = transaction.size_bytes(),
+                    max_bytes,
+                    "Splitting oversized write batch; the write is not atomic"
+                );
+                for chunk in transaction.into_chunks(max_bytes) {
+                    self.write_batch(chunk)?;
+                }
+                Ok(())
+            }
+            _ => self.write_batch(transaction),
+        }
+    }
 
-    opts.set_target_file_size_base(64 * bytesize::MIB);
-    if temp == Temperature::Hot && col.is_rc() {
-        opts.set_merge_operator("refcount merge", RocksDB::refcount_merge, RocksDB::refcount_merge);
-        opts.set_compaction_filter("empty value filter", RocksDB::empty_value_compaction_filter);
+    /// Returns the latest sequence number after the write.  If there are
+    /// concurrent writes, it may include them but it’s never smaller than the
+    /// sequence number of this write.
+    fn write_with_id(&self, transaction: DBTransaction) -> io::Result<WriteId> {
+        self.write(transaction)?;
+        Ok(WriteId(self.db.latest_sequence_number()))
     }
-    opts
-}
 
-fn set_compression_options(opts: &mut Options) {
-    opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
-    opts.set_bottommost_compression_type(rocksdb::DBCompressionType::Zstd);
-    // RocksDB documenation says that 16KB is a typical dictionary size.
-    // We've empirically tuned the dicionary size to twice of that 'typical' size.
-    // Having train data size x100 from dictionary size is a recommendation from RocksDB.
-    // See: https://rocksdb.org/blog/2021/05/31/dictionary-compression.html?utm_source=dbplatz
-    let dict_size = 2 * 16384;
-    let max_train_bytes = dict_size * 100;
-    // We use default parameters of RocksDB here:
-    //      window_bits is -14 and is unused (Zlib-specific parameter),
-    //      compression_level is 32767 meaning the default compression level for ZSTD,
-    //      compression_strategy is 0 and is unused (Zlib-specific parameter).
-    // See: https://github.com/facebook/rocksdb/blob/main/include/rocksdb/advanced_options.h#L176:
-    opts.set_bottommost_compression_options(
-        /*window_bits */ -14, /*compression_level */ 32767,
-        /*compression_strategy */ 0, dict_size, /*enabled */ true,
-    );
-    opts.set_bottommost_zstd_max_train_bytes(max_train_bytes, true);
-}
+    fn write_atomic(&self, transaction: DBTransaction) -> io::Result<()> {
+        let _guard = self.write_lock.read().unwrap();
+        self.write_unsplit(transaction)
+    }
 
-impl RocksDB {
-    /// Blocks until all RocksDB instances (usually 0 or 1) gracefully shutdown.
-    pub fn block_until_all_instances_are_dropped() {
-        instance_tracker::block_until_all_instances_are_closed();
+    /// Holds the write lock exclusively so no write, plain ones included, can
+    /// happen between reading the value and writing the new one.
+    fn update(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        crate::db::check_not_rc(col, "update")?;
+        let cf_handle = self.cf_handle(col)?;
+        let _guard = self.write_lock.write().unwrap();
+        let value = self.get_raw_bytes(col, key)?;
+        let key = self.map_key(col, key.into());
+        let mut batch = WriteBatch::default();
+        match f(value.as_deref()) {
+            Some(value) => batch.put_cf(cf_handle, key, value),
+            None => batch.delete_cf(cf_handle, key),
+        }
+        self.write_rocksdb_batch(batch, !self.regenerable_columns[col])
     }
 
-    /// Returns metadata of the database or `None` if the db doesn’t exist.
-    pub(crate) fn get_metadata(
-        path: &Path,
-        config: &StoreConfig,
-    ) -> io::Result<Option<metadata::DbMetadata>> {
-        if !path.join("CURRENT").is_file() {
-            return Ok(None);
+    /// Like [`Self::update`], holds the write lock exclusively while checking
+    /// the witnesses and writing the batch.  The batch is never split so
+    /// batches exceeding `max_write_batch_size` fail.
+    fn write_if_unchanged(
+        &self,
+        batch: DBTransaction,
+        witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> io::Result<bool> {
+        let _guard = self.write_lock.write().unwrap();
+        let get = |col, key: &[u8]| self.get_raw_bytes_internal(col, key);
+        if !crate::db::witnesses_hold(witnesses, get)? {
+            return Ok(false);
         }
-        // Specify only DBCol::DbVersion.  It’s ok to open db in read-only mode
-        // without specifying all column families but it’s an error to provide
-        // a descriptor for a column family which doesn’t exist.  This allows us
-        // to read the version without modifying the database before we figure
-        // out if there are any necessary migrations to perform.
-        let cols = [DBCol::DbVersion];
-        let db = Self::open_with_columns(path, config, Mode::ReadOnly, Temperature::Hot, &cols)?;
-        Some(metadata::DbMetadata::read(&db)).transpose()
+        self.write_unsplit(batch)?;
+        Ok(true)
     }
 
-    /// Gets every int property in CF_PROPERTY_NAMES for every column in DBCol.
-    fn get_cf_statistics(&self, result: &mut StoreStatistics) {
-        for prop_name in CF_PROPERTY_NAMES.deref() {
-            let values = self
-                .cf_handles()
-                .filter_map(|(col, handle)| {
-                    let prop = self.db.property_int_value_cf(handle, prop_name);
-                    Some(StatsValue::ColumnValue(col, prop.ok()?? as i64))
+    fn truncate_column(&self, col: DBCol) -> io::Result<()> {
+        let cf_handle = self.cf_handle(col)?;
+        let range = self.get_cf_key_range(cf_handle).map_err(io::Error::other)?;
+        let Some(range) = range else {
+            return Ok(());
+        };
+        // Same deletion as DeleteAll does but the range tombstone is compacted
+        // away immediately rather than left for the background compaction.
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(cf_handle, range.start(), range.end());
+        batch.delete_cf(cf_handle, range.end());
+        {
+            let _guard = self.write_lock.read().unwrap();
+            self.write_rocksdb_batch(batch, !self.regenerable_columns[col])?;
+        }
+        // single_thread_rocksdb makes compact hang forever.  The data is
+        // deleted either way; without background threads the tombstone is
+        // simply left in place like with DeleteAll.
+        if cfg!(feature = "single_thread_rocksdb") {
+            return Ok(());
+        }
+        self.compact_column(col)
+    }
+
+    /// Estimates number of keys in the range from SST files metadata.
+    ///
+    /// Sums number of entries of all SST files of the column whose key range
+    /// overlaps the requested range and adds all entries held in memtables.
+    /// Files which overlap the range only partially as well as memtables are
+    /// counted in full so this tends to overestimate.  Deleted keys which
+    /// haven’t been compacted yet may also be counted.
+    fn approx_keys_in_range(&self, col: DBCol, from: &[u8], to: &[u8]) -> io::Result<u64> {
+        use ::rocksdb::properties;
+        let cf_handle = self.cf_handle(col)?;
+        let cf_name = col_name(col);
+        let mut count = 0;
+        for file in self.db.live_files().map_err(io::Error::other)? {
+            if file.column_family_name != cf_name {
+                continue;
+            }
+            let overlaps = file.start_key.as_deref().map_or(true, |start| start < to)
+                && file.end_key.as_deref().map_or(true, |end| end >= from);
+            if overlaps {
+                count += file.num_entries.saturating_sub(file.num_deletions);
+            }
+        }
+        for prop_name in
+            [properties::NUM_ENTRIES_ACTIVE_MEM_TABLE, properties::NUM_ENTRIES_IMM_MEM_TABLES]
+        {
+            let entries = self.db.property_int_value_cf(cf_handle, prop_name);
+            count += entries.map_err(io::Error::other)?.unwrap_or(0);
+        }
+        Ok(count)
+    }
+
+    /// Iterates over the range with large readahead so that data blocks are
+    /// read sequentially and stored in the block cache.
+    fn prefetch_range(&self, col: DBCol, from: &[u8], to: &[u8]) -> io::Result<()> {
+        let cf_handle = self.cf_handle(col)?;
+        let mut read_options = rocksdb_read_options();
+        read_options.set_readahead_size(2 * bytesize::MIB as usize);
+        read_options.fill_cache(true);
+        read_options.set_iterate_lower_bound(from);
+        read_options.set_iterate_upper_bound(to);
+        let mut iter = self.db.raw_iterator_cf_opt(cf_handle, read_options);
+        iter.seek_to_first();
+        while iter.valid() {
+            iter.next();
+        }
+        iter.status().map_err(io::Error::other)
+    }
+
+    /// Large values aren’t copied out of RocksDB’s blocks.  They’re still
+    /// read from disk though, just not materialised as separate buffers.
+    fn iter_keys_and_small_values<'a>(
+        &'a self,
+        col: DBCol,
+        max_value_bytes: usize,
+    ) -> Box<dyn Iterator<Item = io::Result<(Box<[u8]>, Option<Box<[u8]>>, usize)>> + 'a> {
+        let cf_handle = match self.cf_handle(col) {
+            Ok(cf_handle) => cf_handle,
+            Err(err) => return Box::new(std::iter::once(Err(err))),
+        };
+        let mut iter = self.db.raw_iterator_cf_opt(cf_handle, rocksdb_read_options());
+        iter.seek_to_first();
+        let mut done = false;
+        Box::new(std::iter::from_fn(move || {
+            while !done {
+                let (Some(key), Some(value)) = (iter.key(), iter.value()) else {
+                    done = true;
+                    return iter.status().err().map(|err| Err(io::Error::other(err)));
+                };
+                let value =
+                    if col.is_rc() { refcount::decode_value_with_rc(value).0 } else { Some(value) };
+                let item: Option<(Box<[u8]>, Option<Box<[u8]>>, usize)> = value.map(|value| {
+                    let len = value.len();
+                    (key.into(), (len <= max_value_bytes).then(|| value.into()), len)
+                });
+                iter.next();
+                if let Some(item) = item {
+                    return Some(Ok(item));
+                }
+            }
+            None
+        }))
+    }
+
+    fn count_distinct_prefixes(&self, col: DBCol, prefix_len: usize) -> io::Result<u64> {
+        let mut iter = self.db.raw_iterator_cf_opt(self.cf_handle(col)?, rocksdb_read_options());
+        let mut count = 0;
+        iter.seek_to_first();
+        while let Some(key) = iter.key() {
+            if col.is_rc()
+                && iter.value().map_or(0, |value| refcount::decode_value_with_rc(value).1) <= 0
+            {
+                // The prefix may still have live keys further on.
+                iter.next();
+                continue;
+            }
+            count += 1;
+            let next = if key.len() < prefix_len {
+                // Longer keys starting with this one have different prefixes.
+                Some([key, &[0]].concat())
+            } else {
+                super::prefix_upper_bound(&key[..prefix_len])
+            };
+            match next {
+                Some(next) => iter.seek(next),
+                None => break,
+            }
+        }
+        iter.status().map_err(io::Error::other)?;
+        Ok(count)
+    }
+
+    /// Uses bloom filters to rule out absent keys and fetches the remaining
+    /// ones with a single multi-get.
+    fn contains_keys(&self, col: DBCol, keys: &[&[u8]]) -> io::Result<Vec<bool>> {
+        let cf_handle = self.cf_handle(col)?;
+        let keys = keys.iter().map(|key| self.map_key(col, (*key).into())).collect::<Vec<_>>();
+        let mut result = vec![false; keys.len()];
+        let candidates = (0..keys.len())
+            .filter(|&idx| self.db.key_may_exist_cf(cf_handle, &keys[idx]))
+            .collect::<Vec<_>>();
+        let read_options = rocksdb_read_options();
+        let values = self.db.batched_multi_get_cf_opt(
+            cf_handle,
+            candidates.iter().map(|&idx| &keys[idx]),
+            false,
+            &read_options,
+        );
+        for (idx, value) in candidates.into_iter().zip(values) {
+            let Some(value) = value.map_err(io::Error::other)? else {
+                continue;
+            };
+            result[idx] = !col.is_rc() || refcount::decode_value_with_rc(&value).1 > 0;
+        }
+        Ok(result)
+    }
+
+    /// Reads all keys with a single iterator which steps over short gaps
+    /// between consecutive keys and seeks over longer ones.  A key which after
+    /// mapping (see [`Self::set_key_mapper`]) sorts before the previous one is
+    /// sought from scratch.
+    fn get_sorted(
+        &self,
+        col: DBCol,
+        sorted_keys: &[&[u8]],
+    ) -> io::Result<Vec<Option<DBSlice<'_>>>> {
+        /// Number of steps taken before giving up and seeking to the key.
+        const MAX_STEPS: usize = 8;
+
+        crate::db::check_sorted(sorted_keys)?;
+        let timer = metrics::DATABASE_OP_LATENCY_HIST
+            .with_label_values(&["get_sorted", col.into()])
+            .start_timer();
+        let mut iter = self.db.raw_iterator_cf_opt(self.cf_handle(col)?, rocksdb_read_options());
+        let mut result = Vec::with_capacity(sorted_keys.len());
+        let mut prev: Option<Cow<[u8]>> = None;
+        for key in sorted_keys {
+            let key = self.map_key(col, (*key).into());
+            // The iterator is positioned at the first key not below the
+            // previous one so it can only move forward from there.
+            if prev.as_deref().is_some_and(|prev| prev <= &*key) {
+                for _ in 0..MAX_STEPS {
+                    if !iter.key().is_some_and(|current| current < &*key) {
+                        break;
+                    }
+                    iter.next();
+                }
+                if iter.key().is_some_and(|current| current < &*key) {
+                    iter.seek(&key);
+                }
+            } else {
+                iter.seek(&key);
+            }
+            let value = match (iter.key(), iter.value()) {
+                (Some(current), Some(value)) if current == &*key => {
+                    Some(DBSlice::from_vec(value.to_vec()))
+                }
+                _ => None,
+            };
+            result.push(value);
+            prev = Some(key);
+        }
+        iter.status().map_err(io::Error::other)?;
+        timer.observe_duration();
+        Ok(result)
+    }
+
+    fn level_file_counts(&self, col: DBCol) -> io::Result<Vec<usize>> {
+        let cf_name = col_name(col);
+        let mut counts = Vec::new();
+        for file in self.db.live_files().map_err(io::Error::other)? {
+            if file.column_family_name != cf_name {
+                continue;
+            }
+            let level = usize::try_from(file.level).unwrap_or(0);
+            if counts.len() <= level {
+                counts.resize(level + 1, 0);
+            }
+            counts[level] += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Iterates over key ranges covered by SST files at given level.
+    ///
+    /// RocksDB doesn’t allow reading a single SST file through the database so
+    /// this yields current values of all keys in the ranges.  That includes
+    /// keys whose newest version lives in a memtable or at another level if
+    /// they fall within the ranges.  The result is exact for the bottommost
+    /// level of a fully compacted column.
+    fn iter_level<'a>(&'a self, col: DBCol, level: usize) -> io::Result<DBIterator<'a>> {
+        self.cf_handle(col)?;
+        let cf_name = col_name(col);
+        let mut ranges = Vec::new();
+        for file in self.db.live_files().map_err(io::Error::other)? {
+            if file.column_family_name != cf_name || usize::try_from(file.level) != Ok(level) {
+                continue;
+            }
+            if let (Some(start), Some(end)) = (file.start_key, file.end_key) {
+                ranges.push((start, end));
+            }
+        }
+        ranges.sort();
+        // Files at level 0 may overlap so merge the ranges.
+        let mut merged = Vec::<(Vec<u8>, Vec<u8>)>::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= *last_end => {
+                    *last_end = std::cmp::max(std::mem::take(last_end), end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        Ok(Box::new(merged.into_iter().flat_map(move |(start, mut end)| {
+            // Upper bound is exclusive while file’s end key is the last key in it.
+            end.push(0);
+            self.iter_raw_bytes_internal(col, Some(&start), Some(&end), IteratorMode::Start)
+        })))
+    }
+
+    fn total_disk_size(&self) -> io::Result<u64> {
+        let files = self.db.live_files().map_err(io::Error::other)?;
+        let mut size = files.iter().map(|file| file.size as u64).sum::<u64>();
+        for entry in std::fs::read_dir(self.db.path())? {
+            let entry = entry?;
+            if entry.path().extension().is_some_and(|ext| ext == "log") {
+                size += entry.metadata()?.len();
+            }
+        }
+        Ok(size)
+    }
+
+    fn wal_sequence_range(&self) -> io::Result<Option<(u64, u64)>> {
+        // Reading updates since sequence number zero starts at the oldest
+        // batch still in the log.
+        let mut updates = self.db.get_updates_since(0).map_err(io::Error::other)?;
+        let Some(oldest) = updates.next() else { return Ok(None) };
+        let (oldest, _) = oldest.map_err(io::Error::other)?;
+        Ok(Some((oldest, self.db.latest_sequence_number())))
+    }
+
+    /// Computed from table properties aggregated over the column’s SST files
+    /// as raw key and value bytes divided by size of data blocks.  Data in
+    /// memtables isn’t included.  Since levels may be compressed differently,
+    /// the ratio is an average over the whole column.
+    fn compression_ratio(&self, col: DBCol) -> io::Result<Option<f64>> {
+        let cf_handle = self.cf_handle(col)?;
+        let prop_name = ::rocksdb::properties::AGGREGATED_TABLE_PROPERTIES;
+        let props = self.db.property_value_cf(cf_handle, prop_name).map_err(io::Error::other)?;
+        let Some(props) = props else { return Ok(None) };
+        // The properties are formatted as `name=value` pairs separated by
+        // semicolons.
+        let get = |name: &str| {
+            props.split(';').find_map(|prop| {
+                let (key, value) = prop.split_once('=')?;
+                (key.trim() == name).then(|| value.trim().parse::<u64>().ok()).flatten()
+            })
+        };
+        let (Some(key_size), Some(value_size), Some(data_size)) =
+            (get("raw key size"), get("raw value size"), get("data block size"))
+        else {
+            return Ok(None);
+        };
+        if data_size == 0 {
+            return Ok(None);
+        }
+        Ok(Some((key_size + value_size) as f64 / data_size as f64))
+    }
+
+    fn compaction_pending_bytes(&self, col: DBCol) -> io::Result<u64> {
+        let cf_handle = self.cf_handle(col)?;
+        let prop_name = ::rocksdb::properties::ESTIMATE_PENDING_COMPACTION_BYTES;
+        let bytes = self.db.property_int_value_cf(cf_handle, prop_name);
+        Ok(bytes.map_err(io::Error::other)?.unwrap_or(0))
+    }
+
+    /// Checks bloom filters and memtables first and otherwise looks for the
+    /// SST file at the lowest level whose key range covers the key.
+    ///
+    /// The level is a hint only: RocksDB doesn’t report which file a value was
+    /// read from so a file covering the key is assumed to hold it.  Returns
+    /// `None` if no such file exists.
+    fn key_location(&self, col: DBCol, key: &[u8]) -> io::Result<Option<KeyLocation>> {
+        let cf_handle = self.cf_handle(col)?;
+        let key = self.map_key(col, key.into());
+        if !self.db.key_may_exist_cf(cf_handle, &key) {
+            return Ok(Some(KeyLocation::Absent));
+        }
+        let read_options = rocksdb_read_options();
+        if self
+            .db
+            .get_pinned_cf_opt(cf_handle, &key, &read_options)
+            .map_err(io::Error::other)?
+            .is_none()
+        {
+            return Ok(Some(KeyLocation::Absent));
+        }
+
+        let mut read_options = rocksdb_read_options();
+        read_options.set_read_tier(ReadTier::Memtable);
+        let mut iter = self.db.raw_iterator_cf_opt(cf_handle, read_options);
+        iter.seek(&key);
+        if iter.valid() && iter.key() == Some(&key[..]) {
+            return Ok(Some(KeyLocation::MemTable));
+        }
+        iter.status().map_err(io::Error::other)?;
+
+        let cf_name = col_name(col);
+        let level = self
+            .db
+            .live_files()
+            .map_err(io::Error::other)?
+            .into_iter()
+            .filter(|file| {
+                file.column_family_name == cf_name
+                    && file.start_key.as_deref().map_or(true, |start| start <= &key[..])
+                    && file.end_key.as_deref().map_or(true, |end| end >= &key[..])
+            })
+            .map(|file| file.level)
+            .min();
+        Ok(level.map(|level| KeyLocation::Sst(level as u32)))
+    }
+
+    /// Seeks to keys evenly spaced between the smallest and the largest key of
+    /// the column rather than iterating over it.
+    ///
+    /// The synthetic keys are computed by interpolating the first eight bytes
+    /// following the common prefix of the smallest and the largest key so the
+    /// sample is skewed if keys aren’t uniformly distributed.  If multiple
+    /// seeks land on the same key, its value is returned once so the sample
+    /// may be smaller than requested.
+    fn sample_values(&self, col: DBCol, sample_size: usize) -> io::Result<Vec<Box<[u8]>>> {
+        if sample_size == 0 {
+            return Ok(Vec::new());
+        }
+        let Some((first, last)) = self.key_range(col)? else {
+            return Ok(Vec::new());
+        };
+        let prefix_len = first.iter().zip(last.iter()).take_while(|(a, b)| a == b).count();
+        let to_u64 = |key: &[u8]| {
+            let tail = &key[prefix_len..];
+            let len = tail.len().min(8);
+            let mut buf = [0; 8];
+            buf[..len].copy_from_slice(&tail[..len]);
+            u64::from_be_bytes(buf)
+        };
+        let (lo, hi) = (to_u64(&first), to_u64(&last));
+
+        let mut iter = self.db.raw_iterator_cf_opt(self.cf_handle(col)?, rocksdb_read_options());
+        let mut values = Vec::with_capacity(sample_size);
+        let mut prev_key: Option<Box<[u8]>> = None;
+        for i in 0..sample_size {
+            if i == 0 {
+                iter.seek(&first);
+            } else {
+                let offset = u128::from(hi - lo) * i as u128 / sample_size as u128;
+                let target = lo + offset as u64;
+                iter.seek([&first[..prefix_len], &target.to_be_bytes()].concat());
+            }
+            while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+                let value =
+                    if col.is_rc() { refcount::decode_value_with_rc(value).0 } else { Some(value) };
+                if let Some(value) = value {
+                    if prev_key.as_deref() != Some(key) {
+                        values.push(value.into());
+                        prev_key = Some(key.into());
+                    }
+                    break;
+                }
+                iter.next();
+            }
+            iter.status().map_err(io::Error::other)?;
+        }
+        Ok(values)
+    }
+
+    /// Seeks to the first and to the last key of the column rather than
+    /// iterating over it.  In reference counted columns, cells with
+    /// non-positive reference count which haven’t been compacted away yet are
+    /// skipped.
+    fn key_range(&self, col: DBCol) -> io::Result<Option<(Box<[u8]>, Box<[u8]>)>> {
+        let cf_handle = self.cf_handle(col)?;
+        let edge = |mode| -> io::Result<Option<Box<[u8]>>> {
+            for item in self.db.iterator_cf_opt(cf_handle, rocksdb_read_options(), mode) {
+                let (key, value) = item.map_err(io::Error::other)?;
+                if !col.is_rc() || refcount::decode_value_with_rc(&value).1 > 0 {
+                    return Ok(Some(key));
+                }
+            }
+            Ok(None)
+        };
+        let Some(first) = edge(IteratorMode::Start)? else {
+            return Ok(None);
+        };
+        let last = edge(IteratorMode::End)?.unwrap_or_else(|| first.clone());
+        Ok(Some((first, last)))
+    }
+
+    /// Returns bloom filter counters from RocksDB statistics.
+    ///
+    /// RocksDB collects the counters for the whole database rather than per
+    /// column family so the result is the same regardless of the column.  The
+    /// statistics need to be enabled with [`StoreConfig::enable_statistics`];
+    /// otherwise an error is returned.
+    fn bloom_filter_stats(&self, col: DBCol) -> io::Result<BloomStats> {
+        self.cf_handle(col)?;
+        let Some(statistics) = self.db_opt.get_statistics() else {
+            return Err(io::Error::other("RocksDB statistics are disabled"));
+        };
+        let mut result = StoreStatistics { data: vec![] };
+        parse_statistics(&statistics, &mut result)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        let get = |name: &str| {
+            result
+                .data
+                .iter()
+                .find(|(stat_name, _)| stat_name == name)
+                .and_then(|(_, values)| match values.first() {
+                    Some(StatsValue::Count(count)) => Some(*count as u64),
+                    _ => None,
                 })
-                .collect::<Vec<_>>();
-            if !values.is_empty() {
-                // TODO(mina86): Once const_str_from_utf8 is stabilised we might
-                // be able convert this runtime UTF-8 validation into const.
-                let stat_name = prop_name.to_str().unwrap();
-                result.data.push((stat_name.to_string(), values));
+                .unwrap_or(0)
+        };
+        Ok(BloomStats {
+            useful: get("rocksdb.bloom.filter.useful"),
+            positive: get("rocksdb.bloom.filter.full.positive"),
+            true_positive: get("rocksdb.bloom.filter.full.true.positive"),
+        })
+    }
+
+    /// Returns sections of the most recent RocksDB options file which
+    /// describe given column.
+    ///
+    /// RocksDB persists the options in effect in an `OPTIONS-<number>` file
+    /// whenever the database is opened or the options are changed.  The
+    /// returned string includes the column family options and its table
+    /// options, in RocksDB’s INI-like format.
+    fn effective_options(&self, col: DBCol) -> io::Result<String> {
+        let mut latest = None;
+        for entry in std::fs::read_dir(self.db.path())? {
+            let path = entry?.path();
+            let number = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_prefix("OPTIONS-")?.parse::<u64>().ok());
+            if let Some(number) = number {
+                if latest.as_ref().map_or(true, |(latest, _)| number > *latest) {
+                    latest = Some((number, path));
+                }
+            }
+        }
+        let Some((_, path)) = latest else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no RocksDB options file"));
+        };
+        let contents = std::fs::read_to_string(path)?;
+        // Section headers look like `[CFOptions "col1"]` or
+        // `[TableOptions/BlockBasedTable "col1"]`.
+        let suffix = format!(" \"{}\"]", col_name(col));
+        let mut result = String::new();
+        let mut in_section = false;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                in_section = line.ends_with(&suffix);
+            }
+            if in_section && !line.is_empty() {
+                result.push_str(line);
+                result.push('\n');
+            }
+        }
+        Ok(result)
+    }
+
+    /// Adjusts `level0_file_num_compaction_trigger` of the column.
+    ///
+    /// With [`CompactionPriority::High`] a compaction is scheduled as soon as
+    /// a single L0 file exists; [`CompactionPriority::Normal`] restores the
+    /// default trigger set by `optimize_level_style_compaction`.
+    fn request_compaction_priority(
+        &self,
+        col: DBCol,
+        priority: CompactionPriority,
+    ) -> io::Result<()> {
+        let trigger = match priority {
+            CompactionPriority::Normal => "2",
+            CompactionPriority::High => "1",
+        };
+        self.db
+            .set_options_cf(
+                self.cf_handle(col)?,
+                &[("level0_file_num_compaction_trigger", trigger)],
+            )
+            .map_err(io::Error::other)
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        for col in DBCol::iter() {
+            self.compact_column(col)?;
+        }
+        Ok(())
+    }
+
+    /// Compacts only the cleared range of the column.  Like
+    /// [`Self::compact_column`], waits for other manual compactions of the
+    /// column to finish first.
+    fn clear_range_and_compact(&self, col: DBCol, from: &[u8], to: &[u8]) -> io::Result<()> {
+        crate::db::delete_range_checked(self, col, from, to)?;
+        let _guard = self.compaction_locks[col].lock().unwrap();
+        tracing::info!(target: "db", column = %col, "Compact cleared range");
+        self.db.compact_range_cf(self.cf_handle(col)?, Some(from), Some(to));
+        Ok(())
+    }
+
+    fn delete_files_in_range(&self, col: DBCol, from: &[u8], to: &[u8]) -> io::Result<()> {
+        crate::db::check_not_rc(col, "delete files of")?;
+        let cf_handle = self.cf_handle(col)?;
+        // RocksDB’s range is inclusive so narrow it to end at the last key
+        // before `to`.  Files ending with a deleted key between that one and
+        // `to` are therefore kept even if they could be dropped.
+        let mut read_options = rocksdb_read_options();
+        read_options.set_iterate_lower_bound(from);
+        read_options.set_iterate_upper_bound(to);
+        let mut iter = self.db.raw_iterator_cf_opt(cf_handle, read_options);
+        iter.seek_to_last();
+        iter.status().map_err(io::Error::other)?;
+        let Some(end) = iter.key().map(<[u8]>::to_vec) else {
+            return Ok(());
+        };
+        drop(iter);
+        let _guard = self.write_lock.read().unwrap();
+        self.db.delete_file_in_range_cf(cf_handle, from, &end).map_err(io::Error::other)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        // Need to iterator over all CFs because the normal `flush()` only
+        // flushes the default column family.  Columns which aren’t open have
+        // nothing to flush.
+        for (_, cf_handle) in self.cf_handles() {
+            self.db.flush_cf(cf_handle).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    fn flush_column(&self, col: DBCol) -> io::Result<()> {
+        self.db.flush_cf(self.cf_handle(col)?).map_err(io::Error::other)
+    }
+
+    /// Trying to get
+    /// 1. RocksDB statistics
+    /// 2. Selected RockdDB properties for column families
+    fn get_store_statistics(&self) -> Option<StoreStatistics> {
+        let mut result = StoreStatistics { data: vec![] };
+        if let Some(stats_str) = self.db_opt.get_statistics() {
+            if let Err(err) = parse_statistics(&stats_str, &mut result) {
+                warn!(target: "store", "Failed to parse store statistics: {:?}", err);
+            }
+        }
+        self.get_cf_statistics(&mut result);
+        if self.store_config.memtable_memory_budget.is_some() {
+            match self.memtable_memory_usage() {
+                Ok(usage) => result.data.push((
+                    "rocksdb.memtable-memory-usage".to_string(),
+                    vec![StatsValue::Count(usage.try_into().unwrap_or(i64::MAX))],
+                )),
+                Err(err) => warn!(target: "store", "Failed to read memtable memory usage: {err}"),
+            }
+        }
+        if result.data.is_empty() {
+            None
+        } else {
+            Some(result)
+        }
+    }
+
+    fn create_checkpoint(
+        &self,
+        path: &std::path::Path,
+        columns_to_keep: Option<&[DBCol]>,
+    ) -> anyhow::Result<()> {
+        let _span =
+            tracing::info_span!(target: "state_snapshot", "create_checkpoint", ?path).entered();
+        let cp = ::rocksdb::checkpoint::Checkpoint::new(&self.db)?;
+        cp.create_checkpoint(path)
+            .with_context(|| format!("failed to create checkpoint at {}", path.display()))?;
+
+        let Some(columns_to_keep) = columns_to_keep else {
+            return Ok(());
+        };
+        let opts = common_rocksdb_options();
+        let columns = DBCol::iter().collect_vec();
+        let cfs = cf_descriptors(path, &columns, &StoreConfig::default(), Temperature::Hot)?;
+        let mut db = DB::open_cf_descriptors(&opts, path, cfs)
+            .with_context(|| format!("failed to open checkpoint at {}", path.display()))?;
+        for col in DBCol::iter() {
+            if !columns_to_keep.contains(&col) {
+                if col == DBCol::DbVersion {
+                    // We need to keep DbVersion because it's expected to be there when
+                    // we check the metadata in DBOpener::get_metadata()
+                    tracing::debug!(
+                        target: "store",
+                        "create_checkpoint called with columns to keep not including DBCol::DbVersion. Including it anyway."
+                    );
+                    continue;
+                }
+                db.drop_cf(col_name(col)).with_context(|| {
+                    format!(
+                        "failed to drop column family {:?} from checkpoint at {}",
+                        col,
+                        path.display()
+                    )
+                })?;
             }
         }
+        Ok(())
     }
 }
 
-impl Drop for RocksDB {
-    fn drop(&mut self) {
-        if cfg!(feature = "single_thread_rocksdb") {
-            // RocksDB with only one thread stuck on wait some condition var
-            // Turn on additional threads to proceed
-            let mut env = Env::new().unwrap();
-            env.set_background_threads(4);
+fn cf_descriptors(
+    db_path: &Path,
+    columns: &[DBCol],
+    store_config: &StoreConfig,
+    temp: Temperature,
+) -> io::Result<Vec<rocksdb::ColumnFamilyDescriptor>> {
+    columns
+        .iter()
+        .copied()
+        .map(|col| {
+            let opts = rocksdb_column_options(db_path, col, store_config, temp)?;
+            Ok(rocksdb::ColumnFamilyDescriptor::new(col_name(col), opts))
+        })
+        .collect()
+}
+
+/// DB level options
+fn common_rocksdb_options() -> Options {
+    let mut opts = Options::default();
+
+    set_compression_options(&mut opts);
+    opts.set_use_fsync(false);
+    opts.set_keep_log_file_num(1);
+    opts.set_bytes_per_sync(bytesize::MIB);
+    opts.set_write_buffer_size(256 * bytesize::MIB as usize);
+    opts.set_max_bytes_for_level_base(256 * bytesize::MIB);
+
+    if cfg!(feature = "single_thread_rocksdb") {
+        opts.set_disable_auto_compactions(true);
+        opts.set_max_background_jobs(0);
+        opts.set_stats_dump_period_sec(0);
+        opts.set_stats_persist_period_sec(0);
+        opts.set_level_zero_slowdown_writes_trigger(-1);
+        opts.set_level_zero_file_num_compaction_trigger(-1);
+        opts.set_level_zero_stop_writes_trigger(100000000);
+    } else {
+        opts.increase_parallelism(std::cmp::max(1, num_cpus::get() as i32 / 2));
+        opts.set_max_total_wal_size(bytesize::GIB);
+    }
+    opts
+}
+
+fn rocksdb_options(store_config: &StoreConfig, mode: Mode) -> Options {
+    let mut opts = common_rocksdb_options();
+    opts.create_missing_column_families(mode.read_write());
+    opts.create_if_missing(mode.can_create());
+    opts.set_max_open_files(store_config.max_open_files.try_into().unwrap_or(i32::MAX));
+    // TODO(mina86): Perhaps enable statistics even in read-only mode?
+    if mode.read_write() && store_config.enable_statistics {
+        // Rust API doesn't permit choosing stats level. The default stats level
+        // is `kExceptDetailedTimers`, which is described as: "Collects all
+        // stats except time inside mutex lock AND time spent on compression."
+        opts.enable_statistics();
+        // Disabling dumping stats to files because the stats are exported to
+        // Prometheus.
+        opts.set_stats_persist_period_sec(0);
+        opts.set_stats_dump_period_sec(0);
+    }
+    if let Some(bytes_per_sec) = store_config.background_io_bytes_per_sec {
+        // Refill period and fairness are RocksDB’s defaults.
+        let bytes_per_sec = i64::try_from(bytes_per_sec).unwrap_or(i64::MAX);
+        opts.set_ratelimiter(bytes_per_sec, 100_000, 10);
+    }
+    if let Some(jobs) = store_config.max_background_jobs {
+        if !cfg!(feature = "single_thread_rocksdb") {
+            // This grows the process-wide thread pools as needed.
+            opts.set_max_background_jobs(jobs.try_into().unwrap_or(i32::MAX));
+        }
+    }
+    if let Some(budget) = store_config.memtable_memory_budget {
+        // This makes RocksDB create a write buffer manager shared by all
+        // column families.
+        opts.set_db_write_buffer_size(budget.as_u64().try_into().unwrap_or(usize::MAX));
+    }
+
+    opts
+}
+
+fn rocksdb_read_options() -> ReadOptions {
+    let mut read_options = ReadOptions::default();
+    read_options.set_verify_checksums(false);
+    read_options
+}
+
+fn rocksdb_read_options_with(opts: &crate::db::ReadOptions) -> ReadOptions {
+    let mut read_options = rocksdb_read_options();
+    read_options.set_verify_checksums(opts.verify_checksums);
+    if let Some(readahead_bytes) = opts.readahead_bytes {
+        read_options.set_readahead_size(readahead_bytes);
+    }
+    read_options
+}
+
+/// If true then we enable caching of index blocks inside block cache
+fn use_block_cache_for_index_and_filter_blocks(db_col: DBCol) -> bool {
+    match db_col {
+        DBCol::FlatState => false,
+        _ => true,
+    }
+}
+
+fn rocksdb_block_based_options(store_config: &StoreConfig, db_col: DBCol) -> BlockBasedOptions {
+    let column_config = store_config.column_config(db_col);
+    let cache_size = column_config.cache_size.unwrap_or(store_config.col_cache_size(db_col));
+
+    let mut block_opts = BlockBasedOptions::default();
+    let block_size = column_config.block_size.unwrap_or(store_config.block_size);
+    block_opts.set_block_size(block_size.as_u64().try_into().unwrap());
+    // We create block_cache for each of the columns, so the total cache size is (num_of_columns - 2) * 32MiB
+    // Plus the 128MiB from FlatState and 512MiB from State columns
+    block_opts.set_block_cache(&Cache::new_lru_cache(cache_size.as_u64().try_into().unwrap()));
+    if column_config.cache_pinned {
+        block_opts.set_cache_index_and_filter_blocks(true);
+        block_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
+        block_opts.set_pin_top_level_index_and_filter(true);
+    } else if use_block_cache_for_index_and_filter_blocks(db_col) {
+        block_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
+        block_opts.set_cache_index_and_filter_blocks(true);
+    } else {
+        block_opts.set_cache_index_and_filter_blocks(false);
+    }
+    block_opts.set_bloom_filter(column_config.bloom_bits_per_key.unwrap_or(10.0), true);
+    if let Some(TableFormat::BlockBased { partitioned_index: true }) = column_config.table_format {
+        block_opts.set_index_type(::rocksdb::BlockBasedIndexType::TwoLevelIndexSearch);
+        block_opts.set_partition_filters(true);
+    }
+
+    block_opts
+}
+
+fn rocksdb_column_options(
+    db_path: &Path,
+    col: DBCol,
+    store_config: &StoreConfig,
+    temp: Temperature,
+) -> io::Result<Options> {
+    let mut opts = Options::default();
+    set_compression_options(&mut opts);
+    opts.set_level_compaction_dynamic_level_bytes(true);
+    match store_config.column_config(col).table_format {
+        Some(TableFormat::Plain {
+            prefix_len,
+            bloom_bits_per_key,
+            hash_table_ratio,
+            index_sparseness,
+        }) => {
+            opts.set_prefix_extractor(::rocksdb::SliceTransform::create_fixed_prefix(prefix_len));
+            opts.set_plain_table_factory(&::rocksdb::PlainTableFactoryOptions {
+                // Zero means keys may be of any length.
+                user_key_length: 0,
+                bloom_bits_per_key,
+                hash_table_ratio,
+                index_sparseness,
+            });
+        }
+        Some(TableFormat::BlockBased { .. }) | None => {
+            opts.set_block_based_table_factory(&rocksdb_block_based_options(store_config, col));
+        }
+    }
+
+    // Note that this function changes a lot of rustdb parameters including:
+    //      write_buffer_size = memtable_memory_budget / 4
+    //      min_write_buffer_number_to_merge = 2
+    //      max_write_buffer_number = 6
+    //      level0_file_num_compaction_trigger = 2
+    //      target_file_size_base = memtable_memory_budget / 8
+    //      max_bytes_for_level_base = memtable_memory_budget
+    //      compaction_style = kCompactionStyleLevel
+    // Also it sets compression_per_level in a way that the first 2 levels have no compression and
+    // the rest use LZ4 compression.
+    // See the implementation here:
+    //      https://github.com/facebook/rocksdb/blob/c18c4a081c74251798ad2a1abf83bad417518481/options/options.cc#L588.
+    let memtable_memory_budget = 128 * bytesize::MIB as usize;
+    opts.optimize_level_style_compaction(memtable_memory_budget);
+
+    opts.set_target_file_size_base(64 * bytesize::MIB);
+    let column_config = store_config.column_config(col);
+    if let Some(compression) = column_config.compression {
+        set_column_compression(&mut opts, compression);
+    }
+    if let Some(size) = column_config.write_buffer_size {
+        opts.set_write_buffer_size(size.as_u64().try_into().unwrap_or(usize::MAX));
+    }
+    if let Some(style) = column_config.compaction_style {
+        opts.set_compaction_style(match style {
+            CompactionStyle::Level => rocksdb::DBCompactionStyle::Level,
+            CompactionStyle::Universal => rocksdb::DBCompactionStyle::Universal,
+        });
+    }
+    if let Some(path) = column_config.path {
+        // Target size matters only if there are multiple paths.  With a single
+        // path all files are put there regardless.
+        let path =
+            ::rocksdb::DBPath::new(db_path.join(path), u64::MAX).map_err(io::Error::other)?;
+        opts.set_cf_paths(&[path]);
+    }
+    if temp == Temperature::Hot && col.is_rc() {
+        opts.set_merge_operator("refcount merge", RocksDB::refcount_merge, RocksDB::refcount_merge);
+        opts.set_compaction_filter("empty value filter", RocksDB::empty_value_compaction_filter);
+    }
+    Ok(opts)
+}
+
+fn set_compression_options(opts: &mut Options) {
+    opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
+    opts.set_bottommost_compression_type(rocksdb::DBCompressionType::Zstd);
+    // RocksDB documenation says that 16KB is a typical dictionary size.
+    // We've empirically tuned the dicionary size to twice of that 'typical' size.
+    // Having train data size x100 from dictionary size is a recommendation from RocksDB.
+    // See: https://rocksdb.org/blog/2021/05/31/dictionary-compression.html?utm_source=dbplatz
+    let dict_size = 2 * 16384;
+    let max_train_bytes = dict_size * 100;
+    // We use default parameters of RocksDB here:
+    //      window_bits is -14 and is unused (Zlib-specific parameter),
+    //      compression_level is 32767 meaning the default compression level for ZSTD,
+    //      compression_strategy is 0 and is unused (Zlib-specific parameter).
+    // See: https://github.com/facebook/rocksdb/blob/main/include/rocksdb/advanced_options.h#L176:
+    opts.set_bottommost_compression_options(
+        /*window_bits */ -14, /*compression_level */ 32767,
+        /*compression_strategy */ 0, dict_size, /*enabled */ true,
+    );
+    opts.set_bottommost_zstd_max_train_bytes(max_train_bytes, true);
+}
+
+/// Configures all levels of a column to use given compression.
+///
+/// Must be called after `optimize_level_style_compaction` since that sets
+/// per-level compression which takes precedence over the compression type.
+fn set_column_compression(opts: &mut Options, compression: Compression) {
+    let (compression_type, level) = match compression {
+        Compression::None => (rocksdb::DBCompressionType::None, 32767),
+        Compression::Lz4 => (rocksdb::DBCompressionType::Lz4, 32767),
+        Compression::Zstd { level } => (rocksdb::DBCompressionType::Zstd, level),
+    };
+    opts.set_compression_type(compression_type);
+    opts.set_bottommost_compression_type(compression_type);
+    opts.set_compression_per_level(&[compression_type; 7]);
+    opts.set_compression_options(
+        /*window_bits */ -14, level, /*compression_strategy */ 0,
+        /*max_dict_bytes */ 0,
+    );
+    // Disabling bottommost options makes RocksDB use the options set above for
+    // the bottommost level as well.
+    opts.set_bottommost_compression_options(-14, level, 0, 0, /*enabled */ false);
+}
+
+impl RocksDB {
+    /// Blocks until all RocksDB instances (usually 0 or 1) gracefully shutdown.
+    pub fn block_until_all_instances_are_dropped() {
+        instance_tracker::block_until_all_instances_are_closed();
+    }
+
+    /// Returns metadata of the database or `None` if the db doesn’t exist.
+    pub(crate) fn get_metadata(
+        path: &Path,
+        config: &StoreConfig,
+    ) -> io::Result<Option<metadata::DbMetadata>> {
+        if !path.join("CURRENT").is_file() {
+            return Ok(None);
+        }
+        // Specify only DBCol::DbVersion.  It’s ok to open db in read-only mode
+        // without specifying all column families but it’s an error to provide
+        // a descriptor for a column family which doesn’t exist.  This allows us
+        // to read the version without modifying the database before we figure
+        // out if there are any necessary migrations to perform.
+        let cols = [DBCol::DbVersion];
+        let db = Self::open_with_columns(path, config, Mode::ReadOnly, Temperature::Hot, &cols)?;
+        Some(metadata::DbMetadata::read(&db)).transpose()
+    }
+
+    /// Returns names of column families present in the database at `path`.
+    ///
+    /// This doesn’t open the database and thus works even if the database
+    /// contains column families which this build doesn’t know about.  Tools
+    /// can use it to detect extra or missing columns (e.g. when the database
+    /// was created by a different version of neard) before opening it.
+    ///
+    /// Note that the returned list includes RocksDB’s `default` column family
+    /// which isn’t used by us.
+    pub fn list_column_families(path: &Path) -> io::Result<Vec<String>> {
+        DB::list_cf(&Options::default(), path).map_err(io::Error::other)
+    }
+
+    /// Attempts to repair a corrupted database at given path.
+    ///
+    /// This is a last resort for databases which fail to open.  It must not be
+    /// called on a database which is open.  RocksDB recovers as much data as
+    /// it can by rebuilding the manifest from the SST files it finds.  Files it
+    /// cannot use are moved to `lost` subdirectory of the database and their
+    /// data is no longer part of the database.  Even if no files are lost,
+    /// recent writes whose write-ahead log is corrupted may be dropped.  Since
+    /// data may be lost, consider backing up the database directory first.
+    ///
+    /// Returns a report listing the files RocksDB gave up on.
+    pub fn repair(path: &Path, store_config: &StoreConfig) -> io::Result<RepairReport> {
+        let lost_dir = path.join("lost");
+        let list_lost = || -> io::Result<Vec<std::path::PathBuf>> {
+            match std::fs::read_dir(&lost_dir) {
+                Ok(entries) => entries.map(|entry| entry.map(|entry| entry.path())).collect(),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+                Err(err) => Err(err),
+            }
+        };
+        let lost_before = list_lost()?;
+        let options = rocksdb_options(store_config, Mode::ReadWriteExisting);
+        DB::repair(&options, path).map_err(io::Error::other)?;
+        let mut lost_files = list_lost()?;
+        lost_files.retain(|file| !lost_before.contains(file));
+        lost_files.sort();
+        let sst_files = std::fs::read_dir(path)?
+            .filter(|entry| {
+                entry.as_ref().map_or(true, |entry| {
+                    entry.path().extension() == Some(std::ffi::OsStr::new("sst"))
+                })
+            })
+            .try_fold(0, |count, entry| entry.map(|_| count + 1))?;
+        Ok(RepairReport { sst_files, lost_files })
+    }
+
+    /// Gets every int property in CF_PROPERTY_NAMES for every column in DBCol.
+    ///
+    /// If statistics are enabled for some columns, gets the properties only
+    /// for those columns together with properties in
+    /// DETAILED_CF_PROPERTY_NAMES.
+    fn get_cf_statistics(&self, result: &mut StoreStatistics) {
+        let prop_names = if self.statistics_columns.is_empty() {
+            CF_PROPERTY_NAMES.iter().collect::<Vec<_>>()
+        } else {
+            CF_PROPERTY_NAMES.iter().chain(DETAILED_CF_PROPERTY_NAMES.iter()).collect()
+        };
+        for prop_name in prop_names {
+            let values = self
+                .cf_handles()
+                .filter(|(col, _)| {
+                    self.statistics_columns.is_empty() || self.statistics_columns.contains(col)
+                })
+                .filter_map(|(col, handle)| {
+                    let prop = self.db.property_int_value_cf(handle, prop_name);
+                    Some(StatsValue::ColumnValue(col, prop.ok()?? as i64))
+                })
+                .collect::<Vec<_>>();
+            if !values.is_empty() {
+                // TODO(mina86): Once const_str_from_utf8 is stabilised we might
+                // be able convert this runtime UTF-8 validation into const.
+                let stat_name = prop_name.to_str().unwrap();
+                result.data.push((stat_name.to_string(), values));
+            }
+        }
+    }
+}
+
+impl Drop for RocksDB {
+    fn drop(&mut self) {
+        if cfg!(feature = "single_thread_rocksdb") {
+            // RocksDB with only one thread stuck on wait some condition var
+            // Turn on additional threads to proceed
+            let mut env = Env::new().unwrap();
+            env.set_background_threads(4);
+        }
+        if self.flush_on_drop {
+            if let Err(err) = self.flush() {
+                warn!(target: "db", %err, "Failed to flush memtables on drop");
+            }
+        }
+        self.db.cancel_all_background_work(true);
+    }
+}
+
+/// Parses a string containing RocksDB statistics.
+/// Results are added into provided 'result' parameter.
+fn parse_statistics(
+    statistics: &str,
+    result: &mut StoreStatistics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Statistics are given one per line.
+    for line in statistics.lines() {
+        // Each line follows one of two formats:
+        // 1) <stat_name> COUNT : <value>
+        // 2) <stat_name> P50 : <value> P90 : <value> COUNT : <value> SUM : <value>
+        // Each line gets split into words and we parse statistics according to this format.
+        if let Some((stat_name, words)) = line.split_once(' ') {
+            let mut values = vec![];
+            let mut words = words.split(" : ").flat_map(|v| v.split(" "));
+            while let (Some(key), Some(val)) = (words.next(), words.next()) {
+                match key {
+                    "COUNT" => values.push(StatsValue::Count(val.parse::<i64>()?)),
+                    "SUM" => values.push(StatsValue::Sum(val.parse::<i64>()?)),
+                    p if p.starts_with("P") => values.push(StatsValue::Percentile(
+                        key[1..].parse::<u32>()?,
+                        val.parse::<f64>()?,
+                    )),
+                    _ => {
+                        warn!(target: "stats", "Unsupported stats value: {key} in {line}");
+                    }
+                }
+            }
+            // We push some stats to result, even if later parsing will fail.
+            result.data.push((stat_name.to_string(), values));
+        }
+    }
+    Ok(())
+}
+
+/// Returns name of a RocksDB column family corresponding to given column.
+///
+/// Historically we used `col##` names (with `##` being index of the column).
+/// We have since deprecated this convention.  All future column families are
+/// named the same as the variant of the [`DBCol`] enum.
+fn col_name(col: DBCol) -> &'static str {
+    match col {
+        DBCol::DbVersion => "col0",
+        DBCol::BlockMisc => "col1",
+        DBCol::Block => "col2",
+        DBCol::BlockHeader => "col3",
+        DBCol::BlockHeight => "col4",
+        DBCol::State => "col5",
+        DBCol::ChunkExtra => "col6",
+        DBCol::_TransactionResult => "col7",
+        DBCol::OutgoingReceipts => "col8",
+        DBCol::IncomingReceipts => "col9",
+        DBCol::_Peers => "col10",
+        DBCol::EpochInfo => "col11",
+        DBCol::BlockInfo => "col12",
+        DBCol::Chunks => "col13",
+        DBCol::PartialChunks => "col14",
+        DBCol::BlocksToCatchup => "col15",
+        DBCol::StateDlInfos => "col16",
+        DBCol::ChallengedBlocks => "col17",
+        DBCol::StateHeaders => "col18",
+        DBCol::InvalidChunks => "col19",
+        DBCol::BlockExtra => "col20",
+        DBCol::BlockPerHeight => "col21",
+        DBCol::StateParts => "col22",
+        DBCol::EpochStart => "col23",
+        DBCol::AccountAnnouncements => "col24",
+        DBCol::NextBlockHashes => "col25",
+        DBCol::EpochLightClientBlocks => "col26",
+        DBCol::ReceiptIdToShardId => "col27",
+        DBCol::_NextBlockWithNewChunk => "col28",
+        DBCol::_LastBlockWithNewChunk => "col29",
+        DBCol::PeerComponent => "col30",
+        DBCol::ComponentEdges => "col31",
+        DBCol::LastComponentNonce => "col32",
+        DBCol::Transactions => "col33",
+        DBCol::_ChunkPerHeightShard => "col34",
+        DBCol::StateChanges => "col35",
+        DBCol::BlockRefCount => "col36",
+        DBCol::TrieChanges => "col37",
+        DBCol::BlockMerkleTree => "col38",
+        DBCol::ChunkHashesByHeight => "col39",
+        DBCol::BlockOrdinal => "col40",
+        DBCol::_GCCount => "col41",
+        DBCol::OutcomeIds => "col42",
+        DBCol::_TransactionRefCount => "col43",
+        DBCol::ProcessedBlockHeights => "col44",
+        DBCol::Receipts => "col45",
+        DBCol::CachedContractCode => "col46",
+        DBCol::EpochValidatorInfo => "col47",
+        DBCol::HeaderHashesByHeight => "col48",
+        DBCol::StateChangesForSplitStates => "col49",
+        // If you’re adding a new column, do *not* create a new case for it.
+        // All new columns are handled by this default case:
+        #[allow(unreachable_patterns)]
+        _ => <&str>::from(col),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Database, StatsValue};
+    use crate::{DBCol, NodeStorage, StoreStatistics};
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    /// Opens a fresh hot RocksDB instance with given configuration.
+    ///
+    /// The returned temporary directory must be held for as long as the
+    /// database is in use.
+    fn open_test_rocksdb_with_config(config: &StoreConfig) -> (tempfile::TempDir, RocksDB) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db = RocksDB::open(tmp_dir.path(), config, Mode::ReadWrite, Temperature::Hot).unwrap();
+        (tmp_dir, db)
+    }
+
+    fn open_test_rocksdb() -> (tempfile::TempDir, RocksDB) {
+        open_test_rocksdb_with_config(&StoreConfig::test_config())
+    }
+
+    /// Returns number of SST files holding data of given column.
+    fn live_file_count(db: &RocksDB, col: DBCol) -> usize {
+        let files = db.db.live_files().unwrap();
+        files.iter().filter(|file| file.column_family_name == col_name(col)).count()
+    }
+
+    #[test]
+    fn rocksdb_merge_sanity() {
+        let (_tmp_dir, opener) = NodeStorage::test_opener();
+        let store = opener.open().unwrap().get_hot_store();
+        let ptr = (&*store.storage) as *const (dyn Database + 'static);
+        let rocksdb = unsafe { &*(ptr as *const RocksDB) };
+        assert_eq!(store.get(DBCol::State, &[1]).unwrap(), None);
+        {
+            let mut store_update = store.store_update();
+            store_update.increment_refcount(DBCol::State, &[1], &[1]);
+            store_update.commit().unwrap();
+        }
+        {
+            let mut store_update = store.store_update();
+            store_update.increment_refcount(DBCol::State, &[1], &[1]);
+            store_update.commit().unwrap();
+        }
+        assert_eq!(store.get(DBCol::State, &[1]).unwrap().as_deref(), Some(&[1][..]));
+        assert_eq!(
+            rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap().as_deref(),
+            Some(&[1, 2, 0, 0, 0, 0, 0, 0, 0][..])
+        );
+        {
+            let mut store_update = store.store_update();
+            store_update.decrement_refcount(DBCol::State, &[1]);
+            store_update.commit().unwrap();
+        }
+        assert_eq!(store.get(DBCol::State, &[1]).unwrap().as_deref(), Some(&[1][..]));
+        assert_eq!(
+            rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap().as_deref(),
+            Some(&[1, 1, 0, 0, 0, 0, 0, 0, 0][..])
+        );
+        {
+            let mut store_update = store.store_update();
+            store_update.decrement_refcount(DBCol::State, &[1]);
+            store_update.commit().unwrap();
+        }
+        // Refcount goes to 0 -> get() returns None
+        assert_eq!(store.get(DBCol::State, &[1]).unwrap(), None);
+        // Internally there is an empty value
+        assert_eq!(rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap().as_deref(), Some(&[][..]));
+
+        // single_thread_rocksdb makes compact hang forever
+        if !cfg!(feature = "single_thread_rocksdb") {
+            let none = Option::<&[u8]>::None;
+            let cf = rocksdb.cf_handle(DBCol::State).unwrap();
+
+            // I’m not sure why but we need to run compaction twice.  If we run
+            // it only once, we end up with an empty value for the key.  This is
+            // surprising because I assumed that compaction filter would discard
+            // empty values.
+            rocksdb.db.compact_range_cf(cf, none, none);
+            assert_eq!(
+                rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap().as_deref(),
+                Some(&[][..])
+            );
+            assert_eq!(store.get(DBCol::State, &[1]).unwrap(), None);
+
+            rocksdb.db.compact_range_cf(cf, none, none);
+            assert_eq!(rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap(), None);
+            assert_eq!(store.get(DBCol::State, &[1]).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_parse_statistics() {
+        let statistics = "rocksdb.cold.file.read.count COUNT : 999\n\
+         rocksdb.db.get.micros P50 : 9.171086 P95 : 222.678751 P99 : 549.611652 P100 : 45816.000000 COUNT : 917578 SUM : 38313754";
+        let mut result = StoreStatistics { data: vec![] };
+        let parse_result = parse_statistics(statistics, &mut result);
+        // We should be able to parse stats and the result should be Ok(()).
+        parse_result.unwrap();
+        assert_eq!(
+            result,
+            StoreStatistics {
+                data: vec![
+                    ("rocksdb.cold.file.read.count".to_string(), vec![StatsValue::Count(999)]),
+                    (
+                        "rocksdb.db.get.micros".to_string(),
+                        vec![
+                            StatsValue::Percentile(50, 9.171086),
+                            StatsValue::Percentile(95, 222.678751),
+                            StatsValue::Percentile(99, 549.611652),
+                            StatsValue::Percentile(100, 45816.0),
+                            StatsValue::Count(917578),
+                            StatsValue::Sum(38313754)
+                        ]
+                    )
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let store = NodeStorage::test_opener().1.open().unwrap().get_hot_store();
+        let keys = [vec![0], vec![1], vec![2], vec![3]];
+        let column = DBCol::Block;
+
+        let mut store_update = store.store_update();
+        for key in &keys {
+            store_update.insert(column, key.clone(), vec![42]);
+        }
+        store_update.commit().unwrap();
+
+        let mut store_update = store.store_update();
+        store_update.delete_range(column, &keys[1], &keys[3]);
+        store_update.commit().unwrap();
+
+        assert_matches!(store.exists(column, &keys[0]), Ok(true));
+        assert_matches!(store.exists(column, &keys[1]), Ok(false));
+        assert_matches!(store.exists(column, &keys[2]), Ok(false));
+        assert_matches!(store.exists(column, &keys[3]), Ok(true));
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_truncate_column() {
+        const COLUMN: DBCol = DBCol::BlockMisc;
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let fill = |db: &RocksDB| {
+            let mut transaction = DBTransaction::new();
+            for key in 0u8..10 {
+                transaction.set(COLUMN, vec![key], vec![key; 100]);
+            }
+            db.write(transaction).unwrap();
+            db.flush().unwrap();
+        };
+
+        // DeleteAll only records a range tombstone; the data stays on disk
+        // until background compaction gets to it.
+        fill(&db);
+        assert_eq!(1, live_file_count(&db, COLUMN));
+        let mut transaction = DBTransaction::new();
+        transaction.delete_all(COLUMN);
+        db.write(transaction).unwrap();
+        assert_eq!(0, db.iter(COLUMN).count());
+        assert_eq!(1, live_file_count(&db, COLUMN));
+
+        // Truncating compacts the column so no files are left behind.
+        fill(&db);
+        assert_eq!(10, db.iter(COLUMN).count());
+        db.truncate_column(COLUMN).unwrap();
+        assert_eq!(0, db.iter(COLUMN).count());
+        assert_eq!(0, live_file_count(&db, COLUMN));
+    }
+
+    #[test]
+    fn test_approx_keys_in_range() {
+        const COLUMN: DBCol = DBCol::BlockMisc;
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let mut transaction = DBTransaction::new();
+        for key in 0u8..100 {
+            transaction.set(COLUMN, vec![key], vec![key; 100]);
+        }
+        db.write(transaction).unwrap();
+
+        // Before flush, everything is in the memtable.
+        let estimate = db.approx_keys_in_range(COLUMN, &[], &[255]).unwrap();
+        assert!((50..=200).contains(&estimate), "{estimate}");
+
+        db.flush().unwrap();
+        let estimate = db.approx_keys_in_range(COLUMN, &[], &[255]).unwrap();
+        assert!((50..=200).contains(&estimate), "{estimate}");
+        // Range past all the keys doesn’t overlap the file.
+        assert_eq!(db.approx_keys_in_range(COLUMN, &[200], &[255]).unwrap(), 0);
+        assert_eq!(db.approx_keys_in_range(DBCol::BlockHeader, &[], &[255]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_column_compression_override() {
+        use crate::config::ColumnConfig;
+
+        let mut config = StoreConfig::test_config();
+        let overrides = [
+            (DBCol::StateParts, Compression::Zstd { level: 3 }),
+            (DBCol::BlockMisc, Compression::None),
+            (DBCol::Block, Compression::Lz4),
+        ];
+        for (col, compression) in overrides {
+            config
+                .column_overrides
+                .insert(col, ColumnConfig { compression: Some(compression), ..Default::default() });
+        }
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let mut transaction = DBTransaction::new();
+        for (col, _) in overrides {
+            for key in 0u8..10 {
+                transaction.set(col, vec![key], vec![key; 1000]);
+            }
+        }
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+
+        for (col, _) in overrides {
+            assert_eq!(db.iter(col).count(), 10);
+            let value = db.get_raw_bytes(col, &[7]).unwrap();
+            assert_eq!(value.as_deref(), Some(&[7; 1000][..]));
+        }
+    }
+
+    #[test]
+    fn test_list_column_families() {
+        let (tmp_dir, db) = open_test_rocksdb();
+        drop(db);
+
+        let mut got = RocksDB::list_column_families(tmp_dir.path()).unwrap();
+        got.sort();
+        let mut want = DBCol::iter().map(|col| col_name(col).to_string()).collect_vec();
+        want.push("default".to_string());
+        want.sort();
+        assert_eq!(got, want);
+
+        let missing = tmp_dir.path().join("missing");
+        RocksDB::list_column_families(&missing).unwrap_err();
+    }
+
+    #[test]
+    fn test_max_write_batch_size() {
+        const COLUMN: DBCol = DBCol::BlockMisc;
+        let config = StoreConfig {
+            max_write_batch_size: Some(bytesize::ByteSize::kib(1)),
+            ..StoreConfig::test_config()
+        };
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+        let transaction = |keys: std::ops::Range<u8>| {
+            let mut transaction = DBTransaction::new();
+            for key in keys {
+                transaction.set(COLUMN, vec![key], vec![key; 100]);
+            }
+            transaction
+        };
+
+        // Under the threshold both variants write the batch.
+        db.write(transaction(0..5)).unwrap();
+        db.write_atomic(transaction(5..10)).unwrap();
+        assert_eq!(db.iter(COLUMN).count(), 10);
+
+        // Over the threshold, write splits the batch while write_atomic fails
+        // without writing anything.
+        db.write(transaction(10..30)).unwrap();
+        assert_eq!(db.iter(COLUMN).count(), 30);
+        let err = db.write_atomic(transaction(30..50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(db.iter(COLUMN).count(), 30);
+    }
+
+    #[test]
+    fn test_column_path_override() {
+        use crate::config::ColumnConfig;
+
+        const COLUMN: DBCol = DBCol::BlockMisc;
+        let mut config = StoreConfig::test_config();
+        let column_config = ColumnConfig { path: Some("misc".into()), ..Default::default() };
+        config.column_overrides.insert(COLUMN, column_config);
+        let (tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let mut transaction = DBTransaction::new();
+        for key in 0u8..10 {
+            transaction.set(COLUMN, vec![key], vec![key; 100]);
+            transaction.set(DBCol::Block, vec![key], vec![key; 100]);
+        }
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+
+        let sst_count = |dir: &Path| {
+            std::fs::read_dir(dir)
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("sst".as_ref()))
+                .count()
+        };
+        assert_eq!(sst_count(&tmp_dir.path().join("misc")), 1);
+        assert_eq!(sst_count(tmp_dir.path()), 1);
+
+        assert_eq!(db.iter(COLUMN).count(), 10);
+        let value = db.get_raw_bytes(COLUMN, &[7]).unwrap();
+        assert_eq!(value.as_deref(), Some(&[7; 100][..]));
+
+        // Reopening with the same configuration finds the files.
+        drop(db);
+        let db = RocksDB::open(tmp_dir.path(), &config, Mode::ReadWrite, Temperature::Hot).unwrap();
+        assert_eq!(db.iter(COLUMN).count(), 10);
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_compaction_guard() {
+        const COLUMN: DBCol = DBCol::BlockMisc;
+        let (_tmp_dir, db) = open_test_rocksdb();
+        assert!(!db.is_compacting(COLUMN));
+        db.try_compact_column(COLUMN).unwrap();
+
+        // Block compactions of the column in their span until released so
+        // that the test controls how long they run.
+        let mut capture = near_o11y::testonly::TracingCapture::enable();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let release_rx = std::sync::Mutex::new(release_rx);
+        capture.set_callback(move |log| {
+            if log.starts_with("compact_column") && log.contains("col=BlockMisc") {
+                started_tx.send(()).unwrap();
+                release_rx.lock().unwrap().recv().unwrap();
+            }
+        });
+        let dispatch = tracing::dispatcher::get_default(|dispatch| dispatch.clone());
+
+        // Spawn two compactions; only one of them may run at a time.
+        std::thread::scope(|scope| {
+            for _ in 0..2 {
+                let (db, dispatch) = (&db, dispatch.clone());
+                scope.spawn(move || {
+                    tracing::dispatcher::with_default(&dispatch, || db.compact_column(COLUMN))
+                        .unwrap()
+                });
+            }
+            started_rx.recv().unwrap();
+            let timeout = std::time::Duration::from_millis(100);
+            assert!(started_rx.recv_timeout(timeout).is_err());
+            assert!(db.is_compacting(COLUMN));
+            assert!(!db.is_compacting(DBCol::Block));
+            let err = db.try_compact_column(COLUMN).unwrap_err();
+            let err = err.get_ref().unwrap().downcast_ref::<CompactionInProgress>();
+            assert_matches!(err, Some(CompactionInProgress { col: COLUMN }));
+            db.try_compact_column(DBCol::Block).unwrap();
+
+            // The second compaction starts once the first one finishes.
+            release_tx.send(()).unwrap();
+            started_rx.recv().unwrap();
+            assert!(started_rx.recv_timeout(timeout).is_err());
+            release_tx.send(()).unwrap();
+        });
+        assert!(!db.is_compacting(COLUMN));
+    }
+
+    #[test]
+    fn test_effective_options() {
+        use crate::config::ColumnConfig;
+
+        let mut config = StoreConfig::test_config();
+        let column_config =
+            ColumnConfig { compression: Some(Compression::None), ..Default::default() };
+        config.column_overrides.insert(DBCol::BlockMisc, column_config);
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let options = db.effective_options(DBCol::BlockMisc).unwrap();
+        assert!(options.contains(&format!("[CFOptions \"{}\"]", col_name(DBCol::BlockMisc))));
+        assert!(options.contains("compression=kNoCompression"), "{options}");
+        assert!(options.contains("block_size=16384"), "{options}");
+
+        let options = db.effective_options(DBCol::Block).unwrap();
+        assert!(options.contains("compression=kLZ4Compression"), "{options}");
+        assert!(!options.contains(&format!("\"{}\"", col_name(DBCol::BlockMisc))));
+
+        assert_eq!(crate::db::TestDB::new().effective_options(DBCol::Block).unwrap(), "");
+    }
+
+    #[test]
+    fn test_with_snapshot() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let write = |value: &[u8]| {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, b"key".to_vec(), value.to_vec());
+            transaction.set(DBCol::BlockHeader, b"key".to_vec(), value.to_vec());
+            db.write(transaction).unwrap();
+        };
+        write(b"old");
+
+        let (block_misc, header, iterated) = db
+            .with_snapshot(|snapshot| {
+                let get = |col| snapshot.get_raw_bytes(col, b"key").unwrap().map(|v| v.to_vec());
+                let block_misc = get(DBCol::BlockMisc);
+                write(b"new");
+                let header = get(DBCol::BlockHeader);
+                let iterated =
+                    snapshot.iter(DBCol::BlockHeader).map(Result::unwrap).collect::<Vec<_>>();
+                assert!(snapshot.write(DBTransaction::new()).is_err());
+                (block_misc, header, iterated)
+            })
+            .unwrap();
+        assert_eq!(block_misc, Some(b"old".to_vec()));
+        assert_eq!(header, Some(b"old".to_vec()));
+        let want: Vec<(Box<[u8]>, Box<[u8]>)> =
+            vec![(b"key".to_vec().into(), b"old".to_vec().into())];
+        assert_eq!(iterated, want);
+
+        // The database itself sees the new values.
+        let value = db.get_raw_bytes(DBCol::BlockHeader, b"key").unwrap();
+        assert_eq!(value.as_deref(), Some(&b"new"[..]));
+    }
+
+    #[test]
+    fn test_open_with_logger() {
+        let lines = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let logger: Logger = {
+            let lines = lines.clone();
+            Box::new(move |level, line| lines.lock().unwrap().push((level, line.to_string())))
+        };
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let config = StoreConfig::test_config();
+        let db = RocksDB::open_with_logger(
+            tmp_dir.path(),
+            &config,
+            Mode::ReadWrite,
+            Temperature::Hot,
+            logger,
+        )
+        .unwrap();
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+        db.flush_column(DBCol::BlockMisc).unwrap();
+        drop(db);
+
+        let lines = lines.lock().unwrap();
+        let flushes = lines
+            .iter()
+            .filter(|(level, line)| *level == LogLevel::Info && line.contains("Flushing memtable"))
+            .count();
+        assert!(flushes >= 1, "{lines:?}");
+
+        assert_eq!(
+            log_line_level("2024/01/01-00:00:00.000000 1 [WARN] [db/foo.cc:1] x"),
+            LogLevel::Warn
+        );
+        assert_eq!(log_line_level("2024/01/01-00:00:00.000000 1 [db/foo.cc:1] x"), LogLevel::Info);
+    }
+
+    #[test]
+    fn test_flush_column() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        transaction.set(DBCol::Block, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+
+        db.flush_column(DBCol::BlockMisc).unwrap();
+        assert_eq!(live_file_count(&db, DBCol::BlockMisc), 1);
+        assert_eq!(live_file_count(&db, DBCol::Block), 0);
+
+        crate::db::TestDB::new().flush_column(DBCol::BlockMisc).unwrap();
+    }
+
+    #[test]
+    fn test_level_file_counts() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        assert_eq!(db.level_file_counts(DBCol::BlockMisc).unwrap(), Vec::<usize>::new());
+
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+        let counts = db.level_file_counts(DBCol::BlockMisc).unwrap();
+        assert!(counts.first().is_some_and(|&count| count >= 1), "{counts:?}");
+        assert_eq!(counts.iter().sum::<usize>(), live_file_count(&db, DBCol::BlockMisc));
+        assert_eq!(db.level_file_counts(DBCol::Block).unwrap(), Vec::<usize>::new());
+
+        let db = crate::db::TestDB::new();
+        assert_eq!(db.level_file_counts(DBCol::BlockMisc).unwrap(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_compaction_pending_bytes() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        assert_eq!(db.compaction_pending_bytes(DBCol::BlockMisc).unwrap(), 0);
+        for batch in 0u16..4 {
+            let mut transaction = DBTransaction::new();
+            for key in 0u16..100 {
+                let key = (batch * 100 + key).to_be_bytes().to_vec();
+                transaction.set(DBCol::BlockMisc, key, vec![42; 1000]);
+            }
+            db.write(transaction).unwrap();
+            db.flush().unwrap();
+        }
+        // The exact estimate depends on RocksDB internals; it’s enough that
+        // the property can be read.
+        db.compaction_pending_bytes(DBCol::BlockMisc).unwrap();
+
+        let db = crate::db::TestDB::new();
+        assert_eq!(db.compaction_pending_bytes(DBCol::BlockMisc).unwrap(), 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_iter_level() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let mut transaction = DBTransaction::new();
+        for key in 0u8..10 {
+            transaction.set(DBCol::BlockMisc, vec![key], vec![key]);
+        }
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+        db.compact().unwrap();
+
+        let counts = db.level_file_counts(DBCol::BlockMisc).unwrap();
+        let level = counts.len() - 1;
+        assert!(level > 0, "{counts:?}");
+        let entries = db.iter_level(DBCol::BlockMisc, level).unwrap().map(Result::unwrap);
+        let want: Vec<(Box<[u8]>, Box<[u8]>)> =
+            (0u8..10).map(|key| (vec![key].into(), vec![key].into())).collect();
+        assert_eq!(entries.collect::<Vec<_>>(), want);
+        assert_eq!(db.iter_level(DBCol::BlockMisc, 0).unwrap().count(), 0);
+
+        let db = crate::db::TestDB::new();
+        let err = db.iter_level(DBCol::BlockMisc, 0).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_total_disk_size() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let sst_size = |col| -> u64 {
+            let files = db.db.live_files().unwrap();
+            let files = files.iter().filter(|file| file.column_family_name == col_name(col));
+            files.map(|file| file.size as u64).sum()
+        };
+        let before = db.total_disk_size().unwrap();
+        let mut transaction = DBTransaction::new();
+        for key in 0u16..100 {
+            for col in [DBCol::BlockMisc, DBCol::Block] {
+                transaction.set(col, key.to_be_bytes().to_vec(), vec![42; 1000]);
+            }
+        }
+        db.write(transaction).unwrap();
+        // The write-ahead log counts as well.
+        let unflushed = db.total_disk_size().unwrap();
+        assert!(unflushed > before, "{unflushed} <= {before}");
+
+        db.flush().unwrap();
+        let total = db.total_disk_size().unwrap();
+        let columns = sst_size(DBCol::BlockMisc) + sst_size(DBCol::Block);
+        assert!(total > sst_size(DBCol::BlockMisc), "{total}");
+        assert!(total >= columns, "{total} < {columns}");
+
+        let db = crate::db::TestDB::new();
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        transaction.set(DBCol::Block, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+        assert_eq!(db.total_disk_size().unwrap(), 16);
+    }
+
+    #[test]
+    fn test_wal_sequence_range() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let write = |db: &dyn Database, count: u16| {
+            for key in 0..count {
+                let mut transaction = DBTransaction::new();
+                transaction.set(DBCol::BlockMisc, key.to_be_bytes().to_vec(), b"value".to_vec());
+                db.write(transaction).unwrap();
+            }
+        };
+        write(&db, 10);
+        let (oldest, newest) = db.wal_sequence_range().unwrap().unwrap();
+        assert!(oldest <= newest, "{oldest} > {newest}");
+        write(&db, 10);
+        let (got_oldest, got_newest) = db.wal_sequence_range().unwrap().unwrap();
+        assert!(got_oldest <= oldest, "{got_oldest} > {oldest}");
+        assert_eq!(got_newest, newest + 10);
+        assert_eq!(got_newest, db.db.latest_sequence_number());
+
+        let db = crate::db::TestDB::new();
+        assert_eq!(db.wal_sequence_range().unwrap(), None);
+        write(db.as_ref(), 3);
+        assert_eq!(db.wal_sequence_range().unwrap(), Some((1, 3)));
+        write(db.as_ref(), 2);
+        assert_eq!(db.wal_sequence_range().unwrap(), Some((1, 5)));
+    }
+
+    #[test]
+    fn test_read_options() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let mut transaction = DBTransaction::new();
+        for key in 0u16..100 {
+            transaction.set(
+                DBCol::BlockMisc,
+                key.to_be_bytes().to_vec(),
+                key.to_le_bytes().to_vec(),
+            );
+        }
+        let rc = std::num::NonZeroU32::new(1).unwrap();
+        let value = refcount::add_positive_refcount(b"node", rc);
+        transaction.update_refcount(DBCol::State, b"key".to_vec(), value.clone());
+        db.write(transaction).unwrap();
+        // Make sure the reads go to SST files so checksums are in play.
+        db.flush().unwrap();
+
+        let opts = crate::db::ReadOptions { verify_checksums: true, ..Default::default() };
+        for key in 0u16..100 {
+            let value = db.get_with_opts(DBCol::BlockMisc, &key.to_be_bytes(), &opts).unwrap();
+            assert_eq!(value.as_deref(), Some(&key.to_le_bytes()[..]));
+        }
+        assert!(db.get_with_opts(DBCol::BlockMisc, b"missing", &opts).unwrap().is_none());
+        let got =
+            db.iter_with_opts(DBCol::BlockMisc, &opts).map(Result::unwrap).collect::<Vec<_>>();
+        let want = db.iter(DBCol::BlockMisc).map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(got.len(), 100);
+        assert_eq!(got, want);
+
+        // Values are raw on get but stripped of reference counts when iterating.
+        let got = db.get_with_opts(DBCol::State, b"key", &opts).unwrap();
+        assert_eq!(got.as_deref(), Some(&value[..]));
+        let got = db.iter_with_opts(DBCol::State, &opts).map(Result::unwrap).collect::<Vec<_>>();
+        let want: Vec<(Box<[u8]>, Box<[u8]>)> =
+            vec![(b"key".to_vec().into(), b"node".to_vec().into())];
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_clear_range_and_compact() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let sst_size = |col| -> u64 {
+            let files = db.db.live_files().unwrap();
+            let files = files.iter().filter(|file| file.column_family_name == col_name(col));
+            files.map(|file| file.size as u64).sum()
+        };
+        let mut transaction = DBTransaction::new();
+        for key in 0u16..1000 {
+            // Hashes so that the values don’t compress.
+            let value = (0u8..32)
+                .flat_map(|i| {
+                    near_primitives::hash::hash(&[&key.to_le_bytes()[..], &[i]].concat()).0
+                })
+                .collect();
+            transaction.set(DBCol::BlockMisc, key.to_be_bytes().to_vec(), value);
+        }
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+        let before = sst_size(DBCol::BlockMisc);
+
+        let (from, to) = (100u16.to_be_bytes(), 900u16.to_be_bytes());
+        db.clear_range_and_compact(DBCol::BlockMisc, &from, &to).unwrap();
+        let keys = db
+            .iter(DBCol::BlockMisc)
+            .map(|item| u16::from_be_bytes(item.unwrap().0[..].try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, (0..100).chain(900..1000).collect::<Vec<_>>());
+        let after = sst_size(DBCol::BlockMisc);
+        assert!(after < before / 2, "{after} >= {before} / 2");
+
+        let err = db.clear_range_and_compact(DBCol::State, &from, &to).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_write_column_not_open() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let config = StoreConfig::test_config();
+        let columns = [DBCol::DbVersion, DBCol::BlockMisc];
+        let db = RocksDB::open_with_columns(
+            tmp_dir.path(),
+            &config,
+            Mode::Create,
+            Temperature::Hot,
+            &columns,
+        )
+        .unwrap();
+        let write = |cols: &[DBCol]| {
+            let mut transaction = DBTransaction::new();
+            for &col in cols {
+                transaction.set(col, b"key".to_vec(), b"value".to_vec());
+            }
+            db.write(transaction)
+        };
+        write(&[DBCol::BlockMisc]).unwrap();
+
+        let err = write(&[DBCol::BlockMisc, DBCol::Block]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let inner = err.get_ref().and_then(|err| err.downcast_ref::<ColumnNotOpen>());
+        assert_matches!(inner, Some(ColumnNotOpen { col: DBCol::Block }));
+        assert_eq!(err.to_string(), "column Block isn’t open in this database");
+        // Nothing has been written.
+        let mut transaction = DBTransaction::new();
+        transaction.delete(DBCol::BlockMisc, b"key".to_vec());
+        transaction.set(DBCol::State, b"key".to_vec(), b"value".to_vec());
+        assert!(db.write_atomic(transaction).is_err());
+        assert!(db.get_raw_bytes(DBCol::BlockMisc, b"key").unwrap().is_some());
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_delete_files_in_range() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let cf_handle = db.cf_handle(DBCol::BlockMisc).unwrap();
+        db.db.set_options_cf(cf_handle, &[("disable_auto_compactions", "true")]).unwrap();
+        let key = |key: u16| key.to_be_bytes();
+        // Create three files with disjoint key ranges outside of level 0
+        // whose files are never dropped.
+        for round in 0u16..3 {
+            let mut transaction = DBTransaction::new();
+            for k in round * 100..(round + 1) * 100 {
+                transaction.set(DBCol::BlockMisc, key(k).to_vec(), vec![42; 100]);
+            }
+            db.write(transaction).unwrap();
+            db.flush().unwrap();
+            let range = (key(round * 100), key(round * 100 + 99));
+            db.db.compact_range_cf(cf_handle, Some(&range.0), Some(&range.1));
+        }
+        assert_eq!(live_file_count(&db, DBCol::BlockMisc), 3);
+        let keys = |db: &RocksDB| {
+            db.iter(DBCol::BlockMisc)
+                .map(|item| u16::from_be_bytes(item.unwrap().0[..].try_into().unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        // Only the file fully in the range is dropped.
+        db.delete_files_in_range(DBCol::BlockMisc, &key(50), &key(250)).unwrap();
+        assert_eq!(live_file_count(&db, DBCol::BlockMisc), 2);
+        assert_eq!(keys(&db), (0..100).chain(200..300).collect::<Vec<_>>());
+        // The upper bound is exclusive so a file ending at it is kept.
+        db.delete_files_in_range(DBCol::BlockMisc, &key(200), &key(299)).unwrap();
+        assert_eq!(live_file_count(&db, DBCol::BlockMisc), 2);
+        db.delete_files_in_range(DBCol::BlockMisc, &key(200), &key(300)).unwrap();
+        assert_eq!(keys(&db), (0..100).collect::<Vec<_>>());
+
+        let err = db.delete_files_in_range(DBCol::State, &key(0), &key(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_periodic_compaction() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let db = std::sync::Arc::new(db);
+        // Make sure it’s the periodic compaction which compacts the files.
+        let cf_handle = db.cf_handle(DBCol::BlockMisc).unwrap();
+        db.db.set_options_cf(cf_handle, &[("disable_auto_compactions", "true")]).unwrap();
+        for round in 0u8..3 {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, vec![round], vec![round]);
+            db.write(transaction).unwrap();
+            db.flush().unwrap();
+        }
+        assert_eq!(db.level_file_counts(DBCol::BlockMisc).unwrap().first(), Some(&3));
+
+        let handle = db
+            .enable_periodic_compaction(DBCol::BlockMisc, std::time::Duration::from_millis(10))
+            .unwrap();
+        // Compaction moves all the data out of level 0 into a single file.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        loop {
+            let counts = db.level_file_counts(DBCol::BlockMisc).unwrap();
+            if counts.first() == Some(&0) && counts.iter().sum::<usize>() == 1 {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "no compaction: {counts:?}");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        drop(handle);
+        assert_eq!(db.iter(DBCol::BlockMisc).count(), 3);
+    }
+
+    #[test]
+    fn test_reset_column() {
+        let (_tmp_dir, mut db) = open_test_rocksdb();
+        let sst_size = |db: &RocksDB| -> u64 {
+            let files = db.db.live_files().unwrap();
+            let files =
+                files.iter().filter(|file| file.column_family_name == col_name(DBCol::BlockMisc));
+            files.map(|file| file.size as u64).sum()
+        };
+        let mut transaction = DBTransaction::new();
+        for key in 0u16..1000 {
+            transaction.set(DBCol::BlockMisc, key.to_be_bytes().to_vec(), vec![42; 1000]);
+        }
+        transaction.set(DBCol::Block, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+        assert!(sst_size(&db) > 0);
+
+        db.reset_column(DBCol::BlockMisc).unwrap();
+        assert_eq!(db.iter(DBCol::BlockMisc).count(), 0);
+        assert_eq!(sst_size(&db), 0);
+        // Other columns are intact.
+        let value = db.get_raw_bytes(DBCol::Block, b"key").unwrap();
+        assert_eq!(value.as_deref(), Some(&b"value"[..]));
+
+        // The column is usable after the reset.
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+        assert_eq!(db.iter(DBCol::BlockMisc).count(), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_background_io_rate_limit() {
+        let config = StoreConfig {
+            background_io_bytes_per_sec: Some(bytesize::MIB),
+            ..StoreConfig::test_config()
+        };
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+        for round in 0u8..3 {
+            let mut transaction = DBTransaction::new();
+            for key in 0u8..100 {
+                transaction.set(DBCol::BlockMisc, vec![round, key], vec![key; 1000]);
+            }
+            db.write(transaction).unwrap();
+            db.flush().unwrap();
+        }
+        db.compact().unwrap();
+        assert_eq!(db.iter(DBCol::BlockMisc).count(), 300);
+        let value = db.get_raw_bytes(DBCol::BlockMisc, &[2, 42]).unwrap();
+        assert_eq!(value.as_deref(), Some(&[42; 1000][..]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_max_background_jobs() {
+        let config = StoreConfig { max_background_jobs: Some(1), ..StoreConfig::test_config() };
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+        for round in 0u8..5 {
+            let mut transaction = DBTransaction::new();
+            for key in 0u8..100 {
+                transaction.set(DBCol::BlockMisc, vec![round, key], vec![key; 100]);
+            }
+            db.write(transaction).unwrap();
+            db.flush().unwrap();
+        }
+        db.compact().unwrap();
+        assert_eq!(db.iter(DBCol::BlockMisc).count(), 500);
+        let value = db.get_raw_bytes(DBCol::BlockMisc, &[4, 42]).unwrap();
+        assert_eq!(value.as_deref(), Some(&[42; 100][..]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_memtable_memory_budget() {
+        let budget = bytesize::ByteSize::mib(1);
+        let config =
+            StoreConfig { memtable_memory_budget: Some(budget), ..StoreConfig::test_config() };
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+        let columns = [DBCol::BlockMisc, DBCol::Block, DBCol::BlockHeader, DBCol::ChunkExtra];
+        for round in 0u16..20 {
+            let mut transaction = DBTransaction::new();
+            for col in columns {
+                for key in 0u16..50 {
+                    let key = [round.to_be_bytes(), key.to_be_bytes()].concat();
+                    transaction.set(col, key, vec![42; 1000]);
+                }
+            }
+            db.write(transaction).unwrap();
+        }
+        // Four columns received about 4 MB in total.  Flushes which kick in
+        // at the budget are asynchronous so wait for them to catch up.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        loop {
+            let usage = db.memtable_memory_usage().unwrap();
+            if usage <= 2 * budget.as_u64() {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "memtables use {usage} bytes");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        for col in columns {
+            assert_eq!(db.iter(col).count(), 1000);
+        }
+
+        let stats = db.get_store_statistics().unwrap();
+        let stat = stats.data.iter().find(|(name, _)| name == "rocksdb.memtable-memory-usage");
+        assert!(matches!(stat, Some((_, values)) if matches!(values[..], [StatsValue::Count(_)])));
+    }
+
+    #[test]
+    fn test_strict_rc_reads() {
+        let config = StoreConfig { strict_rc_reads: true, ..StoreConfig::test_config() };
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+        let mut capture = near_o11y::testonly::TracingCapture::enable_with_events();
+        let is_warning = |log: &String| log.contains("Raw read of a reference counted column");
+
+        db.get_raw_bytes(DBCol::BlockMisc, b"key").unwrap();
+        db.get_with_rc_stripped(DBCol::State, b"key").unwrap();
+        assert!(!capture.drain().iter().any(is_warning));
+
+        db.get_raw_bytes(DBCol::State, b"key").unwrap();
+        let logs = capture.drain();
+        assert!(logs.iter().any(|log| is_warning(log) && log.contains("col=State")), "{logs:?}");
+
+        // Nothing is logged when not in strict mode.
+        let (_tmp_dir, db) = open_test_rocksdb();
+        db.get_raw_bytes(DBCol::State, b"key").unwrap();
+        assert!(!capture.drain().iter().any(is_warning));
+    }
+
+    #[test]
+    fn test_slow_write_logged() {
+        let config = StoreConfig {
+            slow_write_threshold: Some(std::time::Duration::ZERO),
+            ..StoreConfig::test_config()
+        };
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+        let mut capture = near_o11y::testonly::TracingCapture::enable_with_events();
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        transaction.delete(DBCol::Block, b"key".to_vec());
+        db.write(transaction).unwrap();
+
+        let logs = capture.drain();
+        let log = logs.iter().find(|log| log.contains("Slow write")).expect("no slow write log");
+        assert!(log.contains("ops=2"), "{log}");
+        assert!(log.contains("size=11"), "{log}");
+        assert!(log.contains("BlockMisc: 1 ops, 8 bytes"), "{log}");
+        assert!(log.contains("Block: 1 ops, 3 bytes"), "{log}");
+
+        // Nothing is logged without the threshold.
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+        assert!(!capture.drain().iter().any(|log| log.contains("Slow write")));
+    }
+
+    #[test]
+    fn test_key_location() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+
+        let location = |key: &[u8]| db.key_location(DBCol::BlockMisc, key).unwrap();
+        assert_eq!(location(b"key"), Some(KeyLocation::MemTable));
+        assert_eq!(location(b"missing"), Some(KeyLocation::Absent));
+        db.flush().unwrap();
+        assert_eq!(location(b"key"), Some(KeyLocation::Sst(0)));
+        assert_eq!(location(b"missing"), Some(KeyLocation::Absent));
+
+        let db = crate::db::TestDB::new();
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::BlockMisc, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+        assert_eq!(db.key_location(DBCol::BlockMisc, b"key").unwrap(), Some(KeyLocation::MemTable));
+    }
+
+    #[test]
+    fn test_column_block_size() {
+        use crate::config::ColumnConfig;
+
+        let mut config = StoreConfig::test_config();
+        for (col, size) in [(DBCol::BlockMisc, 4), (DBCol::Block, 64)] {
+            let block_size = Some(bytesize::ByteSize::kib(size));
+            let column_config = ColumnConfig { block_size, ..Default::default() };
+            config.column_overrides.insert(col, column_config);
+        }
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let options = db.effective_options(DBCol::BlockMisc).unwrap();
+        assert!(options.contains("block_size=4096"), "{options}");
+        let options = db.effective_options(DBCol::Block).unwrap();
+        assert!(options.contains("block_size=65536"), "{options}");
+
+        let mut transaction = DBTransaction::new();
+        for col in [DBCol::BlockMisc, DBCol::Block] {
+            for key in 0u16..100 {
+                transaction.set(col, key.to_be_bytes().to_vec(), vec![42; 1000]);
+            }
+        }
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+        for col in [DBCol::BlockMisc, DBCol::Block] {
+            assert_eq!(db.iter(col).count(), 100);
+            let value = db.get_raw_bytes(col, &42u16.to_be_bytes()).unwrap();
+            assert_eq!(value.as_deref(), Some(&[42; 1000][..]));
+        }
+    }
+
+    #[test]
+    fn test_column_write_buffer_size() {
+        use crate::config::ColumnConfig;
+
+        let mut config = StoreConfig::test_config();
+        for (col, size) in [(DBCol::BlockMisc, 1), (DBCol::Block, 64)] {
+            let write_buffer_size = Some(bytesize::ByteSize::mib(size));
+            let column_config = ColumnConfig { write_buffer_size, ..Default::default() };
+            config.column_overrides.insert(col, column_config);
+        }
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let options = db.effective_options(DBCol::BlockMisc).unwrap();
+        assert!(options.contains("write_buffer_size=1048576"), "{options}");
+        let options = db.effective_options(DBCol::Block).unwrap();
+        assert!(options.contains("write_buffer_size=67108864"), "{options}");
+
+        for col in [DBCol::BlockMisc, DBCol::Block] {
+            let mut transaction = DBTransaction::new();
+            for key in 0u16..100 {
+                transaction.set(col, key.to_be_bytes().to_vec(), vec![42; 1000]);
+            }
+            db.write(transaction).unwrap();
+            db.flush_column(col).unwrap();
+            assert!(live_file_count(&db, col) >= 1);
+            assert_eq!(db.iter(col).count(), 100);
+        }
+    }
+
+    #[test]
+    fn test_bloom_bits_per_key() {
+        use crate::config::ColumnConfig;
+
+        let mut config = StoreConfig::test_config();
+        for (col, bits) in [(DBCol::BlockMisc, 4.0), (DBCol::Block, 20.0)] {
+            let column_config =
+                ColumnConfig { bloom_bits_per_key: Some(bits), ..Default::default() };
+            config.column_overrides.insert(col, column_config);
+        }
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let mut transaction = DBTransaction::new();
+        for col in [DBCol::BlockMisc, DBCol::Block, DBCol::BlockHeader] {
+            for key in (0u16..100).step_by(2) {
+                transaction.set(col, key.to_be_bytes().to_vec(), key.to_le_bytes().to_vec());
+            }
+        }
+        db.write(transaction).unwrap();
+        // Bloom filters live in SST files so make sure the data is flushed.
+        db.flush().unwrap();
+        for col in [DBCol::BlockMisc, DBCol::Block, DBCol::BlockHeader] {
+            for key in 0u16..100 {
+                let value = db.get_raw_bytes(col, &key.to_be_bytes()).unwrap();
+                let want = (key % 2 == 0).then(|| key.to_le_bytes());
+                assert_eq!(value.as_deref(), want.as_ref().map(|want| &want[..]), "{col} {key}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_format() {
+        use crate::config::ColumnConfig;
+
+        let mut config = StoreConfig::test_config();
+        let formats = [
+            (DBCol::BlockMisc, TableFormat::BlockBased { partitioned_index: true }),
+            (
+                DBCol::Block,
+                TableFormat::Plain {
+                    prefix_len: 1,
+                    bloom_bits_per_key: 10,
+                    hash_table_ratio: 0.75,
+                    index_sparseness: 16,
+                },
+            ),
+        ];
+        for (col, format) in formats {
+            let column_config = ColumnConfig { table_format: Some(format), ..Default::default() };
+            config.column_overrides.insert(col, column_config);
+        }
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let options = db.effective_options(DBCol::BlockMisc).unwrap();
+        assert!(options.contains("index_type=kTwoLevelIndexSearch"), "{options}");
+        let options = db.effective_options(DBCol::Block).unwrap();
+        assert!(options.contains("[TableOptions/PlainTable"), "{options}");
+        assert!(options.contains("prefix_extractor=rocksdb.FixedPrefix.1"), "{options}");
+
+        let mut transaction = DBTransaction::new();
+        for col in [DBCol::BlockMisc, DBCol::Block] {
+            for key in (0u16..1000).step_by(2) {
+                transaction.set(col, key.to_be_bytes().to_vec(), key.to_le_bytes().to_vec());
+            }
+        }
+        db.write(transaction).unwrap();
+        // Make sure the reads go to SST files in the configured format.
+        db.flush().unwrap();
+        for col in [DBCol::BlockMisc, DBCol::Block] {
+            assert_eq!(db.iter(col).count(), 500, "{col}");
+            for key in 0u16..1000 {
+                let value = db.get_raw_bytes(col, &key.to_be_bytes()).unwrap();
+                let want = (key % 2 == 0).then(|| key.to_le_bytes());
+                assert_eq!(value.as_deref(), want.as_ref().map(|want| &want[..]), "{col} {key}");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_compaction_style() {
+        use crate::config::ColumnConfig;
+
+        let mut config = StoreConfig::test_config();
+        for (col, style) in
+            [(DBCol::BlockMisc, CompactionStyle::Universal), (DBCol::Block, CompactionStyle::Level)]
+        {
+            let column_config =
+                ColumnConfig { compaction_style: Some(style), ..Default::default() };
+            config.column_overrides.insert(col, column_config);
+        }
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let options = db.effective_options(DBCol::BlockMisc).unwrap();
+        assert!(options.contains("compaction_style=kCompactionStyleUniversal"), "{options}");
+        let options = db.effective_options(DBCol::Block).unwrap();
+        assert!(options.contains("compaction_style=kCompactionStyleLevel"), "{options}");
+
+        for round in 0u8..3 {
+            let mut transaction = DBTransaction::new();
+            for col in [DBCol::BlockMisc, DBCol::Block] {
+                transaction.set(col, vec![round], vec![round]);
+            }
+            db.write(transaction).unwrap();
+            db.flush().unwrap();
+        }
+        db.compact().unwrap();
+        for col in [DBCol::BlockMisc, DBCol::Block] {
+            let keys = db.iter(col).map(|item| item.unwrap().0.to_vec()).collect::<Vec<_>>();
+            assert_eq!(keys, [[0], [1], [2]]);
         }
-        self.db.cancel_all_background_work(true);
     }
-}
 
-/// Parses a string containing RocksDB statistics.
-/// Results are added into provided 'result' parameter.
-fn parse_statistics(
-    statistics: &str,
-    result: &mut StoreStatistics,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Statistics are given one per line.
-    for line in statistics.lines() {
-        // Each line follows one of two formats:
-        // 1) <stat_name> COUNT : <value>
-        // 2) <stat_name> P50 : <value> P90 : <value> COUNT : <value> SUM : <value>
-        // Each line gets split into words and we parse statistics according to this format.
-        if let Some((stat_name, words)) = line.split_once(' ') {
-            let mut values = vec![];
-            let mut words = words.split(" : ").flat_map(|v| v.split(" "));
-            while let (Some(key), Some(val)) = (words.next(), words.next()) {
-                match key {
-                    "COUNT" => values.push(StatsValue::Count(val.parse::<i64>()?)),
-                    "SUM" => values.push(StatsValue::Sum(val.parse::<i64>()?)),
-                    p if p.starts_with("P") => values.push(StatsValue::Percentile(
-                        key[1..].parse::<u32>()?,
-                        val.parse::<f64>()?,
-                    )),
-                    _ => {
-                        warn!(target: "stats", "Unsupported stats value: {key} in {line}");
-                    }
-                }
-            }
-            // We push some stats to result, even if later parsing will fail.
-            result.data.push((stat_name.to_string(), values));
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_pause_background_work() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let disabled = |db: &RocksDB, col| {
+            let options = db.effective_options(col).unwrap();
+            options
+                .lines()
+                .find_map(|line| line.strip_prefix("disable_auto_compactions="))
+                .map(str::to_owned)
+        };
+        let write = |db: &RocksDB, key: u8| {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, vec![key], vec![key]);
+            db.write(transaction).unwrap();
+        };
+        assert_eq!(disabled(&db, DBCol::BlockMisc).as_deref(), Some("false"));
+
+        let guard = db.pause_background_work().unwrap();
+        let nested = db.pause_background_work().unwrap();
+        for col in [DBCol::BlockMisc, DBCol::State] {
+            assert_eq!(disabled(&db, col).as_deref(), Some("true"));
         }
+        // Writes are still accepted during the pause.
+        write(&db, 1);
+        drop(nested);
+        assert_eq!(disabled(&db, DBCol::BlockMisc).as_deref(), Some("true"));
+        drop(guard);
+        for col in [DBCol::BlockMisc, DBCol::State] {
+            assert_eq!(disabled(&db, col).as_deref(), Some("false"));
+        }
+
+        write(&db, 2);
+        db.flush().unwrap();
+        db.compact().unwrap();
+        let got =
+            db.iter(DBCol::BlockMisc).map(|item| item.unwrap().0.into_vec()).collect::<Vec<_>>();
+        assert_eq!(got, [vec![1u8], vec![2u8]]);
     }
-    Ok(())
-}
 
-/// Returns name of a RocksDB column family corresponding to given column.
-///
-/// Historically we used `col##` names (with `##` being index of the column).
-/// We have since deprecated this convention.  All future column families are
-/// named the same as the variant of the [`DBCol`] enum.
-fn col_name(col: DBCol) -> &'static str {
-    match col {
-        DBCol::DbVersion => "col0",
-        DBCol::BlockMisc => "col1",
-        DBCol::Block => "col2",
-        DBCol::BlockHeader => "col3",
-        DBCol::BlockHeight => "col4",
-        DBCol::State => "col5",
-        DBCol::ChunkExtra => "col6",
-        DBCol::_TransactionResult => "col7",
-        DBCol::OutgoingReceipts => "col8",
-        DBCol::IncomingReceipts => "col9",
-        DBCol::_Peers => "col10",
-        DBCol::EpochInfo => "col11",
-        DBCol::BlockInfo => "col12",
-        DBCol::Chunks => "col13",
-        DBCol::PartialChunks => "col14",
-        DBCol::BlocksToCatchup => "col15",
-        DBCol::StateDlInfos => "col16",
-        DBCol::ChallengedBlocks => "col17",
-        DBCol::StateHeaders => "col18",
-        DBCol::InvalidChunks => "col19",
-        DBCol::BlockExtra => "col20",
-        DBCol::BlockPerHeight => "col21",
-        DBCol::StateParts => "col22",
-        DBCol::EpochStart => "col23",
-        DBCol::AccountAnnouncements => "col24",
-        DBCol::NextBlockHashes => "col25",
-        DBCol::EpochLightClientBlocks => "col26",
-        DBCol::ReceiptIdToShardId => "col27",
-        DBCol::_NextBlockWithNewChunk => "col28",
-        DBCol::_LastBlockWithNewChunk => "col29",
-        DBCol::PeerComponent => "col30",
-        DBCol::ComponentEdges => "col31",
-        DBCol::LastComponentNonce => "col32",
-        DBCol::Transactions => "col33",
-        DBCol::_ChunkPerHeightShard => "col34",
-        DBCol::StateChanges => "col35",
-        DBCol::BlockRefCount => "col36",
-        DBCol::TrieChanges => "col37",
-        DBCol::BlockMerkleTree => "col38",
-        DBCol::ChunkHashesByHeight => "col39",
-        DBCol::BlockOrdinal => "col40",
-        DBCol::_GCCount => "col41",
-        DBCol::OutcomeIds => "col42",
-        DBCol::_TransactionRefCount => "col43",
-        DBCol::ProcessedBlockHeights => "col44",
-        DBCol::Receipts => "col45",
-        DBCol::CachedContractCode => "col46",
-        DBCol::EpochValidatorInfo => "col47",
-        DBCol::HeaderHashesByHeight => "col48",
-        DBCol::StateChangesForSplitStates => "col49",
-        // If you’re adding a new column, do *not* create a new case for it.
-        // All new columns are handled by this default case:
-        #[allow(unreachable_patterns)]
-        _ => <&str>::from(col),
+    #[test]
+    fn test_swap_directory() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (path, new_path, old_path) =
+            (tmp_dir.path().join("db"), tmp_dir.path().join("new"), tmp_dir.path().join("old"));
+        let config = StoreConfig::test_config();
+        let open =
+            |path: &Path| RocksDB::open(path, &config, Mode::ReadWrite, Temperature::Hot).unwrap();
+        let write = |db: &RocksDB, key: &[u8], value: &[u8]| {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::BlockMisc, key.to_vec(), value.to_vec());
+            db.write(transaction).unwrap();
+        };
+        let get = |db: &RocksDB, key: &[u8]| {
+            db.get_raw_bytes(DBCol::BlockMisc, key).unwrap().map(|value| value.to_vec())
+        };
+
+        let new_db = open(&new_path);
+        write(&new_db, b"a", b"new");
+        write(&new_db, b"b", b"new");
+        drop(new_db);
+
+        let db = open(&path).with_name("hot");
+        write(&db, b"a", b"old");
+        let db = db.swap_directory(&new_path, &old_path).unwrap();
+        assert_eq!(db.name(), "hot");
+        assert_eq!(db.db.path(), path);
+        assert_eq!(get(&db, b"a").as_deref(), Some(&b"new"[..]));
+        assert_eq!(get(&db, b"b").as_deref(), Some(&b"new"[..]));
+        assert!(!new_path.exists());
+        drop(db);
+
+        // The write done right before the swap made it to the old database.
+        let old_db = open(&old_path);
+        assert_eq!(get(&old_db, b"a").as_deref(), Some(&b"old"[..]));
+        assert_eq!(get(&old_db, b"b"), None);
+        drop(old_db);
+
+        // Failed swap leaves the database where it was.
+        std::fs::remove_dir_all(&old_path).unwrap();
+        let db = open(&path);
+        let result = db.swap_directory(&tmp_dir.path().join("missing"), &old_path);
+        assert!(result.is_err());
+        assert!(!old_path.exists());
+        let db = open(&path);
+        assert_eq!(get(&db, b"a").as_deref(), Some(&b"new"[..]));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::db::{Database, StatsValue};
-    use crate::{DBCol, NodeStorage, StoreStatistics};
-    use assert_matches::assert_matches;
+    #[test]
+    #[cfg(not(feature = "single_thread_rocksdb"))]
+    fn test_compression_ratio() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        assert_eq!(db.compression_ratio(DBCol::BlockMisc).unwrap(), None);
+        let mut transaction = DBTransaction::new();
+        for key in 0u16..1000 {
+            transaction.set(DBCol::BlockMisc, key.to_be_bytes().to_vec(), vec![42; 1000]);
+        }
+        db.write(transaction).unwrap();
+        // Compact so that data ends up at the bottommost, compressed level.
+        db.compact().unwrap();
+        let ratio = db.compression_ratio(DBCol::BlockMisc).unwrap().unwrap();
+        assert!((2.0..1000.0).contains(&ratio), "{ratio}");
 
-    use super::*;
+        let db = crate::db::TestDB::new();
+        assert_eq!(db.compression_ratio(DBCol::BlockMisc).unwrap(), None);
+    }
 
     #[test]
-    fn rocksdb_merge_sanity() {
-        let (_tmp_dir, opener) = NodeStorage::test_opener();
-        let store = opener.open().unwrap().get_hot_store();
-        let ptr = (&*store.storage) as *const (dyn Database + 'static);
-        let rocksdb = unsafe { &*(ptr as *const RocksDB) };
-        assert_eq!(store.get(DBCol::State, &[1]).unwrap(), None);
-        {
-            let mut store_update = store.store_update();
-            store_update.increment_refcount(DBCol::State, &[1], &[1]);
-            store_update.commit().unwrap();
+    fn test_read_options_readahead() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let mut transaction = DBTransaction::new();
+        for key in 0u16..1000 {
+            transaction.set(DBCol::BlockMisc, key.to_be_bytes().to_vec(), vec![42; 100]);
         }
-        {
-            let mut store_update = store.store_update();
-            store_update.increment_refcount(DBCol::State, &[1], &[1]);
-            store_update.commit().unwrap();
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+
+        let opts = crate::db::ReadOptions {
+            readahead_bytes: Some(4 * bytesize::MIB as usize),
+            ..Default::default()
+        };
+        let got =
+            db.iter_with_opts(DBCol::BlockMisc, &opts).map(Result::unwrap).collect::<Vec<_>>();
+        let want = db.iter(DBCol::BlockMisc).map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(got.len(), 1000);
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn test_request_compaction_priority() {
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let trigger = |db: &RocksDB| {
+            let options = db.effective_options(DBCol::BlockMisc).unwrap();
+            options
+                .lines()
+                .find_map(|line| line.strip_prefix("level0_file_num_compaction_trigger="))
+                .map(str::to_owned)
+        };
+        assert_eq!(trigger(&db).as_deref(), Some("2"));
+        db.request_compaction_priority(DBCol::BlockMisc, CompactionPriority::High).unwrap();
+        assert_eq!(trigger(&db).as_deref(), Some("1"));
+        db.request_compaction_priority(DBCol::BlockMisc, CompactionPriority::Normal).unwrap();
+        assert_eq!(trigger(&db).as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_bloom_filter_stats() {
+        const COLUMN: DBCol = DBCol::BlockMisc;
+        let config = StoreConfig { enable_statistics: true, ..StoreConfig::test_config() };
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        // Bloom filters live in SST files so make sure the data is flushed.
+        // Keys are even numbers so odd numbers fall within the file’s key
+        // range but don’t exist.
+        let mut transaction = DBTransaction::new();
+        for key in (0u8..200).step_by(2) {
+            transaction.set(COLUMN, vec![key], vec![key]);
         }
-        assert_eq!(store.get(DBCol::State, &[1]).unwrap().as_deref(), Some(&[1][..]));
-        assert_eq!(
-            rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap().as_deref(),
-            Some(&[1, 2, 0, 0, 0, 0, 0, 0, 0][..])
-        );
-        {
-            let mut store_update = store.store_update();
-            store_update.decrement_refcount(DBCol::State, &[1]);
-            store_update.commit().unwrap();
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+
+        let before = db.bloom_filter_stats(COLUMN).unwrap();
+        for key in (1u8..200).step_by(2) {
+            assert_eq!(db.get_raw_bytes(COLUMN, &[key]).unwrap(), None);
         }
-        assert_eq!(store.get(DBCol::State, &[1]).unwrap().as_deref(), Some(&[1][..]));
+        let after = db.bloom_filter_stats(COLUMN).unwrap();
+        assert!(after.useful > before.useful, "{before:?} {after:?}");
+        assert!(after.checked() >= before.checked() + 100, "{before:?} {after:?}");
+
+        // Without statistics enabled, the stats aren’t available.
+        let (_tmp_dir, db) = open_test_rocksdb();
+        db.bloom_filter_stats(COLUMN).unwrap_err();
         assert_eq!(
-            rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap().as_deref(),
-            Some(&[1, 1, 0, 0, 0, 0, 0, 0, 0][..])
+            crate::db::TestDB::new().bloom_filter_stats(COLUMN).unwrap(),
+            BloomStats::default()
         );
-        {
-            let mut store_update = store.store_update();
-            store_update.decrement_refcount(DBCol::State, &[1]);
-            store_update.commit().unwrap();
+    }
+
+    #[test]
+    fn test_column_statistics() {
+        use crate::config::ColumnConfig;
+
+        const COLUMN: DBCol = DBCol::BlockMisc;
+        let mut config = StoreConfig::test_config();
+        config
+            .column_overrides
+            .insert(COLUMN, ColumnConfig { statistics: true, ..Default::default() });
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+        let mut transaction = DBTransaction::new();
+        transaction.set(COLUMN, b"foo".to_vec(), b"bar".to_vec());
+        db.write(transaction).unwrap();
+
+        let stats = db.get_store_statistics().unwrap();
+        let mut names = Vec::new();
+        for (name, values) in stats.data {
+            for value in values {
+                match value {
+                    StatsValue::ColumnValue(col, _) => assert_eq!(COLUMN, col, "{name}"),
+                    value => panic!("unexpected {name} value {value:?}"),
+                }
+            }
+            names.push(name);
         }
-        // Refcount goes to 0 -> get() returns None
-        assert_eq!(store.get(DBCol::State, &[1]).unwrap(), None);
-        // Internally there is an empty value
-        assert_eq!(rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap().as_deref(), Some(&[][..]));
+        assert!(names.iter().any(|name| name == "rocksdb.estimate-num-keys"), "{names:?}");
 
-        // single_thread_rocksdb makes compact hang forever
-        if !cfg!(feature = "single_thread_rocksdb") {
-            let none = Option::<&[u8]>::None;
-            let cf = rocksdb.cf_handle(DBCol::State).unwrap();
+        // Without per-column statistics, all the columns are reported.
+        let (_tmp_dir, db) = open_test_rocksdb();
+        let stats = db.get_store_statistics().unwrap();
+        let (_, values) =
+            stats.data.iter().find(|(name, _)| name == "rocksdb.live-sst-files-size").unwrap();
+        assert!(values.len() > 1);
+        assert!(!stats.data.iter().any(|(name, _)| name == "rocksdb.estimate-num-keys"));
+    }
 
-            // I’m not sure why but we need to run compaction twice.  If we run
-            // it only once, we end up with an empty value for the key.  This is
-            // surprising because I assumed that compaction filter would discard
-            // empty values.
-            rocksdb.db.compact_range_cf(cf, none, none);
-            assert_eq!(
-                rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap().as_deref(),
-                Some(&[][..])
-            );
-            assert_eq!(store.get(DBCol::State, &[1]).unwrap(), None);
+    #[test]
+    #[cfg(feature = "key_mapper")]
+    fn test_key_mapper() {
+        const COLUMN: DBCol = DBCol::BlockMisc;
+        let (_tmp_dir, mut db) = open_test_rocksdb();
+        db.set_key_mapper(COLUMN, Box::new(|key: &[u8]| [&b"shard0/"[..], key].concat()));
 
-            rocksdb.db.compact_range_cf(cf, none, none);
-            assert_eq!(rocksdb.get_raw_bytes(DBCol::State, &[1]).unwrap(), None);
-            assert_eq!(store.get(DBCol::State, &[1]).unwrap(), None);
+        let mut transaction = DBTransaction::new();
+        transaction.set(COLUMN, b"foo".to_vec(), b"bar".to_vec());
+        transaction.set(COLUMN, b"baz".to_vec(), b"qux".to_vec());
+        db.write(transaction).unwrap();
+
+        // Point reads apply the same mapping.
+        assert_eq!(db.get_raw_bytes(COLUMN, b"foo").unwrap().as_deref(), Some(&b"bar"[..]));
+        // Iterators return the keys as stored.
+        let keys = db.iter(COLUMN).map(|item| item.unwrap().0.into_vec()).collect::<Vec<_>>();
+        assert_eq!(keys, [b"shard0/baz".to_vec(), b"shard0/foo".to_vec()]);
+
+        let mut transaction = DBTransaction::new();
+        transaction.delete(COLUMN, b"foo".to_vec());
+        db.write(transaction).unwrap();
+        assert_eq!(db.get_raw_bytes(COLUMN, b"foo").unwrap(), None);
+
+        // Other columns are not affected.
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::Block, b"foo".to_vec(), b"bar".to_vec());
+        db.write(transaction).unwrap();
+        let keys = db.iter(DBCol::Block).map(|item| item.unwrap().0.into_vec()).collect::<Vec<_>>();
+        assert_eq!(keys, [b"foo".to_vec()]);
+    }
+
+    #[test]
+    fn test_repair() {
+        let (tmp_dir, db) = open_test_rocksdb();
+        let mut transaction = DBTransaction::new();
+        for key in 0u8..100 {
+            transaction.set(DBCol::BlockMisc, vec![key], vec![key]);
         }
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        let config = StoreConfig::test_config();
+        let report = RocksDB::repair(tmp_dir.path(), &config).unwrap();
+        assert_eq!(report.lost_files, Vec::<std::path::PathBuf>::new());
+        assert!(report.sst_files > 0);
+
+        let db = RocksDB::open(tmp_dir.path(), &config, Mode::ReadWrite, Temperature::Hot).unwrap();
+        assert_eq!(db.iter(DBCol::BlockMisc).count(), 100);
+        assert_eq!(db.get_raw_bytes(DBCol::BlockMisc, &[42]).unwrap().as_deref(), Some(&[42][..]));
     }
 
     #[test]
-    fn test_parse_statistics() {
-        let statistics = "rocksdb.cold.file.read.count COUNT : 999\n\
-         rocksdb.db.get.micros P50 : 9.171086 P95 : 222.678751 P99 : 549.611652 P100 : 45816.000000 COUNT : 917578 SUM : 38313754";
-        let mut result = StoreStatistics { data: vec![] };
-        let parse_result = parse_statistics(statistics, &mut result);
-        // We should be able to parse stats and the result should be Ok(()).
-        parse_result.unwrap();
-        assert_eq!(
-            result,
-            StoreStatistics {
-                data: vec![
-                    ("rocksdb.cold.file.read.count".to_string(), vec![StatsValue::Count(999)]),
-                    (
-                        "rocksdb.db.get.micros".to_string(),
-                        vec![
-                            StatsValue::Percentile(50, 9.171086),
-                            StatsValue::Percentile(95, 222.678751),
-                            StatsValue::Percentile(99, 549.611652),
-                            StatsValue::Percentile(100, 45816.0),
-                            StatsValue::Count(917578),
-                            StatsValue::Sum(38313754)
-                        ]
-                    )
-                ]
+    fn test_regenerable_column_skips_wal() {
+        use crate::config::ColumnConfig;
+
+        const REGENERABLE: DBCol = DBCol::BlockMisc;
+        let mut config = StoreConfig::test_config();
+        config
+            .column_overrides
+            .insert(REGENERABLE, ColumnConfig { regenerable: true, ..Default::default() });
+        let (tmp_dir, db) = open_test_rocksdb_with_config(&config);
+        let wal_size = || {
+            std::fs::read_dir(tmp_dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.path().extension() == Some("log".as_ref()))
+                .map(|entry| entry.metadata().unwrap().len())
+                .sum::<u64>()
+        };
+        let write = |cols: &[DBCol]| {
+            let mut transaction = DBTransaction::new();
+            for &col in cols {
+                transaction.set(col, b"foo".to_vec(), vec![7; 1000]);
             }
-        );
+            db.write(transaction).unwrap();
+        };
+
+        let size = wal_size();
+        write(&[REGENERABLE]);
+        assert_eq!(wal_size(), size);
+        assert_eq!(db.get_raw_bytes(REGENERABLE, b"foo").unwrap().as_deref(), Some(&[7; 1000][..]));
+
+        // Touching any other column uses the log for the whole batch.
+        write(&[REGENERABLE, DBCol::Block]);
+        let size_mixed = wal_size();
+        assert!(size_mixed >= size + 2000, "{size} {size_mixed}");
+
+        write(&[DBCol::Block]);
+        assert!(wal_size() >= size_mixed + 1000);
     }
 
     #[test]
-    fn test_delete_range() {
-        let store = NodeStorage::test_opener().1.open().unwrap().get_hot_store();
-        let keys = [vec![0], vec![1], vec![2], vec![3]];
-        let column = DBCol::Block;
+    fn test_flush_on_drop() {
+        use crate::config::ColumnConfig;
 
-        let mut store_update = store.store_update();
-        for key in &keys {
-            store_update.insert(column, key.clone(), vec![42]);
+        // Writes to regenerable columns skip the write-ahead log so only
+        // a flush makes them survive closing the database.
+        const REGENERABLE: DBCol = DBCol::BlockMisc;
+        let mut config = StoreConfig::test_config();
+        config
+            .column_overrides
+            .insert(REGENERABLE, ColumnConfig { regenerable: true, ..Default::default() });
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let open = || RocksDB::open(tmp_dir.path(), &config, Mode::ReadWrite, Temperature::Hot);
+        let write = |db: &RocksDB, key: &[u8]| {
+            // Separate batches since touching any other column makes the
+            // whole batch use the log.
+            for col in [REGENERABLE, DBCol::Block] {
+                let mut transaction = DBTransaction::new();
+                transaction.set(col, key.to_vec(), b"value".to_vec());
+                db.write(transaction).unwrap();
+            }
+        };
+
+        let db = open().unwrap();
+        write(&db, b"flushed");
+        drop(db);
+        let mut db = open().unwrap();
+        for col in [REGENERABLE, DBCol::Block] {
+            let value = db.get_raw_bytes(col, b"flushed").unwrap();
+            assert_eq!(value.as_deref(), Some(&b"value"[..]), "{col}");
         }
-        store_update.commit().unwrap();
 
-        let mut store_update = store.store_update();
-        store_update.delete_range(column, &keys[1], &keys[3]);
-        store_update.commit().unwrap();
+        db.set_flush_on_drop(false).unwrap();
+        write(&db, b"unflushed");
+        drop(db);
+        let db = open().unwrap();
+        assert!(db.get_raw_bytes(REGENERABLE, b"unflushed").unwrap().is_none());
+        // Writes using the log are recovered from it.
+        let value = db.get_raw_bytes(DBCol::Block, b"unflushed").unwrap();
+        assert_eq!(value.as_deref(), Some(&b"value"[..]));
+    }
 
-        assert_matches!(store.exists(column, &keys[0]), Ok(true));
-        assert_matches!(store.exists(column, &keys[1]), Ok(false));
-        assert_matches!(store.exists(column, &keys[2]), Ok(false));
-        assert_matches!(store.exists(column, &keys[3]), Ok(true));
+    #[test]
+    fn test_cache_pinned_column() {
+        use crate::config::ColumnConfig;
+
+        const COLUMN: DBCol = DBCol::EpochInfo;
+        let mut config = StoreConfig::test_config();
+        let column_config = ColumnConfig {
+            cache_pinned: true,
+            cache_size: Some(bytesize::ByteSize::mib(1)),
+            ..Default::default()
+        };
+        config.column_overrides.insert(COLUMN, column_config);
+        let (_tmp_dir, db) = open_test_rocksdb_with_config(&config);
+
+        let mut transaction = DBTransaction::new();
+        for key in 0u8..100 {
+            transaction.set(COLUMN, vec![key], vec![key; 100]);
+        }
+        db.write(transaction).unwrap();
+        db.flush().unwrap();
+
+        for key in 0u8..100 {
+            let value = db.get_raw_bytes(COLUMN, &[key]).unwrap();
+            assert_eq!(value.as_deref(), Some(&[key; 100][..]));
+        }
+        assert_eq!(db.get_raw_bytes(COLUMN, &[200]).unwrap(), None);
+
+        let options = db.effective_options(COLUMN).unwrap();
+        assert!(options.contains("cache_index_and_filter_blocks=true"), "{options}");
+        assert!(options.contains("pin_top_level_index_and_filter=true"), "{options}");
     }
 }
@@ -30,6 +30,15 @@ impl<'a> DBSlice<'a> {
         }
     }
 
+    /// Returns the data as a fixed-size array or `None` if its length isn’t
+    /// `N`.
+    ///
+    /// Useful for reading fixed-size values such as hashes without converting
+    /// them by hand.
+    pub fn as_array<const N: usize>(&self) -> Option<&[u8; N]> {
+        self.as_slice().try_into().ok()
+    }
+
     /// Constructs the object from a vector.
     ///
     /// In the current implementation, this is a zero-copy operation.
@@ -58,6 +67,18 @@ impl<'a> DBSlice<'a> {
             Inner::Rocks(rocks) => rocks.strip_refcount().map(|rocks| Self(Inner::Rocks(rocks))),
         }
     }
+
+    /// Decodes and strips reference count from the data returning it along
+    /// with the value.
+    ///
+    /// Like [`Self::strip_refcount`], returns `None` if the reference count is
+    /// non-positive.
+    pub(super) fn split_refcount(self) -> Option<(Self, i64)> {
+        let (Some(_), rc) = refcount::decode_value_with_rc(self.as_slice()) else {
+            return None;
+        };
+        Some((self.strip_refcount()?, rc))
+    }
 }
 
 /// A slice owned by the RocksDB with RAII mechanism for letting RocksDB know
@@ -142,3 +163,17 @@ impl<'a> std::cmp::PartialEq<[u8]> for DBSlice<'a> {
         self.as_slice() == other
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DBSlice;
+
+    #[test]
+    fn test_as_array() {
+        let slice = DBSlice::from_vec(vec![1, 2, 3, 4]);
+        assert_eq!(slice.as_array::<4>(), Some(&[1, 2, 3, 4]));
+        assert_eq!(slice.as_array::<3>(), None);
+        assert_eq!(slice.as_array::<5>(), None);
+        assert_eq!(DBSlice::from_vec(Vec::new()).as_array::<0>(), Some(&[]));
+    }
+}
@@ -1,11 +1,18 @@
 use itertools::{self, EitherOrBoth};
 use std::cmp::Ordering;
 use std::io;
-use std::sync::Arc;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use near_o11y::log_assert_fail;
 
-use crate::db::{DBIterator, DBIteratorItem, DBSlice, DBTransaction, Database, StoreStatistics};
+use near_fmt::StorageKey;
+
+use crate::db::{
+    refcount, DBIterator, DBIteratorItem, DBOp, DBSlice, DBTransaction, Database, StoreStatistics,
+    WriteId, SPLIT_WRITE_PENDING_KEY,
+};
 use crate::DBCol;
 
 /// A database that provides access to the hot and cold databases.
@@ -18,14 +25,236 @@ use crate::DBCol;
 ///
 /// This database should be treated as read-only but it is not enforced because
 /// even the view client writes to the database in order to update caches.
+/// With read repair enabled (see [`Self::with_read_repair`]) reads may write
+/// to the hot database as well.
 pub struct SplitDB {
     hot: Arc<dyn Database>,
     cold: Arc<dyn Database>,
+    /// Serialises [`Self::write_both`] calls so that the pending write marker
+    /// is owned by a single write at a time.
+    write_both_lock: Mutex<()>,
+    read_repair: Option<ReadRepair>,
+}
+
+/// Configuration of read repair; see [`SplitDB::with_read_repair`].
+///
+/// The default policy has no columns and so repairs nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadRepairPolicy {
+    /// Columns whose values read from the cold database are copied to the hot
+    /// one.  Columns which aren’t cold are ignored.
+    pub columns: Vec<DBCol>,
+    /// Maximum number of repair writes per second.  Values read once the
+    /// limit is reached are returned but not copied.
+    pub max_repairs_per_second: u32,
+    /// Maximum number of keys repaired over the lifetime of the database.
+    /// Once reached, no more values are copied until the repaired ones are
+    /// removed with [`SplitDB::remove_repaired_values`].
+    pub max_repaired_keys: usize,
+}
+
+/// Read repair policy together with the state of its limits.
+struct ReadRepair {
+    policy: ReadRepairPolicy,
+    state: Mutex<ReadRepairState>,
+}
+
+struct ReadRepairState {
+    /// Start of the current one-second window.
+    window_start: Instant,
+    /// Number of repairs done in the current window.
+    window_repairs: u32,
+    /// Keys written to the hot database by read repair and not removed since.
+    repaired: Vec<(DBCol, Vec<u8>)>,
+}
+
+/// Sizes of the hot and cold databases on disk; see [`SplitDB::disk_sizes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitDiskSize {
+    pub hot: u64,
+    pub cold: u64,
 }
 
 impl SplitDB {
     pub fn new(hot: Arc<dyn Database>, cold: Arc<dyn Database>) -> Arc<Self> {
-        return Arc::new(SplitDB { hot, cold });
+        return Arc::new(SplitDB { hot, cold, write_both_lock: Mutex::new(()), read_repair: None });
+    }
+
+    /// Enables read repair: values of cold columns which are read from the
+    /// cold database because they’re missing in the hot one are written to
+    /// the hot database so that following reads of recently accessed archival
+    /// data are served from hot storage.
+    ///
+    /// Only keys missing in the hot database are repaired; values present in
+    /// hot storage are always returned as is.  A failed repair is logged and
+    /// doesn’t fail the read.
+    ///
+    /// Every repaired read turns into a write so scans over archival data can
+    /// considerably increase both write load and size of the hot database
+    /// (and writes done by a read-only process, e.g. the view client, may
+    /// interfere with its owner).  The rate limit of the policy bounds the
+    /// former.
+    ///
+    /// Values read from the cold database belong to heights below the garbage
+    /// collection tail of the hot database so hot garbage collection never
+    /// removes them.  Repaired values stay in hot storage until
+    /// [`Self::remove_repaired_values`] is called; repaired keys are tracked
+    /// in memory only so values repaired by a process which exits without
+    /// calling it stay in the hot database permanently.  `max_repaired_keys`
+    /// bounds how many keys a single database can leak that way.
+    ///
+    /// **Panics** if the database has already been shared, i.e. this must be
+    /// called on the value returned by [`Self::new`].
+    pub fn with_read_repair(mut self: Arc<Self>, policy: ReadRepairPolicy) -> Arc<Self> {
+        let this = Arc::get_mut(&mut self).expect("read repair must be set before sharing SplitDB");
+        let state = ReadRepairState {
+            window_start: Instant::now(),
+            window_repairs: 0,
+            repaired: Vec::new(),
+        };
+        this.read_repair = Some(ReadRepair { policy, state: Mutex::new(state) });
+        self
+    }
+
+    /// Removes from the hot database values written there by read repair.
+    /// Reference counted values have their reference count decreased by one;
+    /// other values are deleted.  The values remain readable from the cold
+    /// database.  Returns number of removed keys.
+    pub fn remove_repaired_values(&self) -> io::Result<usize> {
+        let Some(repair) = &self.read_repair else { return Ok(0) };
+        let mut state = repair.state.lock().unwrap();
+        let mut transaction = DBTransaction::new();
+        for (col, key) in &state.repaired {
+            if col.is_rc() {
+                let one = NonZeroU32::new(1).unwrap();
+                transaction.update_refcount(
+                    *col,
+                    key.clone(),
+                    refcount::encode_negative_refcount(one),
+                );
+            } else {
+                transaction.delete(*col, key.clone());
+            }
+        }
+        self.hot.write(transaction)?;
+        Ok(std::mem::take(&mut state.repaired).len())
+    }
+
+    /// Writes to the hot database a value read from the cold database if read
+    /// repair is enabled for the column and its limits allow it.  `value`
+    /// includes reference count in reference counted columns.
+    fn repair(&self, col: DBCol, key: &[u8], value: &[u8]) {
+        let Some(repair) = &self.read_repair else { return };
+        if !repair.policy.columns.contains(&col) {
+            return;
+        }
+        // The lock is held over the write so that the limits are exact and
+        // a concurrent remove_repaired_values sees all repaired keys.
+        let mut state = repair.state.lock().unwrap();
+        if state.repaired.len() >= repair.policy.max_repaired_keys {
+            return;
+        }
+        let now = Instant::now();
+        if now.duration_since(state.window_start) >= Duration::from_secs(1) {
+            state.window_start = now;
+            state.window_repairs = 0;
+        }
+        if state.window_repairs >= repair.policy.max_repairs_per_second {
+            return;
+        }
+        state.window_repairs += 1;
+        let mut transaction = DBTransaction::new();
+        if col.is_rc() {
+            transaction.update_refcount(col, key.to_vec(), value.to_vec());
+        } else if col.is_insert_only() {
+            transaction.insert(col, key.to_vec(), value.to_vec());
+        } else {
+            transaction.set(col, key.to_vec(), value.to_vec());
+        }
+        match self.hot.write(transaction) {
+            Ok(()) => state.repaired.push((col, key.to_vec())),
+            Err(err) => tracing::warn!(
+                target: "store",
+                %col,
+                key = %StorageKey(key),
+                %err,
+                "Failed to repair value read from cold storage"
+            ),
+        }
+    }
+
+    /// Writes the batch to both the cold and the hot database.
+    ///
+    /// Unlike [`Database::write`], which touches the hot database only, this
+    /// applies operations on cold columns to the cold database as well.  The
+    /// two databases cannot be updated atomically so the write is done in
+    /// phases, always in the same order:
+    ///
+    /// 1. [`SPLIT_WRITE_PENDING_KEY`] marker is set in the hot database.
+    /// 2. Set and refcount operations on cold columns are written to the cold
+    ///    database.  Cold writes are idempotent (refcounts are always set to
+    ///    one) so repeating them is harmless.  Delete operations are not
+    ///    applied to cold storage.
+    /// 3. The whole batch is written to the hot database together with
+    ///    removal of the marker in a single atomic write.
+    ///
+    /// Concurrent calls are serialised with a single lock and each call always
+    /// writes cold before hot, so the calls cannot deadlock on each other.
+    ///
+    /// If this returns `Ok`, both databases have been updated.  If it fails in
+    /// the first phase, neither has.  Otherwise the cold database may contain
+    /// data the hot one doesn’t and the marker stays set; see
+    /// [`Self::has_pending_write`].  Repeating the same write until it
+    /// succeeds repairs the inconsistency and clears the marker.
+    pub fn write_both(&self, mut batch: DBTransaction) -> io::Result<()> {
+        // Clearing the marker is part of the hot write.  Add it up front so
+        // that a batch with a limit which cannot fit it fails before anything
+        // is written.
+        let clear_marker =
+            DBOp::Delete { col: DBCol::BlockMisc, key: SPLIT_WRITE_PENDING_KEY.to_vec() };
+        batch.try_push(clear_marker)?;
+
+        let _guard = self.write_both_lock.lock().unwrap();
+
+        let mut marker = DBTransaction::new();
+        marker.set(DBCol::BlockMisc, SPLIT_WRITE_PENDING_KEY.to_vec(), vec![]);
+        self.hot.write(marker)?;
+
+        let cold_ops = batch
+            .ops
+            .iter()
+            .filter(|op| {
+                op.col().is_in_colddb()
+                    && matches!(
+                        op,
+                        DBOp::Set { .. } | DBOp::Insert { .. } | DBOp::UpdateRefcount { .. }
+                    )
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if !cold_ops.is_empty() {
+            let raw_rc_writes = batch.raw_rc_writes;
+            self.cold.write(DBTransaction {
+                ops: cold_ops,
+                raw_rc_writes,
+                ..Default::default()
+            })?;
+        }
+
+        self.hot.write(batch)
+    }
+
+    /// Returns whether a [`Self::write_both`] call failed after it started
+    /// modifying the databases, i.e. whether cold and hot storage may be
+    /// inconsistent.
+    pub fn has_pending_write(&self) -> io::Result<bool> {
+        Ok(self.hot.get_raw_bytes(DBCol::BlockMisc, SPLIT_WRITE_PENDING_KEY)?.is_some())
+    }
+
+    /// Returns sizes of the hot and cold databases on disk; see
+    /// [`Database::total_disk_size`].
+    pub fn disk_sizes(&self) -> io::Result<SplitDiskSize> {
+        Ok(SplitDiskSize { hot: self.hot.total_disk_size()?, cold: self.cold.total_disk_size()? })
     }
 
     /// The cmp function for the DBIteratorItems.
@@ -71,6 +300,10 @@ impl SplitDB {
 }
 
 impl Database for SplitDB {
+    fn name(&self) -> &str {
+        "splitdb"
+    }
+
     /// Returns raw bytes for given `key` ignoring any reference count decoding
     /// if any.
     ///
@@ -81,7 +314,11 @@ impl Database for SplitDB {
             return Ok(Some(hot_result));
         }
         if col.is_cold() {
-            return self.cold.get_raw_bytes(col, key);
+            let value = self.cold.get_raw_bytes(col, key)?;
+            if let Some(value) = &value {
+                self.repair(col, key, value);
+            }
+            return Ok(value);
         }
         Ok(None)
     }
@@ -99,7 +336,35 @@ impl Database for SplitDB {
             return Ok(Some(hot_result));
         }
         if col.is_cold() {
-            return self.cold.get_with_rc_stripped(col, key);
+            let value = self.cold.get_with_rc_stripped(col, key)?;
+            if let Some(value) = &value {
+                let one = NonZeroU32::new(1).unwrap();
+                self.repair(col, key, &refcount::add_positive_refcount(value, one));
+            }
+            return Ok(value);
+        }
+        Ok(None)
+    }
+
+    /// Returns value for given `key` along with its reference count.
+    ///
+    /// **Panics** if the column is not reference counted.
+    ///
+    /// Like [`Self::get_with_rc_stripped`], reads from the hot db first and
+    /// falls back to the cold db.
+    fn get_with_rc(&self, col: DBCol, key: &[u8]) -> io::Result<Option<(DBSlice<'_>, i64)>> {
+        assert!(col.is_rc());
+
+        if let Some(hot_result) = self.hot.get_with_rc(col, key)? {
+            return Ok(Some(hot_result));
+        }
+        if col.is_cold() {
+            let value = self.cold.get_with_rc(col, key)?;
+            if let Some((value, _)) = &value {
+                let one = NonZeroU32::new(1).unwrap();
+                self.repair(col, key, &refcount::add_positive_refcount(value, one));
+            }
+            return Ok(value);
         }
         Ok(None)
     }
@@ -177,6 +442,30 @@ impl Database for SplitDB {
         self.hot.write(batch)
     }
 
+    fn write_with_id(&self, batch: DBTransaction) -> io::Result<WriteId> {
+        self.hot.write_with_id(batch)
+    }
+
+    /// Like writes, updates go to the hot database and thus only see values
+    /// stored in it.
+    fn update(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        self.hot.update(col, key, f)
+    }
+
+    /// Witnesses are checked against the hot database only.
+    fn write_if_unchanged(
+        &self,
+        batch: DBTransaction,
+        witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> io::Result<bool> {
+        self.hot.write_if_unchanged(batch, witnesses)
+    }
+
     fn flush(&self) -> io::Result<()> {
         let msg = "flush is not allowed - the split storage is read only.";
         log_assert_fail!("{}", msg);
@@ -193,6 +482,13 @@ impl Database for SplitDB {
         Ok(())
     }
 
+    /// Returns combined size of the hot and cold databases.  Use
+    /// [`Self::disk_sizes`] to get them separately.
+    fn total_disk_size(&self) -> io::Result<u64> {
+        let sizes = self.disk_sizes()?;
+        Ok(sizes.hot + sizes.cold)
+    }
+
     fn get_store_statistics(&self) -> Option<StoreStatistics> {
         log_assert_fail!("get_store_statistics is not allowed - the split storage has two stores");
         None
@@ -235,13 +531,13 @@ mod test {
 
     fn set(db: &Arc<dyn Database>, col: DBCol, key: &[u8], value: &[u8]) -> () {
         let op = DBOp::Set { col, key: key.to_vec(), value: value.to_vec() };
-        db.write(DBTransaction { ops: vec![op] }).unwrap();
+        db.write(DBTransaction { ops: vec![op], ..Default::default() }).unwrap();
     }
 
     fn set_rc(db: &Arc<dyn Database>, col: DBCol, key: &[u8], value: &[u8]) -> () {
         const ONE: &[u8] = &1i64.to_le_bytes();
         let op = DBOp::UpdateRefcount { col, key: key.to_vec(), value: [&value, ONE].concat() };
-        db.write(DBTransaction { ops: vec![op] }).unwrap();
+        db.write(DBTransaction { ops: vec![op], ..Default::default() }).unwrap();
     }
 
     fn bx<const SIZE: usize>(literal: &[u8; SIZE]) -> Box<[u8]> {
@@ -441,4 +737,149 @@ mod test {
         ];
         assert_eq!(result, expected_result);
     }
+
+    #[test]
+    fn test_write_both() {
+        let hot = create_hot();
+        let cold = create_cold();
+        let split = SplitDB::new(hot.clone(), cold.clone());
+
+        let mut batch = DBTransaction::new();
+        batch.set(DBCol::Block, FOO.to_vec(), FOO_VALUE.to_vec());
+        batch.set(DBCol::BlockMisc, BAR.to_vec(), BAR_VALUE.to_vec());
+        split.write_both(batch).unwrap();
+        assert_eq!(hot.get_raw_bytes(DBCol::Block, FOO).unwrap().as_deref(), Some(FOO_VALUE));
+        assert_eq!(cold.get_raw_bytes(DBCol::Block, FOO).unwrap().as_deref(), Some(FOO_VALUE));
+        assert!(!split.has_pending_write().unwrap());
+    }
+
+    #[test]
+    fn test_write_both_full_batch() {
+        let hot = create_hot();
+        let cold = create_cold();
+        let split = SplitDB::new(hot.clone(), cold.clone());
+
+        // No room for clearing of the marker so nothing gets written.
+        let mut batch = DBTransaction::with_limit(1, 100);
+        batch.set(DBCol::Block, FOO.to_vec(), FOO_VALUE.to_vec());
+        let err = split.write_both(batch).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(cold.get_raw_bytes(DBCol::Block, FOO).unwrap(), None);
+        assert_eq!(hot.get_raw_bytes(DBCol::Block, FOO).unwrap(), None);
+        assert!(!split.has_pending_write().unwrap());
+    }
+
+    #[test]
+    fn test_write_both_hot_failure() {
+        // Let the marker write through but fail writing of the batch itself.
+        let hot = TestDB::new();
+        hot.fail_writes_after(1);
+        let cold = create_cold();
+        let split = SplitDB::new(hot.clone(), cold.clone());
+
+        let mut batch = DBTransaction::new();
+        batch.set(DBCol::Block, FOO.to_vec(), FOO_VALUE.to_vec());
+        split.write_both(batch).unwrap_err();
+
+        // Cold has been written, hot hasn’t and the inconsistency is flagged.
+        assert_eq!(cold.get_raw_bytes(DBCol::Block, FOO).unwrap().as_deref(), Some(FOO_VALUE));
+        assert_eq!(hot.get_raw_bytes(DBCol::Block, FOO).unwrap(), None);
+        assert!(split.has_pending_write().unwrap());
+    }
+
+    #[test]
+    fn test_disk_sizes() {
+        let hot = create_hot();
+        let cold = create_cold();
+        let split = SplitDB::new(hot.clone(), cold.clone());
+
+        set(&hot, DBCol::BlockMisc, FOO, FOO_VALUE);
+        set_rc(&cold, DBCol::State, BAR, BAR_VALUE);
+        let sizes = split.disk_sizes().unwrap();
+        assert_eq!(sizes, SplitDiskSize { hot: 12, cold: 20 });
+        assert_eq!(split.total_disk_size().unwrap(), 32);
+    }
+
+    #[test]
+    fn test_read_repair() {
+        let hot = create_hot();
+        let cold = create_cold();
+        let policy = ReadRepairPolicy {
+            columns: vec![DBCol::Block, DBCol::Transactions],
+            max_repairs_per_second: 100,
+            max_repaired_keys: 100,
+        };
+        let split = SplitDB::new(hot.clone(), cold.clone()).with_read_repair(policy);
+
+        // A cold-only key is present in hot after being read.
+        set(&cold, DBCol::Block, FOO, FOO_VALUE);
+        assert_eq!(hot.get_raw_bytes(DBCol::Block, FOO).unwrap(), None);
+        let value = split.get_raw_bytes(DBCol::Block, FOO).unwrap();
+        assert_eq!(value.as_deref(), Some(FOO_VALUE));
+        assert_eq!(hot.get_raw_bytes(DBCol::Block, FOO).unwrap().as_deref(), Some(FOO_VALUE));
+
+        // Reference counted values are repaired with a reference count of one.
+        set_rc(&cold, DBCol::Transactions, FOO, FOO_VALUE);
+        let value = split.get_with_rc_stripped(DBCol::Transactions, FOO).unwrap();
+        assert_eq!(value.as_deref(), Some(FOO_VALUE));
+        let value = hot.get_with_rc(DBCol::Transactions, FOO).unwrap();
+        assert_eq!(value.map(|(value, rc)| (value.to_vec(), rc)), Some((FOO_VALUE.to_vec(), 1)));
+
+        // Columns outside of the policy aren’t repaired.
+        set(&cold, DBCol::BlockInfo, FOO, FOO_VALUE);
+        let value = split.get_raw_bytes(DBCol::BlockInfo, FOO).unwrap();
+        assert_eq!(value.as_deref(), Some(FOO_VALUE));
+        assert_eq!(hot.get_raw_bytes(DBCol::BlockInfo, FOO).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_repair_rate_limit() {
+        let hot = create_hot();
+        let cold = create_cold();
+        let policy = ReadRepairPolicy {
+            columns: vec![DBCol::Block],
+            max_repairs_per_second: 1,
+            max_repaired_keys: 100,
+        };
+        let split = SplitDB::new(hot.clone(), cold.clone()).with_read_repair(policy);
+
+        set(&cold, DBCol::Block, FOO, FOO_VALUE);
+        set(&cold, DBCol::Block, BAR, BAR_VALUE);
+        split.get_raw_bytes(DBCol::Block, FOO).unwrap();
+        let value = split.get_raw_bytes(DBCol::Block, BAR).unwrap();
+        assert_eq!(value.as_deref(), Some(BAR_VALUE));
+        assert!(hot.get_raw_bytes(DBCol::Block, FOO).unwrap().is_some());
+        assert_eq!(hot.get_raw_bytes(DBCol::Block, BAR).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_repair_cleanup() {
+        let hot = create_hot();
+        let cold = create_cold();
+        let policy = ReadRepairPolicy {
+            columns: vec![DBCol::Block, DBCol::Transactions],
+            max_repairs_per_second: 100,
+            max_repaired_keys: 2,
+        };
+        let split = SplitDB::new(hot.clone(), cold.clone()).with_read_repair(policy);
+
+        set(&cold, DBCol::Block, FOO, FOO_VALUE);
+        set(&cold, DBCol::Block, BAR, BAR_VALUE);
+        set_rc(&cold, DBCol::Transactions, FOO, FOO_VALUE);
+        split.get_raw_bytes(DBCol::Block, FOO).unwrap();
+        split.get_with_rc_stripped(DBCol::Transactions, FOO).unwrap();
+        // The limit of repaired keys has been reached.
+        split.get_raw_bytes(DBCol::Block, BAR).unwrap();
+        assert_eq!(hot.get_raw_bytes(DBCol::Block, BAR).unwrap(), None);
+
+        assert_eq!(split.remove_repaired_values().unwrap(), 2);
+        assert_eq!(hot.get_raw_bytes(DBCol::Block, FOO).unwrap(), None);
+        assert_eq!(hot.get_with_rc_stripped(DBCol::Transactions, FOO).unwrap(), None);
+        let value = split.get_raw_bytes(DBCol::Block, FOO).unwrap();
+        assert_eq!(value.as_deref(), Some(FOO_VALUE));
+
+        // Keys can be repaired again once removed.
+        split.get_raw_bytes(DBCol::Block, BAR).unwrap();
+        assert!(hot.get_raw_bytes(DBCol::Block, BAR).unwrap().is_some());
+    }
 }
@@ -1,9 +1,15 @@
 use std::collections::BTreeMap;
 use std::io;
 use std::ops::Bound;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
-use crate::db::{refcount, DBIterator, DBOp, DBSlice, DBTransaction, Database};
+use near_fmt::StorageKey;
+
+use crate::db::{
+    prefix_upper_bound, refcount, DBIterator, DBOp, DBSlice, DBTransaction, Database, KeyLocation,
+    WriteId,
+};
 use crate::{DBCol, StoreStatistics};
 
 /// An in-memory database intended for tests and IO-agnostic estimations.
@@ -18,6 +24,21 @@ pub struct TestDB {
     // The TestDB doesn't produce any stats on its own, it's up to the user of
     // this class to set the stats as they need it.
     stats: RwLock<Option<StoreStatistics>>,
+
+    // Number of writes done so far used as write identifiers.
+    write_count: AtomicU64,
+
+    // Keys at which iterators over the column fail.  Can be set with
+    // fail_iteration_at.
+    iteration_faults: RwLock<enum_map::EnumMap<DBCol, Option<Vec<u8>>>>,
+
+    // Number of writes which still succeed before all further writes fail.
+    // Can be set with fail_writes_after.
+    writes_left: Mutex<Option<usize>>,
+
+    // Keys of insert-only columns whose values have been overwritten with
+    // different ones.  Checked by assert_invariants.
+    overwrites: Mutex<Vec<(DBCol, Vec<u8>)>>,
 }
 
 impl TestDB {
@@ -30,56 +51,69 @@ impl TestDB {
     pub fn set_store_statistics(&self, stats: StoreStatistics) {
         *self.stats.write().unwrap() = Some(stats);
     }
-}
-
-impl Database for TestDB {
-    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
-        Ok(self.db.read().unwrap()[col].get(key).cloned().map(DBSlice::from_vec))
-    }
 
-    fn iter<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
-        let iterator = self.iter_raw_bytes(col);
-        refcount::iter_with_rc_logic(col, iterator)
+    /// Makes iterators over given column fail when they reach given key.
+    ///
+    /// Rather than returning the entry, the iterators yield an error and
+    /// stop.  This allows testing how iteration errors are handled.
+    pub fn fail_iteration_at(&self, col: DBCol, key: &[u8]) {
+        self.iteration_faults.write().unwrap()[col] = Some(key.to_vec());
     }
 
-    fn iter_raw_bytes<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
-        let iterator = self.db.read().unwrap()[col]
-            .clone()
-            .into_iter()
-            .map(|(k, v)| Ok((k.into_boxed_slice(), v.into_boxed_slice())));
-        Box::new(iterator)
+    /// Makes all writes fail after given number of successful ones.
+    ///
+    /// Writes are calls to [`Database::write`] and its variants as well as
+    /// [`Database::update`].  Failing writes return an error without
+    /// modifying anything.  This allows testing how write errors are handled.
+    pub fn fail_writes_after(&self, writes: usize) {
+        *self.writes_left.lock().unwrap() = Some(writes);
     }
 
-    fn iter_prefix<'a>(&'a self, col: DBCol, key_prefix: &'a [u8]) -> DBIterator<'a> {
-        let iterator = self.db.read().unwrap()[col]
-            .range(key_prefix.to_vec()..)
-            .take_while(move |(k, _)| k.starts_with(&key_prefix))
-            .map(|(k, v)| Ok((k.clone().into_boxed_slice(), v.clone().into_boxed_slice())))
-            .collect::<Vec<io::Result<_>>>();
-        refcount::iter_with_rc_logic(col, iterator.into_iter())
+    /// Panics if the database is in a state correct code can’t produce.
+    ///
+    /// Checks that no value in a reference counted column has a negative
+    /// reference count and that no value in an insert-only column has been
+    /// overwritten with a different one.  Meant as a post-condition at the
+    /// end of tests.
+    pub fn assert_invariants(&self) {
+        let mut violations = Vec::new();
+        for (col, entries) in self.db.read().unwrap().iter() {
+            if !col.is_rc() {
+                continue;
+            }
+            for (key, value) in entries {
+                let rc = refcount::decode_value_with_rc(value).1;
+                if rc < 0 {
+                    violations
+                        .push(format!("{col}: {} has negative refcount {rc}", StorageKey(key)));
+                }
+            }
+        }
+        for (col, key) in self.overwrites.lock().unwrap().iter() {
+            violations
+                .push(format!("{col}: {} overwritten with a different value", StorageKey(key)));
+        }
+        assert!(violations.is_empty(), "store invariants violated:\n{}", violations.join("\n"));
     }
 
-    fn iter_range<'a>(
-        &'a self,
-        col: DBCol,
-        lower_bound: Option<&[u8]>,
-        upper_bound: Option<&[u8]>,
-    ) -> DBIterator<'a> {
-        let lower = lower_bound.map_or(Bound::Unbounded, |f| Bound::Included(f.to_vec()));
-        let upper = upper_bound.map_or(Bound::Unbounded, |f| Bound::Excluded(f.to_vec()));
-
-        let iterator = self.db.read().unwrap()[col]
-            .range((lower, upper))
-            .map(|(k, v)| Ok((k.clone().into_boxed_slice(), v.clone().into_boxed_slice())))
-            .collect::<Vec<io::Result<_>>>();
-        refcount::iter_with_rc_logic(col, iterator.into_iter())
+    /// Records an overwrite of a value in an insert-only column.
+    fn check_overwrite(&self, col: DBCol, old_value: Option<&Vec<u8>>, key: &[u8], value: &[u8]) {
+        if col.is_insert_only() && old_value.is_some_and(|old_value| old_value != value) {
+            self.overwrites.lock().unwrap().push((col, key.to_vec()));
+        }
     }
 
-    fn write(&self, transaction: DBTransaction) -> io::Result<()> {
-        let mut db = self.db.write().unwrap();
+    /// Applies the transaction to the database whose lock is held and
+    /// returns identifier of the write.
+    fn apply(
+        &self,
+        db: &mut enum_map::EnumMap<DBCol, BTreeMap<Vec<u8>, Vec<u8>>>,
+        transaction: DBTransaction,
+    ) -> WriteId {
         for op in transaction.ops {
             match op {
                 DBOp::Set { col, key, value } => {
+                    self.check_overwrite(col, db[col].get(&key), &key, &value);
                     db[col].insert(key, value);
                 }
                 DBOp::Insert { col, key, value } => {
@@ -88,6 +122,7 @@ impl Database for TestDB {
                             super::assert_no_overwrite(col, &key, &value, &*old_value)
                         }
                     }
+                    self.check_overwrite(col, db[col].get(&key), &key, &value);
                     db[col].insert(key, value);
                 }
                 DBOp::UpdateRefcount { col, key, value } => {
@@ -109,10 +144,203 @@ impl Database for TestDB {
                 }
                 DBOp::DeleteAll { col } => db[col].clear(),
                 DBOp::DeleteRange { col, from, to } => {
+                    // Inverted range is empty so nothing gets deleted.
                     db[col].retain(|key, _| !(&from..&to).contains(&key));
                 }
             };
         }
+        // Incremented while holding the lock so the identifiers follow the
+        // order in which writes are applied.
+        WriteId(self.write_count.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    /// Returns an error if the write should fail as configured with
+    /// [`Self::fail_writes_after`].
+    fn check_write_fault(&self) -> io::Result<()> {
+        match self.writes_left.lock().unwrap().as_mut() {
+            Some(0) => Err(io::Error::other("injected write failure")),
+            Some(left) => {
+                *left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Converts entries into iterator items injecting an error configured
+    /// with [`Self::fail_iteration_at`].
+    fn iter_items<'a>(
+        &self,
+        col: DBCol,
+        entries: impl Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a,
+    ) -> DBIterator<'a> {
+        let fault = self.iteration_faults.read().unwrap()[col].clone();
+        let mut failed = false;
+        Box::new(entries.map_while(move |(key, value)| {
+            if failed {
+                None
+            } else if fault.as_ref() == Some(&key) {
+                failed = true;
+                Some(Err(io::Error::other(format!("{col}: injected iteration failure"))))
+            } else {
+                Some(Ok((key.into_boxed_slice(), value.into_boxed_slice())))
+            }
+        }))
+    }
+}
+
+impl Database for TestDB {
+    fn name(&self) -> &str {
+        "testdb"
+    }
+
+    fn get_raw_bytes(&self, col: DBCol, key: &[u8]) -> io::Result<Option<DBSlice<'_>>> {
+        Ok(self.db.read().unwrap()[col].get(key).cloned().map(DBSlice::from_vec))
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        let iterator = self.iter_raw_bytes(col);
+        refcount::iter_with_rc_logic(col, iterator)
+    }
+
+    fn iter_raw_bytes<'a>(&'a self, col: DBCol) -> DBIterator<'a> {
+        let entries = self.db.read().unwrap()[col].clone();
+        self.iter_items(col, entries.into_iter())
+    }
+
+    fn iter_prefix<'a>(&'a self, col: DBCol, key_prefix: &'a [u8]) -> DBIterator<'a> {
+        let upper_bound = prefix_upper_bound(key_prefix);
+        self.iter_range(col, Some(key_prefix), upper_bound.as_deref())
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        // BTreeMap::range panics on inverted range.  Treat it as empty the
+        // same way RocksDB does.
+        if let (Some(lower), Some(upper)) = (lower_bound, upper_bound) {
+            if lower > upper {
+                return Box::new(std::iter::empty());
+            }
+        }
+        let lower = lower_bound.map_or(Bound::Unbounded, |f| Bound::Included(f.to_vec()));
+        let upper = upper_bound.map_or(Bound::Unbounded, |f| Bound::Excluded(f.to_vec()));
+
+        let entries = self.db.read().unwrap()[col]
+            .range((lower, upper))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        refcount::iter_with_rc_logic(col, self.iter_items(col, entries.into_iter()))
+    }
+
+    fn iter_range_rev<'a>(
+        &'a self,
+        col: DBCol,
+        lower_bound: Option<&[u8]>,
+        upper_bound: Option<&[u8]>,
+    ) -> DBIterator<'a> {
+        if let (Some(lower), Some(upper)) = (lower_bound, upper_bound) {
+            if lower > upper {
+                return Box::new(std::iter::empty());
+            }
+        }
+        let lower = lower_bound.map_or(Bound::Unbounded, |f| Bound::Included(f.to_vec()));
+        let upper = upper_bound.map_or(Bound::Unbounded, |f| Bound::Excluded(f.to_vec()));
+
+        let entries = self.db.read().unwrap()[col]
+            .range((lower, upper))
+            .rev()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        refcount::iter_with_rc_logic(col, self.iter_items(col, entries.into_iter()))
+    }
+
+    /// All data is kept in memory so existing keys are always reported as
+    /// being in a memtable.
+    fn key_location(&self, col: DBCol, key: &[u8]) -> io::Result<Option<KeyLocation>> {
+        let exists = self.db.read().unwrap()[col].contains_key(key);
+        Ok(Some(if exists { KeyLocation::MemTable } else { KeyLocation::Absent }))
+    }
+
+    /// Everything is kept in memory so this reports total size of all keys
+    /// and values.
+    fn total_disk_size(&self) -> io::Result<u64> {
+        let db = self.db.read().unwrap();
+        let entries = db.values().flat_map(|col| col.iter());
+        Ok(entries.map(|(key, value)| (key.len() + value.len()) as u64).sum())
+    }
+
+    fn wal_sequence_range(&self) -> io::Result<Option<(u64, u64)>> {
+        let count = self.write_count.load(Ordering::Relaxed);
+        Ok((count > 0).then_some((1, count)))
+    }
+
+    fn key_range(&self, col: DBCol) -> io::Result<Option<(Box<[u8]>, Box<[u8]>)>> {
+        let db = self.db.read().unwrap();
+        let is_live = |(_, value): &(&Vec<u8>, &Vec<u8>)| {
+            !col.is_rc() || refcount::decode_value_with_rc(value).1 > 0
+        };
+        let Some((first, _)) = db[col].iter().find(is_live) else {
+            return Ok(None);
+        };
+        let (last, _) = db[col].iter().rev().find(is_live).unwrap();
+        Ok(Some((first.clone().into_boxed_slice(), last.clone().into_boxed_slice())))
+    }
+
+    fn write(&self, transaction: DBTransaction) -> io::Result<()> {
+        self.write_with_id(transaction).map(|_| ())
+    }
+
+    fn write_with_id(&self, transaction: DBTransaction) -> io::Result<WriteId> {
+        if cfg!(debug_assertions) {
+            transaction.check_rc_ops()?;
+        }
+        self.check_write_fault()?;
+        let mut db = self.db.write().unwrap();
+        Ok(self.apply(&mut db, transaction))
+    }
+
+    fn write_if_unchanged(
+        &self,
+        transaction: DBTransaction,
+        witnesses: &[(DBCol, Vec<u8>, Option<Vec<u8>>)],
+    ) -> io::Result<bool> {
+        if cfg!(debug_assertions) {
+            transaction.check_rc_ops()?;
+        }
+        self.check_write_fault()?;
+        // Holding the write lock throughout makes the check and write atomic.
+        let mut db = self.db.write().unwrap();
+        let get = |col: DBCol, key: &[u8]| Ok(db[col].get(key).map(Vec::as_slice));
+        if !super::witnesses_hold(witnesses, get)? {
+            return Ok(false);
+        }
+        self.apply(&mut db, transaction);
+        Ok(true)
+    }
+
+    fn update(
+        &self,
+        col: DBCol,
+        key: &[u8],
+        f: &dyn Fn(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> io::Result<()> {
+        super::check_not_rc(col, "update")?;
+        self.check_write_fault()?;
+        // Holding the write lock throughout makes the update trivially atomic.
+        let mut db = self.db.write().unwrap();
+        match f(db[col].get(key).map(Vec::as_slice)) {
+            Some(value) => db[col].insert(key.to_vec(), value),
+            None => db[col].remove(key),
+        };
+        Ok(())
+    }
+
+    fn truncate_column(&self, col: DBCol) -> io::Result<()> {
+        self.db.write().unwrap()[col].clear();
         Ok(())
     }
 
@@ -136,3 +364,61 @@ impl Database for TestDB {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assert_invariants() {
+        let db = TestDB::new();
+        let rc = std::num::NonZeroU32::new(1).unwrap();
+        let mut transaction = DBTransaction::new();
+        transaction.insert(DBCol::Block, b"key".to_vec(), b"value".to_vec());
+        transaction.update_refcount(
+            DBCol::State,
+            b"key".to_vec(),
+            refcount::add_positive_refcount(b"value", rc),
+        );
+        transaction.update_refcount(
+            DBCol::State,
+            b"other".to_vec(),
+            refcount::add_positive_refcount(b"value", rc),
+        );
+        transaction.update_refcount(
+            DBCol::State,
+            b"other".to_vec(),
+            refcount::encode_negative_refcount(rc),
+        );
+        db.write(transaction).unwrap();
+        // Setting the same value again is fine.
+        let mut transaction = DBTransaction::new();
+        transaction.set(DBCol::Block, b"key".to_vec(), b"value".to_vec());
+        db.write(transaction).unwrap();
+        db.assert_invariants();
+    }
+
+    #[test]
+    #[should_panic(expected = "State: 'key' has negative refcount -1")]
+    fn test_assert_invariants_negative_refcount() {
+        let db = TestDB::new();
+        let rc = std::num::NonZeroU32::new(1).unwrap();
+        let mut transaction = DBTransaction::new();
+        transaction.allow_raw_rc_writes();
+        transaction.set(DBCol::State, b"key".to_vec(), refcount::encode_negative_refcount(rc));
+        db.write(transaction).unwrap();
+        db.assert_invariants();
+    }
+
+    #[test]
+    #[should_panic(expected = "Block: 'key' overwritten with a different value")]
+    fn test_assert_invariants_overwrite() {
+        let db = TestDB::new();
+        for value in [b"foo", b"bar"] {
+            let mut transaction = DBTransaction::new();
+            transaction.set(DBCol::Block, b"key".to_vec(), value.to_vec());
+            db.write(transaction).unwrap();
+        }
+        db.assert_invariants();
+    }
+}
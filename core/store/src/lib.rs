@@ -39,6 +39,7 @@ use std::sync::Arc;
 use std::{fmt, io};
 use strum;
 
+mod block_misc;
 pub mod cold_storage;
 mod columns;
 pub mod config;
@@ -86,6 +87,9 @@ impl FromStr for Temperature {
 
 const STATE_COLUMNS: [DBCol; 2] = [DBCol::State, DBCol::FlatState];
 const STATE_FILE_END_MARK: u8 = 255;
+const COLUMN_STREAM_VERSION: u8 = 1;
+/// Number of records [`Store::apply_column_stream`] buffers before writing them.
+const COLUMN_STREAM_BATCH_SIZE: usize = 1000;
 
 /// Node’s storage holding chain and all other necessary data.
 ///
@@ -364,6 +368,8 @@ impl Store {
         let file = File::open(filename)?;
         let mut file = std::io::BufReader::new(file);
         let mut transaction = DBTransaction::new();
+        // The file holds raw values of the State column.
+        transaction.allow_raw_rc_writes();
         loop {
             let column = u8::deserialize_reader(&mut file)?;
             if column == STATE_FILE_END_MARK {
@@ -375,6 +381,67 @@ impl Store {
         self.storage.write(transaction)
     }
 
+    /// Writes all items of given column into the writer.
+    ///
+    /// The stream starts with a header consisting of a version byte and the
+    /// name of the column (as borsh-encoded string).  It is followed by
+    /// borsh-encoded `Some((key, value))` records, i.e. `1u8` tag with key and
+    /// value each prefixed by its length as u32, and terminated by a `None`,
+    /// i.e. a single zero byte.  Values are streamed raw, so in reference
+    /// counted columns they include the reference count.
+    ///
+    /// Returns number of streamed records.  The stream can be applied to
+    /// another storage with [`Self::apply_column_stream`].
+    pub fn stream_column(&self, col: DBCol, w: &mut impl std::io::Write) -> io::Result<u64> {
+        let name: &'static str = col.into();
+        (COLUMN_STREAM_VERSION, name).serialize(w)?;
+        let mut count = 0;
+        for item in self.storage.iter_raw_bytes(col) {
+            let (key, value) = item?;
+            Some((key, value)).serialize(w)?;
+            count += 1;
+        }
+        None::<(Box<[u8]>, Box<[u8]>)>.serialize(w)?;
+        Ok(count)
+    }
+
+    /// Reads records produced by [`Self::stream_column`] and sets them in
+    /// given column.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the stream has unknown
+    /// version or was produced from a different column.  Returns number of
+    /// applied records.  The records are written in batches so if reading
+    /// fails mid-stream some of the records may have already been written.
+    pub fn apply_column_stream(&self, col: DBCol, r: &mut impl std::io::Read) -> io::Result<u64> {
+        let (version, name) = <(u8, String)>::deserialize_reader(r)?;
+        if version != COLUMN_STREAM_VERSION {
+            let msg = format!("unsupported column stream version {version}");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        let want: &'static str = col.into();
+        if name != want {
+            let msg = format!("column stream of {name} cannot be applied to {want}");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        // Streams hold raw values, including reference counts.
+        let new_transaction = || {
+            let mut transaction = DBTransaction::new();
+            transaction.allow_raw_rc_writes();
+            transaction
+        };
+        let mut transaction = new_transaction();
+        let mut count = 0;
+        while let Some((key, value)) = Option::<(Vec<u8>, Vec<u8>)>::deserialize_reader(r)? {
+            transaction.set(col, key, value);
+            count += 1;
+            if transaction.ops.len() >= COLUMN_STREAM_BATCH_SIZE {
+                self.storage.write(std::mem::replace(&mut transaction, new_transaction()))?;
+            }
+        }
+        self.storage.write(transaction)?;
+        Ok(count)
+    }
+
     /// If the storage is backed by disk, flushes any in-memory data to disk.
     pub fn flush(&self) -> io::Result<()> {
         self.storage.flush()
@@ -544,6 +611,7 @@ impl StoreUpdate {
     /// of auxilary code like migrations which wants to hack on the database
     /// directly.
     pub fn set_raw_bytes(&mut self, column: DBCol, key: &[u8], value: &[u8]) {
+        self.transaction.allow_raw_rc_writes();
         self.transaction.set(column, key.to_vec(), value.to_vec())
     }
 
@@ -1206,4 +1274,41 @@ mod tests {
             store.load_state_from_file(tmp.path()).unwrap_err().kind()
         );
     }
+
+    #[test]
+    fn test_column_stream() {
+        let store = crate::test_utils::create_test_store();
+        let mut store_update = store.store_update();
+        store_update.increment_refcount(DBCol::State, &[1], &[1]);
+        store_update.increment_refcount(DBCol::State, &[2], &[2]);
+        store_update.increment_refcount(DBCol::State, &[2], &[2]);
+        store_update.set(DBCol::BlockMisc, b"foo", b"bar");
+        store_update.commit().unwrap();
+
+        let mut stream = Vec::new();
+        assert_eq!(2, store.stream_column(DBCol::State, &mut stream).unwrap());
+
+        // Raw values, including refcounts, are transferred.
+        let other = crate::test_utils::create_test_store();
+        assert_eq!(2, other.apply_column_stream(DBCol::State, &mut stream.as_slice()).unwrap());
+        let got = other.iter_raw_bytes(DBCol::State).collect::<std::io::Result<Vec<_>>>().unwrap();
+        let want = store.iter_raw_bytes(DBCol::State).collect::<std::io::Result<Vec<_>>>().unwrap();
+        assert_eq!(want, got);
+        assert_eq!(None, other.get(DBCol::BlockMisc, b"foo").unwrap());
+
+        // Stream of a different column is rejected.
+        assert_eq!(
+            std::io::ErrorKind::InvalidData,
+            other.apply_column_stream(DBCol::FlatState, &mut stream.as_slice()).unwrap_err().kind()
+        );
+
+        // Truncated stream is detected.
+        let truncated = &stream[..stream.len() - 1];
+        assert!(other.apply_column_stream(DBCol::State, &mut &truncated[..]).is_err());
+
+        // Empty column produces an empty stream.
+        let mut stream = Vec::new();
+        assert_eq!(0, store.stream_column(DBCol::Block, &mut stream).unwrap());
+        assert_eq!(0, other.apply_column_stream(DBCol::Block, &mut stream.as_slice()).unwrap());
+    }
 }
@@ -15,7 +15,7 @@ use near_primitives_core::hash::CryptoHash;
 use near_primitives_core::types::AccountId;
 use near_store::cold_storage::{
     copy_all_data_to_cold, test_cold_genesis_update, test_get_store_initial_writes,
-    test_get_store_reads, update_cold_db, update_cold_head,
+    test_get_store_reads, update_cold_db, update_cold_head, verify_cold_completeness,
 };
 use near_store::metadata::DbKind;
 use near_store::metadata::DB_VERSION;
@@ -212,6 +212,67 @@ fn test_storage_after_commit_of_cold_update() {
     }
 }
 
+/// Copies all but the last of a few blocks to cold storage and checks that
+/// verification of cold storage completeness reports keys of the block left
+/// out.
+#[test]
+fn test_verify_cold_completeness() {
+    init_test_logger();
+
+    let epoch_length = 5;
+    let max_height = epoch_length * 2;
+
+    let mut genesis = Genesis::test(vec![test0(), test1()], 1);
+    genesis.config.epoch_length = epoch_length;
+    genesis.config.min_gas_price = 0;
+    let mut env = TestEnv::builder(&genesis.config).nightshade_runtimes(&genesis).build();
+
+    let (storage, ..) = create_test_node_storage_with_cold(DB_VERSION, DbKind::Hot);
+    let cold_db = storage.cold_db().unwrap();
+
+    let last_height = max_height - 1;
+    let mut last_hash = *env.clients[0].chain.genesis().hash();
+    let mut shard_layout = None;
+    for height in 1..=last_height {
+        let signer = InMemorySigner::from_seed(test0(), KeyType::ED25519, "test0");
+        let tx = create_tx_send_money(height, &signer, last_hash);
+        assert_eq!(env.clients[0].process_tx(tx, false, false), ProcessTxResponse::ValidTx);
+
+        let block = env.clients[0].produce_block(height).unwrap().unwrap();
+        env.process_block(0, block.clone(), Provenance::PRODUCED);
+
+        let client = &env.clients[0];
+        let epoch_id = client.epoch_manager.get_epoch_id_from_prev_block(&last_hash).unwrap();
+        let layout = client.epoch_manager.get_shard_layout(&epoch_id).unwrap();
+        if height != last_height {
+            update_cold_db(cold_db, &client.runtime_adapter.store(), &layout, &height, 1).unwrap();
+        }
+        shard_layout = Some(layout);
+        last_hash = *block.hash();
+    }
+    // All blocks share the same single-shard layout.
+    let shard_layout = shard_layout.unwrap();
+    let hot_store = env.clients[0].runtime_adapter.store();
+
+    let copied = 1..=last_height - 1;
+    for col in [DBCol::Block, DBCol::BlockInfo, DBCol::Transactions, DBCol::State] {
+        let missing =
+            verify_cold_completeness(&hot_store, cold_db, &shard_layout, col, copied.clone())
+                .unwrap();
+        assert_eq!(missing, Vec::<Vec<u8>>::new(), "{col}");
+    }
+
+    let missing =
+        verify_cold_completeness(&hot_store, cold_db, &shard_layout, DBCol::Block, 1..=last_height)
+            .unwrap();
+    assert_eq!(missing, vec![last_hash.as_bytes().to_vec()]);
+
+    // Columns which aren’t kept in cold storage are refused.
+    let err = verify_cold_completeness(&hot_store, cold_db, &shard_layout, DBCol::BlockMisc, 1..=1)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
 /// Producing 10 * 5 blocks and updating HEAD of cold storage after each one.
 /// After every update checking that HEAD in cold db, COLD_HEAD in hot db and HEAD in hot store are equal.
 #[test]